@@ -0,0 +1,12 @@
+// writes include/rc8.h for the functions in src/lib.rs
+use std::env;
+
+fn main() {
+    let dir = env::var("CARGO_MANIFEST_DIR").unwrap();
+    println!("cargo:rerun-if-changed=src/lib.rs");
+    println!("cargo:rerun-if-changed=cbindgen.toml");
+    let config = cbindgen::Config::from_file(format!("{}/cbindgen.toml", dir)).unwrap();
+    cbindgen::generate_with_config(&dir, config)
+        .expect("Unable to generate the header")
+        .write_to_file(format!("{}/include/rc8.h", dir));
+}
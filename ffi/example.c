@@ -0,0 +1,45 @@
+/* Runs a ROM for two seconds through the C API and prints the screen and the registers. */
+#include <stdio.h>
+
+#include "rc8.h"
+
+static int check(Rc8Status status, const char *call) {
+    if (status != RC8_STATUS_OK)
+        fprintf(stderr, "%s: %s\n", call, rc8_status_message(status));
+    return status == RC8_STATUS_OK;
+}
+
+int main(int argc, char **argv) {
+    if (argc != 2) {
+        fprintf(stderr, "usage: %s ROM\n", argv[0]);
+        return 1;
+    }
+    FILE *file = fopen(argv[1], "rb");
+    if (!file) {
+        perror(argv[1]);
+        return 1;
+    }
+    static uint8_t rom[4096];
+    size_t len = fread(rom, 1, sizeof rom, file);
+    fclose(file);
+
+    Rc8Machine *machine = rc8_create();
+    if (!machine || !check(rc8_load_rom(machine, rom, len), "rc8_load_rom") ||
+        !check(rc8_run_frames(machine, 120), "rc8_run_frames"))
+        return 1;
+
+    size_t width, height;
+    const uint8_t *pixels = rc8_framebuffer(machine, &width, &height);
+    for (size_t y = 0; y < height; y++) {
+        for (size_t x = 0; x < width; x++)
+            putchar(pixels[y * width + x] ? '#' : '.');
+        putchar('\n');
+    }
+    uint16_t pc, i;
+    if (!check(rc8_get_register(machine, RC8_REGISTER_PC, &pc), "rc8_get_register") ||
+        !check(rc8_get_register(machine, RC8_REGISTER_I, &i), "rc8_get_register"))
+        return 1;
+    printf("PC %03X  I %03X\n", pc, i);
+    rc8_destroy(machine);
+    return 0;
+}
@@ -0,0 +1,120 @@
+/* Generated by cbindgen from src/lib.rs, don't edit. */
+
+#ifndef RC8_H
+#define RC8_H
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+#define RC8_WIDTH 64
+
+#define RC8_HEIGHT 32
+
+/**
+ * Registers for rc8_get_register() and rc8_set_register(), V0 to VF are 0 to 15.
+ */
+#define RC8_REGISTER_I 16
+
+#define RC8_REGISTER_PC 17
+
+#define RC8_REGISTER_SP 18
+
+#define RC8_REGISTER_DELAY_TIMER 19
+
+#define RC8_REGISTER_SOUND_TIMER 20
+
+typedef enum {
+  RC8_STATUS_OK = 0,
+  RC8_STATUS_NULL_POINTER,
+  RC8_STATUS_INVALID_ROM,
+  RC8_STATUS_INVALID_STATE,
+  RC8_STATUS_OUT_OF_RANGE,
+  RC8_STATUS_BUFFER_TOO_SMALL,
+  RC8_STATUS_PANIC,
+} Rc8Status;
+
+/**
+ * A CHIP-8 machine, created by rc8_create() and freed by rc8_destroy().
+ */
+typedef struct Rc8Machine Rc8Machine;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/**
+ * Returns a new machine with the default options, or NULL if it couldn't be created.
+ */
+Rc8Machine *rc8_create(void);
+
+/**
+ * Frees a machine, NULL is ignored.
+ */
+void rc8_destroy(Rc8Machine *machine);
+
+/**
+ * Loads a raw ROM or an Octo cartridge to 0x200 and resets the machine.
+ */
+Rc8Status rc8_load_rom(Rc8Machine *machine, const uint8_t *data, size_t len);
+
+/**
+ * Presses or releases one of the keys 0 to 15.
+ */
+Rc8Status rc8_set_key(Rc8Machine *machine, uint8_t key, bool pressed);
+
+/**
+ * Executes single instructions, the timers don't count down.
+ */
+Rc8Status rc8_run_cycles(Rc8Machine *machine, uint32_t cycles);
+
+/**
+ * Runs frames of 1/60 second, with the speed of the options.
+ */
+Rc8Status rc8_run_frames(Rc8Machine *machine, uint32_t frames);
+
+/**
+ * Returns RC8_WIDTH * RC8_HEIGHT bytes, one per pixel row by row, 0 for an unlit pixel.
+ * The pointer stays valid until the machine is changed or destroyed. width and height may
+ * be NULL. Returns NULL for a NULL machine.
+ */
+const uint8_t *rc8_framebuffer(const Rc8Machine *machine, size_t *width, size_t *height);
+
+Rc8Status rc8_get_register(Rc8Machine *machine, uint32_t reg, uint16_t *value);
+
+/**
+ * Fails with RC8_STATUS_OUT_OF_RANGE if the value doesn't fit the register.
+ */
+Rc8Status rc8_set_register(Rc8Machine *machine, uint32_t reg, uint16_t value);
+
+/**
+ * Copies len bytes from address to buffer, the range has to be inside the 4 KiB memory.
+ */
+Rc8Status rc8_read_memory(Rc8Machine *machine, uint16_t address, uint8_t *buffer, size_t len);
+
+Rc8Status rc8_write_memory(Rc8Machine *machine, uint16_t address, const uint8_t *data, size_t len);
+
+/**
+ * The size of the buffer for rc8_save_state(), the same for every machine.
+ */
+size_t rc8_state_size(void);
+
+Rc8Status rc8_save_state(Rc8Machine *machine, uint8_t *buffer, size_t len);
+
+/**
+ * The machine is left unchanged if the data isn't a state saved by rc8_save_state().
+ */
+Rc8Status rc8_load_state(Rc8Machine *machine, const uint8_t *data, size_t len);
+
+/**
+ * A static description of a status.
+ */
+const char *rc8_status_message(Rc8Status status);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* RC8_H */
@@ -0,0 +1,441 @@
+// C API of rc8 for embedding it in C and C++ programs. include/rc8.h is generated from this
+// file by build.rs. Machines are opaque handles, every fallible function returns an
+// Rc8Status, and panics are caught at the boundary and reported as RC8_STATUS_PANIC.
+//
+//     cargo build --release
+//     cc -Iinclude -o example example.c target/release/librc8_ffi.a -lm
+//     ./example ../IBM.ch8
+//
+// The doc comments use /// since cbindgen copies them into the header.
+#![allow(clippy::missing_safety_doc)]
+
+use rc8::chip8::{Chip8, COL, ROW};
+use rc8::state;
+use std::ffi::{c_char, CStr};
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+use std::slice;
+
+pub const RC8_WIDTH: usize = 64;
+pub const RC8_HEIGHT: usize = 32;
+const _: () = assert!(RC8_WIDTH == COL && RC8_HEIGHT == ROW);
+
+/// Registers for rc8_get_register() and rc8_set_register(), V0 to VF are 0 to 15.
+pub const RC8_REGISTER_I: u32 = 16;
+pub const RC8_REGISTER_PC: u32 = 17;
+pub const RC8_REGISTER_SP: u32 = 18;
+pub const RC8_REGISTER_DELAY_TIMER: u32 = 19;
+pub const RC8_REGISTER_SOUND_TIMER: u32 = 20;
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rc8Status {
+    Ok = 0,
+    NullPointer,
+    InvalidRom,
+    InvalidState,
+    OutOfRange,
+    BufferTooSmall,
+    Panic,
+}
+
+/// A CHIP-8 machine, created by rc8_create() and freed by rc8_destroy().
+pub struct Rc8Machine {
+    chip: Chip8,
+    // the framebuffer as of the last call that changed it, for rc8_framebuffer()
+    pixels: [u8; ROW * COL],
+    // one bit per pressed key
+    keys: u16,
+}
+
+impl Rc8Machine {
+    fn refresh(&mut self) {
+        self.pixels = self.chip.framebuffer();
+    }
+}
+
+// runs f on the machine, turning null pointers and panics into a status
+unsafe fn with_machine(
+    machine: *mut Rc8Machine,
+    f: impl FnOnce(&mut Rc8Machine) -> Result<(), Rc8Status>,
+) -> Rc8Status {
+    let Some(machine) = machine.as_mut() else {
+        return Rc8Status::NullPointer;
+    };
+    match panic::catch_unwind(AssertUnwindSafe(|| f(machine))) {
+        Ok(Ok(())) => Rc8Status::Ok,
+        Ok(Err(status)) => status,
+        Err(_) => Rc8Status::Panic,
+    }
+}
+
+unsafe fn bytes<'a>(data: *const u8, len: usize) -> Result<&'a [u8], Rc8Status> {
+    match len {
+        0 => Ok(&[]),
+        _ if data.is_null() => Err(Rc8Status::NullPointer),
+        _ => Ok(slice::from_raw_parts(data, len)),
+    }
+}
+
+unsafe fn bytes_mut<'a>(data: *mut u8, len: usize) -> Result<&'a mut [u8], Rc8Status> {
+    match len {
+        0 => Ok(&mut []),
+        _ if data.is_null() => Err(Rc8Status::NullPointer),
+        _ => Ok(slice::from_raw_parts_mut(data, len)),
+    }
+}
+
+/// Returns a new machine with the default options, or NULL if it couldn't be created.
+#[no_mangle]
+pub extern "C" fn rc8_create() -> *mut Rc8Machine {
+    panic::catch_unwind(|| {
+        Box::into_raw(Box::new(Rc8Machine {
+            chip: Chip8::new(),
+            pixels: [0; ROW * COL],
+            keys: 0,
+        }))
+    })
+    .unwrap_or(ptr::null_mut())
+}
+
+/// Frees a machine, NULL is ignored.
+#[no_mangle]
+pub unsafe extern "C" fn rc8_destroy(machine: *mut Rc8Machine) {
+    if !machine.is_null() {
+        drop(Box::from_raw(machine));
+    }
+}
+
+/// Loads a raw ROM or an Octo cartridge to 0x200 and resets the machine.
+#[no_mangle]
+pub unsafe extern "C" fn rc8_load_rom(
+    machine: *mut Rc8Machine,
+    data: *const u8,
+    len: usize,
+) -> Rc8Status {
+    with_machine(machine, |machine| {
+        let data = bytes(data, len)?.to_vec();
+        let mut chip = Chip8::new();
+        chip.load_data(data).map_err(|_| Rc8Status::InvalidRom)?;
+        machine.chip = chip;
+        machine.keys = 0;
+        machine.refresh();
+        Ok(())
+    })
+}
+
+/// Presses or releases one of the keys 0 to 15.
+#[no_mangle]
+pub unsafe extern "C" fn rc8_set_key(
+    machine: *mut Rc8Machine,
+    key: u8,
+    pressed: bool,
+) -> Rc8Status {
+    with_machine(machine, |machine| {
+        if key >= 16 {
+            return Err(Rc8Status::OutOfRange);
+        }
+        machine.keys = machine.keys & !(1 << key) | (pressed as u16) << key;
+        machine.chip.set_keypad(machine.keys);
+        Ok(())
+    })
+}
+
+/// Executes single instructions, the timers don't count down.
+#[no_mangle]
+pub unsafe extern "C" fn rc8_run_cycles(machine: *mut Rc8Machine, cycles: u32) -> Rc8Status {
+    with_machine(machine, |machine| {
+        machine.chip.run_instructions(cycles);
+        machine.refresh();
+        Ok(())
+    })
+}
+
+/// Runs frames of 1/60 second, with the speed of the options.
+#[no_mangle]
+pub unsafe extern "C" fn rc8_run_frames(machine: *mut Rc8Machine, frames: u32) -> Rc8Status {
+    with_machine(machine, |machine| {
+        for _ in 0..frames {
+            machine.chip.run_frame();
+        }
+        machine.refresh();
+        Ok(())
+    })
+}
+
+/// Returns RC8_WIDTH * RC8_HEIGHT bytes, one per pixel row by row, 0 for an unlit pixel.
+/// The pointer stays valid until the machine is changed or destroyed. width and height may
+/// be NULL. Returns NULL for a NULL machine.
+#[no_mangle]
+pub unsafe extern "C" fn rc8_framebuffer(
+    machine: *const Rc8Machine,
+    width: *mut usize,
+    height: *mut usize,
+) -> *const u8 {
+    let Some(machine) = machine.as_ref() else {
+        return ptr::null();
+    };
+    if let Some(width) = width.as_mut() {
+        *width = COL;
+    }
+    if let Some(height) = height.as_mut() {
+        *height = ROW;
+    }
+    machine.pixels.as_ptr()
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn rc8_get_register(
+    machine: *mut Rc8Machine,
+    reg: u32,
+    value: *mut u16,
+) -> Rc8Status {
+    with_machine(machine, |machine| {
+        let value = value.as_mut().ok_or(Rc8Status::NullPointer)?;
+        let cpu = machine.chip.cpu();
+        *value = match reg {
+            0..=15 => cpu.v[reg as usize] as u16,
+            RC8_REGISTER_I => cpu.i,
+            RC8_REGISTER_PC => cpu.pc,
+            RC8_REGISTER_SP => cpu.sp,
+            RC8_REGISTER_DELAY_TIMER => cpu.delay_timer as u16,
+            RC8_REGISTER_SOUND_TIMER => cpu.sound_timer as u16,
+            _ => return Err(Rc8Status::OutOfRange),
+        };
+        Ok(())
+    })
+}
+
+/// Fails with RC8_STATUS_OUT_OF_RANGE if the value doesn't fit the register.
+#[no_mangle]
+pub unsafe extern "C" fn rc8_set_register(
+    machine: *mut Rc8Machine,
+    reg: u32,
+    value: u16,
+) -> Rc8Status {
+    with_machine(machine, |machine| {
+        let byte = u8::try_from(value).map_err(|_| Rc8Status::OutOfRange);
+        let cpu = machine.chip.cpu_mut();
+        match reg {
+            0..=15 => cpu.v[reg as usize] = byte?,
+            RC8_REGISTER_I => cpu.i = value,
+            RC8_REGISTER_PC if value < 0x1000 => cpu.pc = value,
+            RC8_REGISTER_SP if value < 16 => cpu.sp = value,
+            RC8_REGISTER_DELAY_TIMER => cpu.delay_timer = byte?,
+            RC8_REGISTER_SOUND_TIMER => cpu.sound_timer = byte?,
+            _ => return Err(Rc8Status::OutOfRange),
+        }
+        Ok(())
+    })
+}
+
+/// Copies len bytes from address to buffer, the range has to be inside the 4 KiB memory.
+#[no_mangle]
+pub unsafe extern "C" fn rc8_read_memory(
+    machine: *mut Rc8Machine,
+    address: u16,
+    buffer: *mut u8,
+    len: usize,
+) -> Rc8Status {
+    with_machine(machine, |machine| {
+        let buffer = bytes_mut(buffer, len)?;
+        let start = address as usize;
+        let memory = machine.chip.memory();
+        let source = memory
+            .get(start..start + len)
+            .ok_or(Rc8Status::OutOfRange)?;
+        buffer.copy_from_slice(source);
+        Ok(())
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn rc8_write_memory(
+    machine: *mut Rc8Machine,
+    address: u16,
+    data: *const u8,
+    len: usize,
+) -> Rc8Status {
+    with_machine(machine, |machine| {
+        let data = bytes(data, len)?;
+        let start = address as usize;
+        let target = machine
+            .chip
+            .cpu_mut()
+            .memory
+            .get_mut(start..start + len)
+            .ok_or(Rc8Status::OutOfRange)?;
+        target.copy_from_slice(data);
+        Ok(())
+    })
+}
+
+/// The size of the buffer for rc8_save_state(), the same for every machine.
+#[no_mangle]
+pub extern "C" fn rc8_state_size() -> usize {
+    state::SIZE
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn rc8_save_state(
+    machine: *mut Rc8Machine,
+    buffer: *mut u8,
+    len: usize,
+) -> Rc8Status {
+    with_machine(machine, |machine| {
+        if len < state::SIZE {
+            return Err(Rc8Status::BufferTooSmall);
+        }
+        let buffer = bytes_mut(buffer, len)?;
+        buffer[..state::SIZE].copy_from_slice(&machine.chip.save_state());
+        Ok(())
+    })
+}
+
+/// The machine is left unchanged if the data isn't a state saved by rc8_save_state().
+#[no_mangle]
+pub unsafe extern "C" fn rc8_load_state(
+    machine: *mut Rc8Machine,
+    data: *const u8,
+    len: usize,
+) -> Rc8Status {
+    with_machine(machine, |machine| {
+        let data = bytes(data, len)?;
+        machine
+            .chip
+            .load_state(data)
+            .map_err(|_| Rc8Status::InvalidState)?;
+        machine.refresh();
+        Ok(())
+    })
+}
+
+/// A static description of a status.
+#[no_mangle]
+pub extern "C" fn rc8_status_message(status: Rc8Status) -> *const c_char {
+    let message: &CStr = match status {
+        Rc8Status::Ok => c"ok",
+        Rc8Status::NullPointer => c"a pointer argument is NULL",
+        Rc8Status::InvalidRom => c"the ROM is too large or not a valid cartridge",
+        Rc8Status::InvalidState => c"the data is not a save state of rc8",
+        Rc8Status::OutOfRange => c"an argument is out of range",
+        Rc8Status::BufferTooSmall => c"the buffer is too small",
+        Rc8Status::Panic => c"internal error",
+    };
+    message.as_ptr()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // LD V0, 5; LD F, V0; DRW V1, V1, 5; JP 0x206
+    const ROM: [u8; 8] = [0x60, 0x05, 0xF0, 0x29, 0xD1, 0x15, 0x12, 0x06];
+
+    #[test]
+    fn run_and_inspect() {
+        unsafe {
+            let machine = rc8_create();
+            assert_eq!(
+                rc8_load_rom(machine, ROM.as_ptr(), ROM.len()),
+                Rc8Status::Ok
+            );
+            assert_eq!(rc8_run_cycles(machine, 3), Rc8Status::Ok);
+
+            let (mut width, mut height) = (0, 0);
+            let pixels = rc8_framebuffer(machine, &mut width, &mut height);
+            assert_eq!((width, height), (64, 32));
+            let pixels = slice::from_raw_parts(pixels, width * height);
+            assert_eq!(pixels.iter().filter(|&&p| p != 0).count(), 14);
+
+            let mut value = 0;
+            assert_eq!(rc8_get_register(machine, 0, &mut value), Rc8Status::Ok);
+            assert_eq!(value, 5);
+            assert_eq!(
+                rc8_get_register(machine, RC8_REGISTER_I, &mut value),
+                Rc8Status::Ok
+            );
+            assert_eq!(value, 25);
+            assert_eq!(rc8_set_register(machine, 1, 0x100), Rc8Status::OutOfRange);
+            assert_eq!(rc8_set_register(machine, 21, 0), Rc8Status::OutOfRange);
+            assert_eq!(
+                rc8_set_register(machine, RC8_REGISTER_PC, 0x200),
+                Rc8Status::Ok
+            );
+
+            let mut byte = 0;
+            assert_eq!(rc8_read_memory(machine, 0x201, &mut byte, 1), Rc8Status::Ok);
+            assert_eq!(byte, 0x05);
+            assert_eq!(rc8_write_memory(machine, 0x201, &7, 1), Rc8Status::Ok);
+            assert_eq!(
+                rc8_read_memory(machine, 0xFFF, &mut byte, 2),
+                Rc8Status::OutOfRange
+            );
+            assert_eq!(rc8_set_key(machine, 16, true), Rc8Status::OutOfRange);
+            rc8_destroy(machine);
+        }
+    }
+
+    #[test]
+    fn save_and_load_state() {
+        unsafe {
+            let machine = rc8_create();
+            rc8_load_rom(machine, ROM.as_ptr(), ROM.len());
+            rc8_run_frames(machine, 1);
+
+            let mut state = vec![0; rc8_state_size()];
+            assert_eq!(
+                rc8_save_state(machine, state.as_mut_ptr(), 10),
+                Rc8Status::BufferTooSmall
+            );
+            assert_eq!(
+                rc8_save_state(machine, state.as_mut_ptr(), state.len()),
+                Rc8Status::Ok
+            );
+            rc8_load_rom(machine, ROM.as_ptr(), ROM.len());
+            assert_eq!(
+                *rc8_framebuffer(machine, ptr::null_mut(), ptr::null_mut()),
+                0
+            );
+            assert_eq!(
+                rc8_load_state(machine, state.as_ptr(), state.len()),
+                Rc8Status::Ok
+            );
+            let pixels = slice::from_raw_parts(
+                rc8_framebuffer(machine, ptr::null_mut(), ptr::null_mut()),
+                2048,
+            );
+            assert_eq!(pixels.iter().filter(|&&p| p != 0).count(), 14);
+            assert_eq!(
+                rc8_load_state(machine, state.as_ptr(), 3),
+                Rc8Status::InvalidState
+            );
+            rc8_destroy(machine);
+        }
+    }
+
+    #[test]
+    fn null_pointers() {
+        unsafe {
+            assert_eq!(rc8_run_frames(ptr::null_mut(), 1), Rc8Status::NullPointer);
+            assert!(rc8_framebuffer(ptr::null(), ptr::null_mut(), ptr::null_mut()).is_null());
+            let machine = rc8_create();
+            assert_eq!(
+                rc8_load_rom(machine, ptr::null(), 4),
+                Rc8Status::NullPointer
+            );
+            assert_eq!(
+                rc8_get_register(machine, 0, ptr::null_mut()),
+                Rc8Status::NullPointer
+            );
+            let too_large = vec![0; 4096];
+            assert_eq!(
+                rc8_load_rom(machine, too_large.as_ptr(), too_large.len()),
+                Rc8Status::InvalidRom
+            );
+            assert!(!rc8_status_message(Rc8Status::Panic).is_null());
+            rc8_destroy(machine);
+            rc8_destroy(ptr::null_mut());
+        }
+    }
+}
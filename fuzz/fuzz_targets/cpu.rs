@@ -0,0 +1,9 @@
+// Random memory images and registers must never make Cpu::step panic.
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use rc8_fuzz::Input;
+
+fuzz_target!(|input: Input| {
+    rc8_fuzz::run(&input);
+});
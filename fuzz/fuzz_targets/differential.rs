@@ -0,0 +1,9 @@
+// Cpu::step has to leave the same state as the reference interpreter after every instruction.
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use rc8_fuzz::Input;
+
+fuzz_target!(|input: Input| {
+    rc8_fuzz::compare(&input);
+});
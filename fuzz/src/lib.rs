@@ -0,0 +1,363 @@
+// Inputs for the fuzz targets and a reference interpreter to compare Cpu with.
+//
+// The reference is written straight from the specification in a single match, without
+// sharing any code with Cpu. Both have to agree on every instruction for every quirk.
+//
+// Run the targets with cargo-fuzz from the root of the repository, e.g.
+//
+//     cargo +nightly fuzz run cpu
+//     cargo +nightly fuzz run differential
+use arbitrary::Arbitrary;
+use rc8::chip8::{COL, FONTSET, ROW};
+use rc8::cpu::Cpu;
+use rc8::options::Quirks;
+
+// instructions executed per input
+pub const STEPS: usize = 256;
+
+#[derive(Debug, Arbitrary)]
+pub struct Input {
+    // one bit per quirk in the order of Quirks::NAMES
+    pub quirks: u8,
+    // one bit per key
+    pub keypad: u16,
+    pub v: [u8; 16],
+    pub i: u16,
+    pub pc: u16,
+    pub delay_timer: u8,
+    // copied to 0x200, the rest of the memory above the font is zero
+    pub memory: Vec<u8>,
+}
+
+impl Input {
+    pub fn quirks(&self) -> Quirks {
+        let mut quirks = Quirks::default();
+        for (bit, name) in Quirks::NAMES.iter().enumerate() {
+            *quirks.flag_mut(name).unwrap() = self.quirks & (1 << bit) != 0;
+        }
+        quirks
+    }
+
+    pub fn cpu(&self) -> Cpu {
+        let mut cpu = Cpu::new();
+        cpu.load_font();
+        let len = self.memory.len().min(4096 - 0x200);
+        cpu.memory[0x200..0x200 + len].copy_from_slice(&self.memory[..len]);
+        cpu.quirks = self.quirks();
+        for key in 0..16 {
+            cpu.keypad[key] = (self.keypad >> key & 1) as u8;
+        }
+        cpu.v = self.v;
+        cpu.i = self.i;
+        cpu.pc = self.pc & 0xFFF;
+        cpu.delay_timer = self.delay_timer;
+        cpu
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Reference {
+    pub memory: Vec<u8>,
+    pub graphics: Vec<u8>,
+    pub v: [u8; 16],
+    pub i: u16,
+    pub pc: u16,
+    pub stack: [u16; 16],
+    pub sp: u16,
+    pub delay_timer: u8,
+    pub sound_timer: u8,
+    pub keypad: [u8; 16],
+    pub quirks: Quirks,
+}
+
+impl Reference {
+    pub fn new(input: &Input) -> Reference {
+        let mut memory = vec![0; 4096];
+        memory[..FONTSET.len()].copy_from_slice(&FONTSET);
+        let len = input.memory.len().min(4096 - 0x200);
+        memory[0x200..0x200 + len].copy_from_slice(&input.memory[..len]);
+        let mut keypad = [0; 16];
+        for (key, state) in keypad.iter_mut().enumerate() {
+            *state = (input.keypad >> key & 1) as u8;
+        }
+        Reference {
+            memory,
+            graphics: vec![0; COL * ROW],
+            v: input.v,
+            i: input.i,
+            pc: input.pc & 0xFFF,
+            stack: [0; 16],
+            sp: 0,
+            delay_timer: input.delay_timer,
+            sound_timer: 0,
+            keypad,
+            quirks: input.quirks(),
+        }
+    }
+
+    fn read(&self, address: usize) -> u8 {
+        self.memory[address % 4096]
+    }
+
+    // writes the result of 8XY4/8XY5/8XY7 and the flag in the order given by vf_order
+    fn arithmetic(&mut self, x: usize, result: u8, flag: bool) {
+        if self.quirks.vf_order {
+            self.v[0xF] = flag as u8;
+            self.v[x] = result;
+        } else {
+            self.v[x] = result;
+            self.v[0xF] = flag as u8;
+        }
+    }
+
+    // executes one instruction, random is the result of CXNN before the mask
+    pub fn step(&mut self, random: u8) {
+        let opcode =
+            u16::from_be_bytes([self.read(self.pc as usize), self.read(self.pc as usize + 1)]);
+        self.pc += 2;
+        let x = (opcode >> 8 & 0xF) as usize;
+        let y = (opcode >> 4 & 0xF) as usize;
+        let n = opcode & 0xF;
+        let nn = opcode as u8;
+        let nnn = opcode & 0xFFF;
+
+        match opcode >> 12 {
+            0x0 if opcode == 0x00E0 => self.graphics.fill(0),
+            0x0 if opcode == 0x00EE => {
+                self.sp = (self.sp + 15) % 16;
+                self.pc = self.stack[self.sp as usize];
+                self.stack[self.sp as usize] = 0;
+            }
+            0x1 => self.pc = nnn,
+            0x2 => {
+                self.stack[self.sp as usize] = self.pc;
+                self.sp = (self.sp + 1) % 16;
+                self.pc = nnn;
+            }
+            0x3 if self.v[x] == nn => self.pc += 2,
+            0x4 if self.v[x] != nn => self.pc += 2,
+            0x5 if n == 0 && self.v[x] == self.v[y] => self.pc += 2,
+            0x6 => self.v[x] = nn,
+            0x7 => self.v[x] = self.v[x].wrapping_add(nn),
+            0x8 => match n {
+                0x0 => self.v[x] = self.v[y],
+                0x1..=0x3 => {
+                    self.v[x] = match n {
+                        0x1 => self.v[x] | self.v[y],
+                        0x2 => self.v[x] & self.v[y],
+                        _ => self.v[x] ^ self.v[y],
+                    };
+                    if self.quirks.logic {
+                        self.v[0xF] = 0;
+                    }
+                }
+                0x4 => {
+                    let sum = self.v[x] as u16 + self.v[y] as u16;
+                    self.arithmetic(x, sum as u8, sum > 0xFF);
+                }
+                0x5 => {
+                    let (vx, vy) = (self.v[x], self.v[y]);
+                    self.arithmetic(x, vx.wrapping_sub(vy), vx >= vy);
+                }
+                0x7 => {
+                    let (vx, vy) = (self.v[x], self.v[y]);
+                    self.arithmetic(x, vy.wrapping_sub(vx), vy >= vx);
+                }
+                0x6 | 0xE => {
+                    let value = if self.quirks.shift {
+                        self.v[x]
+                    } else {
+                        self.v[y]
+                    };
+                    if n == 0x6 {
+                        self.v[0xF] = value & 1;
+                        self.v[x] = value >> 1;
+                    } else {
+                        self.v[0xF] = value >> 7;
+                        self.v[x] = value << 1;
+                    }
+                }
+                _ => (),
+            },
+            0x9 if n == 0 && self.v[x] != self.v[y] => self.pc += 2,
+            0xA => self.i = nnn,
+            0xB => {
+                let offset = if self.quirks.jump {
+                    self.v[x]
+                } else {
+                    self.v[0]
+                };
+                self.pc = nnn + offset as u16;
+            }
+            0xC => self.v[x] = random & nn,
+            0xD => {
+                let (left, top) = (self.v[x] as usize % COL, self.v[y] as usize % ROW);
+                self.v[0xF] = 0;
+                for row in 0..n as usize {
+                    let sprite = self.read(self.i as usize + row);
+                    for column in 0..8 {
+                        let (px, py) = (left + column, top + row);
+                        if self.quirks.clip && (px >= COL || py >= ROW) {
+                            continue;
+                        }
+                        if sprite & (0x80 >> column) != 0 {
+                            let pixel = &mut self.graphics[py % ROW * COL + px % COL];
+                            if *pixel == 1 {
+                                self.v[0xF] = 1;
+                            }
+                            *pixel ^= 1;
+                        }
+                    }
+                }
+            }
+            0xE => {
+                let pressed = self.keypad[self.v[x] as usize & 0xF] != 0;
+                match nn {
+                    0x9E if pressed => self.pc += 2,
+                    0xA1 if !pressed => self.pc += 2,
+                    _ => (),
+                }
+            }
+            0xF => match nn {
+                0x07 => self.v[x] = self.delay_timer,
+                0x0A => match self.keypad.iter().position(|&key| key != 0) {
+                    Some(key) => self.v[x] = key as u8,
+                    None => self.pc -= 2,
+                },
+                0x15 => self.delay_timer = self.v[x],
+                0x18 => self.sound_timer = self.v[x],
+                0x1E => self.i = self.i.wrapping_add(self.v[x] as u16),
+                0x29 => self.i = (self.v[x] & 0xF) as u16 * 5,
+                0x33 => {
+                    let value = self.v[x];
+                    for (offset, digit) in [value / 100, value / 10 % 10, value % 10]
+                        .into_iter()
+                        .enumerate()
+                    {
+                        self.memory[(self.i as usize + offset) % 4096] = digit;
+                    }
+                }
+                0x55 | 0x65 => {
+                    for offset in 0..=x {
+                        let address = (self.i as usize + offset) % 4096;
+                        if nn == 0x55 {
+                            self.memory[address] = self.v[offset];
+                        } else {
+                            self.v[offset] = self.memory[address];
+                        }
+                    }
+                    if !self.quirks.load_store {
+                        self.i = self.i.wrapping_add(x as u16 + 1);
+                    }
+                }
+                _ => (),
+            },
+            _ => (),
+        }
+        self.pc &= 0xFFF;
+    }
+
+    // the first difference to the state of cpu
+    pub fn compare(&self, cpu: &Cpu) -> Option<String> {
+        let registers = [
+            ("v", format!("{:?}", self.v), format!("{:?}", cpu.v)),
+            ("i", self.i.to_string(), cpu.i.to_string()),
+            ("pc", self.pc.to_string(), cpu.pc.to_string()),
+            ("sp", self.sp.to_string(), cpu.sp.to_string()),
+            (
+                "stack",
+                format!("{:?}", self.stack),
+                format!("{:?}", cpu.stack),
+            ),
+            (
+                "delay_timer",
+                self.delay_timer.to_string(),
+                cpu.delay_timer.to_string(),
+            ),
+            (
+                "sound_timer",
+                self.sound_timer.to_string(),
+                cpu.sound_timer.to_string(),
+            ),
+        ];
+        for (name, expected, actual) in registers {
+            if expected != actual {
+                return Some(format!("{}: expected {}, got {}", name, expected, actual));
+            }
+        }
+        if let Some(address) = (0..4096).find(|&a| self.memory[a] != cpu.memory[a]) {
+            return Some(format!("memory differs at {:#05X}", address));
+        }
+        let pixels = cpu.graphics.pixels();
+        if let Some(pixel) = (0..COL * ROW).find(|&p| self.graphics[p] != pixels[p]) {
+            return Some(format!("pixel {},{} differs", pixel % COL, pixel / COL));
+        }
+        None
+    }
+}
+
+// runs the input on Cpu, which must not panic
+pub fn run(input: &Input) {
+    let mut cpu = input.cpu();
+    for _ in 0..STEPS {
+        cpu.step();
+    }
+}
+
+// runs the input on Cpu and the reference and panics on the first divergence
+pub fn compare(input: &Input) {
+    let mut cpu = input.cpu();
+    let mut reference = Reference::new(input);
+    for step in 0..STEPS {
+        let pc = cpu.pc;
+        let opcode = u16::from_be_bytes([
+            cpu.memory[pc as usize],
+            cpu.memory[(pc as usize + 1) % 4096],
+        ]);
+        cpu.step();
+        // CXNN is random, the reference takes the value of the cpu
+        let random = if opcode >> 12 == 0xC {
+            cpu.v[(opcode >> 8 & 0xF) as usize]
+        } else {
+            0
+        };
+        reference.step(random);
+        if let Some(difference) = reference.compare(&cpu) {
+            panic!(
+                "{:#05X} {:04X} at step {}: {}",
+                pc, opcode, step, difference
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // pseudo-random inputs, without needing libFuzzer
+    #[test]
+    fn cpu_matches_reference() {
+        let mut state: u64 = 0x2545F4914F6CDD1D;
+        let mut next = move || {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state
+        };
+        for _ in 0..500 {
+            let mut v = [0; 16];
+            v.iter_mut().for_each(|v| *v = next() as u8);
+            let input = Input {
+                quirks: next() as u8,
+                keypad: next() as u16 & next() as u16,
+                v,
+                i: next() as u16,
+                pc: 0x200,
+                delay_timer: next() as u8,
+                memory: (0..64).map(|_| next() as u8).collect(),
+            };
+            compare(&input);
+        }
+    }
+}
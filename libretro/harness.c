@@ -0,0 +1,125 @@
+/* A minimal libretro frontend for trying the core without RetroArch. It loads a ROM, runs
+ * it for a few seconds with no keys pressed, checks that a save state restores the same
+ * picture and prints the last frame. */
+#include <dlfcn.h>
+#include <stdbool.h>
+#include <stdint.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+struct retro_game_info {
+    const char *path;
+    const void *data;
+    size_t size;
+    const char *meta;
+};
+
+static uint32_t frame[32][64];
+static size_t samples;
+
+static bool environment(unsigned cmd, void *data) {
+    (void)data;
+    /* SET_PIXEL_FORMAT and SET_INPUT_DESCRIPTORS */
+    return cmd == 10 || cmd == 11;
+}
+
+static void video_refresh(const void *data, unsigned width, unsigned height, size_t pitch) {
+    for (unsigned y = 0; y < height && y < 32; y++)
+        memcpy(frame[y], (const char *)data + y * pitch, width * 4);
+}
+
+static size_t audio_sample_batch(const int16_t *data, size_t frames) {
+    (void)data;
+    samples += frames;
+    return frames;
+}
+
+static void input_poll(void) {}
+
+static int16_t input_state(unsigned port, unsigned device, unsigned index, unsigned id) {
+    (void)port, (void)device, (void)index, (void)id;
+    return 0;
+}
+
+static void *symbol(void *core, const char *name) {
+    void *address = dlsym(core, name);
+    if (!address) {
+        fprintf(stderr, "missing %s\n", name);
+        exit(1);
+    }
+    return address;
+}
+
+#define CALL(type, name) ((type)symbol(core, name))
+
+int main(int argc, char **argv) {
+    if (argc != 3) {
+        fprintf(stderr, "usage: %s CORE ROM\n", argv[0]);
+        return 1;
+    }
+    void *core = dlopen(argv[1], RTLD_NOW);
+    if (!core) {
+        fprintf(stderr, "%s\n", dlerror());
+        return 1;
+    }
+    FILE *file = fopen(argv[2], "rb");
+    if (!file) {
+        perror(argv[2]);
+        return 1;
+    }
+    static char rom[4096];
+    struct retro_game_info game = {argv[2], rom, fread(rom, 1, sizeof rom, file), NULL};
+    fclose(file);
+
+    CALL(void (*)(void *), "retro_set_environment")(environment);
+    CALL(void (*)(void *), "retro_set_video_refresh")(video_refresh);
+    CALL(void (*)(void *), "retro_set_audio_sample_batch")(audio_sample_batch);
+    CALL(void (*)(void *), "retro_set_input_poll")(input_poll);
+    CALL(void (*)(void *), "retro_set_input_state")(input_state);
+    CALL(void (*)(void), "retro_init")();
+    if (!CALL(bool (*)(const struct retro_game_info *), "retro_load_game")(&game)) {
+        fprintf(stderr, "the core couldn't load %s\n", argv[2]);
+        return 1;
+    }
+
+    void (*run)(void) = CALL(void (*)(void), "retro_run");
+    for (int i = 0; i < 120; i++)
+        run();
+
+    size_t size = CALL(size_t (*)(void), "retro_serialize_size")();
+    void *state = malloc(size);
+    static uint32_t saved[32][64];
+    if (!CALL(bool (*)(void *, size_t), "retro_serialize")(state, size)) {
+        fprintf(stderr, "retro_serialize failed\n");
+        return 1;
+    }
+    run();
+    memcpy(saved, frame, sizeof frame);
+    for (int i = 0; i < 60; i++)
+        run();
+    CALL(void (*)(void), "retro_reset")();
+    run();
+    if (!CALL(bool (*)(const void *, size_t), "retro_unserialize")(state, size)) {
+        fprintf(stderr, "retro_unserialize failed\n");
+        return 1;
+    }
+    run();
+    if (memcmp(saved, frame, sizeof frame) != 0) {
+        fprintf(stderr, "the save state didn't restore the frame\n");
+        return 1;
+    }
+
+    for (int y = 0; y < 32; y++) {
+        for (int x = 0; x < 64; x++)
+            putchar(frame[y][x] ? '#' : '.');
+        putchar('\n');
+    }
+    printf("%zu audio frames, save states of %zu bytes\n", samples, size);
+
+    CALL(void (*)(void), "retro_unload_game")();
+    CALL(void (*)(void), "retro_deinit")();
+    free(state);
+    dlclose(core);
+    return 0;
+}
@@ -0,0 +1,554 @@
+// libretro core of rc8, for running it in RetroArch or any other libretro frontend.
+//
+// The 16 keys of the keypad are mapped to the 16 buttons of the RetroPad of port 0, with
+// the D-pad on 2, 4, 6 and 8, which most games use for directions. The sound timer plays
+// a square wave through the audio batch callback. Save states are the ones of rc8::state.
+//
+// Build the core and try it with the minimal frontend in harness.c:
+//
+//     cargo build --release
+//     cc -o harness harness.c -ldl
+//     ./harness target/release/librc8_libretro.so ../IBM.ch8
+#![allow(clippy::missing_safety_doc)]
+
+use rc8::chip8::{Chip8, COL, ROW};
+use rc8::phosphor::{blend, Phosphor};
+use std::error::Error;
+use std::ffi::{c_char, c_uint, c_void, CStr};
+use std::slice;
+use std::sync::{Mutex, MutexGuard};
+
+const API_VERSION: c_uint = 1;
+const DEVICE_JOYPAD: c_uint = 1;
+const REGION_NTSC: c_uint = 0;
+const ENVIRONMENT_SET_PIXEL_FORMAT: c_uint = 10;
+const ENVIRONMENT_SET_INPUT_DESCRIPTORS: c_uint = 11;
+const PIXEL_FORMAT_XRGB8888: c_uint = 1;
+
+const FPS: f64 = 60.0;
+const SAMPLE_RATE: usize = 44100;
+const SAMPLES_PER_FRAME: usize = SAMPLE_RATE / FPS as usize;
+const TONE: usize = 440;
+const VOLUME: i16 = 0x1800;
+
+// RetroPad button, CHIP-8 key and the description shown by the frontend
+const KEYS: [(c_uint, u8, &CStr); 16] = [
+    (4, 0x2, c"Up (2)"),
+    (5, 0x8, c"Down (8)"),
+    (6, 0x4, c"Left (4)"),
+    (7, 0x6, c"Right (6)"),
+    (8, 0x5, c"A (5)"),
+    (0, 0x0, c"B (0)"),
+    (9, 0x1, c"X (1)"),
+    (1, 0x3, c"Y (3)"),
+    (10, 0x7, c"L (7)"),
+    (11, 0x9, c"R (9)"),
+    (12, 0xA, c"L2 (A)"),
+    (13, 0xB, c"R2 (B)"),
+    (14, 0xC, c"L3 (C)"),
+    (15, 0xD, c"R3 (D)"),
+    (2, 0xE, c"Select (E)"),
+    (3, 0xF, c"Start (F)"),
+];
+
+#[repr(C)]
+pub struct SystemInfo {
+    pub library_name: *const c_char,
+    pub library_version: *const c_char,
+    pub valid_extensions: *const c_char,
+    pub need_fullpath: bool,
+    pub block_extract: bool,
+}
+
+#[repr(C)]
+pub struct GameGeometry {
+    pub base_width: c_uint,
+    pub base_height: c_uint,
+    pub max_width: c_uint,
+    pub max_height: c_uint,
+    pub aspect_ratio: f32,
+}
+
+#[repr(C)]
+pub struct SystemTiming {
+    pub fps: f64,
+    pub sample_rate: f64,
+}
+
+#[repr(C)]
+pub struct SystemAvInfo {
+    pub geometry: GameGeometry,
+    pub timing: SystemTiming,
+}
+
+#[repr(C)]
+pub struct GameInfo {
+    pub path: *const c_char,
+    pub data: *const c_void,
+    pub size: usize,
+    pub meta: *const c_char,
+}
+
+#[repr(C)]
+pub struct InputDescriptor {
+    pub port: c_uint,
+    pub device: c_uint,
+    pub index: c_uint,
+    pub id: c_uint,
+    pub description: *const c_char,
+}
+
+pub type EnvironmentFn = unsafe extern "C" fn(cmd: c_uint, data: *mut c_void) -> bool;
+pub type VideoRefreshFn =
+    unsafe extern "C" fn(data: *const c_void, width: c_uint, height: c_uint, pitch: usize);
+pub type AudioSampleFn = unsafe extern "C" fn(left: i16, right: i16);
+pub type AudioSampleBatchFn = unsafe extern "C" fn(data: *const i16, frames: usize) -> usize;
+pub type InputPollFn = unsafe extern "C" fn();
+pub type InputStateFn =
+    unsafe extern "C" fn(port: c_uint, device: c_uint, index: c_uint, id: c_uint) -> i16;
+
+struct Game {
+    chip: Chip8,
+    // the file as given by the frontend, for retro_reset
+    data: Vec<u8>,
+    phosphor: Phosphor,
+    // XRGB8888, row by row
+    video: Vec<u32>,
+    // position in the period of the square wave
+    phase: usize,
+}
+
+impl Game {
+    fn new(data: Vec<u8>) -> Result<Game, Box<dyn Error>> {
+        let mut chip = Chip8::new();
+        chip.load_data(data.clone())?;
+        let options = chip.options();
+        let mut game = Game {
+            chip,
+            data,
+            phosphor: Phosphor::new(options.persistence),
+            video: vec![options.palette.background; ROW * COL],
+            phase: 0,
+        };
+        game.paint();
+        Ok(game)
+    }
+
+    // redraws the pixels that changed since the last frame
+    fn paint(&mut self) {
+        let dirty = self.chip.take_dirty();
+        let palette = self.chip.options().palette;
+        if let Some(rect) = self.phosphor.update(&self.chip.framebuffer(), dirty) {
+            let map = self.phosphor.frame();
+            for (x, y) in rect.pixels() {
+                let pixel = x + y * COL;
+                self.video[pixel] = blend(palette.background, palette.fill, map[pixel]);
+            }
+        }
+    }
+
+    fn audio(&mut self) -> [i16; SAMPLES_PER_FRAME * 2] {
+        let mut samples = [0; SAMPLES_PER_FRAME * 2];
+        if self.chip.sound_active() {
+            let period = SAMPLE_RATE / TONE;
+            for frame in samples.chunks_mut(2) {
+                let sample = if self.phase < period / 2 {
+                    VOLUME
+                } else {
+                    -VOLUME
+                };
+                frame.fill(sample);
+                self.phase = (self.phase + 1) % period;
+            }
+        }
+        samples
+    }
+}
+
+struct Core {
+    environment: Option<EnvironmentFn>,
+    video_refresh: Option<VideoRefreshFn>,
+    audio_sample_batch: Option<AudioSampleBatchFn>,
+    input_poll: Option<InputPollFn>,
+    input_state: Option<InputStateFn>,
+    game: Option<Game>,
+}
+
+// libretro has no context pointer, so the core is global
+static CORE: Mutex<Core> = Mutex::new(Core {
+    environment: None,
+    video_refresh: None,
+    audio_sample_batch: None,
+    input_poll: None,
+    input_state: None,
+    game: None,
+});
+
+fn core() -> MutexGuard<'static, Core> {
+    CORE.lock().unwrap_or_else(|err| err.into_inner())
+}
+
+#[no_mangle]
+pub extern "C" fn retro_api_version() -> c_uint {
+    API_VERSION
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn retro_get_system_info(info: *mut SystemInfo) {
+    *info = SystemInfo {
+        library_name: c"rc8".as_ptr(),
+        library_version: c"0.1.0".as_ptr(),
+        valid_extensions: c"ch8|gif".as_ptr(),
+        need_fullpath: false,
+        block_extract: false,
+    };
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn retro_get_system_av_info(info: *mut SystemAvInfo) {
+    *info = SystemAvInfo {
+        geometry: GameGeometry {
+            base_width: COL as c_uint,
+            base_height: ROW as c_uint,
+            max_width: COL as c_uint,
+            max_height: ROW as c_uint,
+            aspect_ratio: COL as f32 / ROW as f32,
+        },
+        timing: SystemTiming {
+            fps: FPS,
+            sample_rate: SAMPLE_RATE as f64,
+        },
+    };
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_environment(callback: EnvironmentFn) {
+    core().environment = Some(callback);
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_video_refresh(callback: VideoRefreshFn) {
+    core().video_refresh = Some(callback);
+}
+
+// the batch callback is used instead
+#[no_mangle]
+pub extern "C" fn retro_set_audio_sample(_callback: AudioSampleFn) {}
+
+#[no_mangle]
+pub extern "C" fn retro_set_audio_sample_batch(callback: AudioSampleBatchFn) {
+    core().audio_sample_batch = Some(callback);
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_input_poll(callback: InputPollFn) {
+    core().input_poll = Some(callback);
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_input_state(callback: InputStateFn) {
+    core().input_state = Some(callback);
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_controller_port_device(_port: c_uint, _device: c_uint) {}
+
+#[no_mangle]
+pub extern "C" fn retro_init() {}
+
+#[no_mangle]
+pub extern "C" fn retro_deinit() {
+    core().game = None;
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn retro_load_game(info: *const GameInfo) -> bool {
+    let mut core = core();
+    let Some(environment) = core.environment else {
+        return false;
+    };
+    if info.is_null() || (*info).data.is_null() {
+        return false;
+    }
+    let mut format = PIXEL_FORMAT_XRGB8888;
+    if !environment(
+        ENVIRONMENT_SET_PIXEL_FORMAT,
+        &mut format as *mut c_uint as *mut c_void,
+    ) {
+        eprintln!("rc8: the frontend doesn't support XRGB8888");
+        return false;
+    }
+    let mut descriptors: Vec<InputDescriptor> = KEYS
+        .iter()
+        .map(|&(id, _, description)| InputDescriptor {
+            port: 0,
+            device: DEVICE_JOYPAD,
+            index: 0,
+            id,
+            description: description.as_ptr(),
+        })
+        .collect();
+    // the list ends with an empty descriptor
+    descriptors.push(InputDescriptor {
+        port: 0,
+        device: 0,
+        index: 0,
+        id: 0,
+        description: std::ptr::null(),
+    });
+    environment(
+        ENVIRONMENT_SET_INPUT_DESCRIPTORS,
+        descriptors.as_mut_ptr() as *mut c_void,
+    );
+
+    let data = slice::from_raw_parts((*info).data as *const u8, (*info).size).to_vec();
+    match Game::new(data) {
+        Ok(game) => {
+            core.game = Some(game);
+            true
+        }
+        Err(err) => {
+            eprintln!("rc8: {}", err);
+            false
+        }
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn retro_load_game_special(
+    _game_type: c_uint,
+    _info: *const GameInfo,
+    _num_info: usize,
+) -> bool {
+    false
+}
+
+#[no_mangle]
+pub extern "C" fn retro_unload_game() {
+    core().game = None;
+}
+
+#[no_mangle]
+pub extern "C" fn retro_get_region() -> c_uint {
+    REGION_NTSC
+}
+
+#[no_mangle]
+pub extern "C" fn retro_reset() {
+    let mut core = core();
+    if let Some(game) = &mut core.game {
+        if let Ok(new) = Game::new(game.data.clone()) {
+            *game = new;
+        }
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn retro_run() {
+    let mut core = core();
+    let core = &mut *core;
+    let Some(game) = &mut core.game else {
+        return;
+    };
+
+    if let Some(poll) = core.input_poll {
+        poll();
+    }
+    let mut keys = 0;
+    if let Some(state) = core.input_state {
+        for (id, key, _) in KEYS {
+            if state(0, DEVICE_JOYPAD, 0, id) != 0 {
+                keys |= 1 << key;
+            }
+        }
+    }
+    game.chip.set_keypad(keys);
+    game.chip.run_frame();
+    game.paint();
+
+    if let Some(video_refresh) = core.video_refresh {
+        video_refresh(
+            game.video.as_ptr() as *const c_void,
+            COL as c_uint,
+            ROW as c_uint,
+            COL * 4,
+        );
+    }
+    if let Some(audio_sample_batch) = core.audio_sample_batch {
+        let samples = game.audio();
+        audio_sample_batch(samples.as_ptr(), SAMPLES_PER_FRAME);
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn retro_serialize_size() -> usize {
+    rc8::state::SIZE
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn retro_serialize(data: *mut c_void, size: usize) -> bool {
+    let core = core();
+    let Some(game) = &core.game else {
+        return false;
+    };
+    let state = game.chip.save_state();
+    if data.is_null() || size < state.len() {
+        return false;
+    }
+    slice::from_raw_parts_mut(data as *mut u8, state.len()).copy_from_slice(&state);
+    true
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn retro_unserialize(data: *const c_void, size: usize) -> bool {
+    let mut core = core();
+    let Some(game) = &mut core.game else {
+        return false;
+    };
+    if data.is_null() {
+        return false;
+    }
+    let state = slice::from_raw_parts(data as *const u8, size.min(rc8::state::SIZE));
+    match game.chip.load_state(state) {
+        Ok(()) => true,
+        Err(err) => {
+            eprintln!("rc8: {}", err);
+            false
+        }
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn retro_cheat_reset() {}
+
+#[no_mangle]
+pub extern "C" fn retro_cheat_set(_index: c_uint, _enabled: bool, _code: *const c_char) {}
+
+// the memory isn't exposed, writes to it would bypass the cached engine
+#[no_mangle]
+pub extern "C" fn retro_get_memory_data(_id: c_uint) -> *mut c_void {
+    std::ptr::null_mut()
+}
+
+#[no_mangle]
+pub extern "C" fn retro_get_memory_size(_id: c_uint) -> usize {
+    0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rc8::assembler::assemble;
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+    static FRAME: Mutex<Vec<u32>> = Mutex::new(Vec::new());
+    static SAMPLES: AtomicUsize = AtomicUsize::new(0);
+    static LOUD: AtomicBool = AtomicBool::new(false);
+    static KEY_5: AtomicBool = AtomicBool::new(false);
+
+    unsafe extern "C" fn environment(cmd: c_uint, data: *mut c_void) -> bool {
+        match cmd {
+            ENVIRONMENT_SET_PIXEL_FORMAT => *(data as *const c_uint) == PIXEL_FORMAT_XRGB8888,
+            ENVIRONMENT_SET_INPUT_DESCRIPTORS => {
+                let descriptors = data as *const InputDescriptor;
+                (0..)
+                    .take_while(|&i| !(*descriptors.add(i)).description.is_null())
+                    .count()
+                    == 16
+            }
+            _ => false,
+        }
+    }
+
+    unsafe extern "C" fn video_refresh(
+        data: *const c_void,
+        width: c_uint,
+        height: c_uint,
+        pitch: usize,
+    ) {
+        assert_eq!((width, height, pitch), (64, 32, 256));
+        let pixels = slice::from_raw_parts(data as *const u32, 64 * 32);
+        *FRAME.lock().unwrap() = pixels.to_vec();
+    }
+
+    unsafe extern "C" fn audio_sample_batch(data: *const i16, frames: usize) -> usize {
+        let samples = slice::from_raw_parts(data, frames * 2);
+        LOUD.store(samples.iter().any(|&s| s != 0), Ordering::SeqCst);
+        SAMPLES.fetch_add(frames, Ordering::SeqCst);
+        frames
+    }
+
+    unsafe extern "C" fn input_poll() {}
+
+    unsafe extern "C" fn input_state(port: c_uint, device: c_uint, _: c_uint, id: c_uint) -> i16 {
+        // A is key 5
+        (port == 0 && device == DEVICE_JOYPAD && id == 8 && KEY_5.load(Ordering::SeqCst)) as i16
+    }
+
+    fn lit() -> usize {
+        FRAME.lock().unwrap().iter().filter(|&&p| p != 0).count()
+    }
+
+    // the core is global, so everything is tested in one go
+    #[test]
+    fn run_a_game() {
+        // waits for key 5, then draws the digit 5 and beeps
+        let rom = assemble(
+            "
+                    LD V0, K
+                    LD F, V0
+                    DRW V1, V1, 5
+                    LD V2, 30
+                    LD ST, V2
+            loop:   JP loop
+            ",
+        )
+        .unwrap();
+        retro_set_environment(environment);
+        retro_set_video_refresh(video_refresh);
+        retro_set_audio_sample_batch(audio_sample_batch);
+        retro_set_input_poll(input_poll);
+        retro_set_input_state(input_state);
+        retro_init();
+        let info = GameInfo {
+            path: std::ptr::null(),
+            data: rom.as_ptr() as *const c_void,
+            size: rom.len(),
+            meta: std::ptr::null(),
+        };
+        unsafe {
+            assert!(retro_load_game(&info));
+            retro_run();
+            retro_run();
+            assert_eq!(lit(), 0);
+            assert_eq!(SAMPLES.load(Ordering::SeqCst), 2 * SAMPLES_PER_FRAME);
+
+            KEY_5.store(true, Ordering::SeqCst);
+            retro_run();
+            KEY_5.store(false, Ordering::SeqCst);
+            retro_run();
+            // the digit 5 of the font has 14 pixels
+            assert_eq!(lit(), 14);
+            assert!(LOUD.load(Ordering::SeqCst));
+
+            let mut state = vec![0u8; retro_serialize_size()];
+            assert!(retro_serialize(
+                state.as_mut_ptr() as *mut c_void,
+                state.len()
+            ));
+            retro_reset();
+            retro_run();
+            assert_eq!(lit(), 0);
+            assert!(!LOUD.load(Ordering::SeqCst));
+            assert!(retro_unserialize(
+                state.as_ptr() as *const c_void,
+                state.len()
+            ));
+            retro_run();
+            assert_eq!(lit(), 14);
+            assert!(!retro_unserialize(state.as_ptr() as *const c_void, 10));
+        }
+        retro_unload_game();
+        retro_deinit();
+    }
+}
@@ -0,0 +1,246 @@
+// Assembler for the mnemonics printed by Instruction, e.g.
+//
+//     ; comments start with a semicolon
+//     loop:   LD I, sprite        ; labels end with a colon
+//             DRW V0, V1, 5
+//             JP loop
+//     sprite: DB 0xF0, 0x90, 0x90, 0x90, 0xF0
+//
+// Numbers are decimal, hexadecimal with 0x or binary with 0b. The program starts at 0x200.
+use crate::instruction::Instruction;
+use std::collections::HashMap;
+use std::error::Error;
+
+const START: u16 = 0x200;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Operand<'a> {
+    Register(u8),
+    I,
+    IndirectI,
+    Delay,
+    Sound,
+    Key,
+    Font,
+    Bcd,
+    // a number or a label
+    Value(&'a str),
+}
+
+fn operand(text: &str) -> Operand<'_> {
+    match text.to_uppercase().as_str() {
+        "I" => Operand::I,
+        "[I]" => Operand::IndirectI,
+        "DT" => Operand::Delay,
+        "ST" => Operand::Sound,
+        "K" => Operand::Key,
+        "F" => Operand::Font,
+        "B" => Operand::Bcd,
+        upper => match upper.strip_prefix('V').map(|x| u8::from_str_radix(x, 16)) {
+            Some(Ok(x)) if x < 16 && upper.len() == 2 => Operand::Register(x),
+            _ => Operand::Value(text),
+        },
+    }
+}
+
+fn number(text: &str) -> Option<u32> {
+    let lower = text.to_lowercase();
+    if let Some(hex) = lower.strip_prefix("0x") {
+        u32::from_str_radix(hex, 16).ok()
+    } else if let Some(binary) = lower.strip_prefix("0b") {
+        u32::from_str_radix(binary, 2).ok()
+    } else {
+        lower.parse().ok()
+    }
+}
+
+// a line without comment and labels
+struct Statement<'a> {
+    line: usize,
+    mnemonic: String,
+    operands: Vec<&'a str>,
+}
+
+impl Statement<'_> {
+    fn size(&self) -> usize {
+        match self.mnemonic.as_str() {
+            "DB" => self.operands.len(),
+            _ => 2,
+        }
+    }
+}
+
+pub fn assemble(source: &str) -> Result<Vec<u8>, Box<dyn Error>> {
+    let mut labels = HashMap::new();
+    let mut statements = Vec::new();
+    let mut address = START as usize;
+
+    for (index, line) in source.lines().enumerate() {
+        let mut text = line.split(';').next().unwrap_or_default().trim();
+        while let Some((label, rest)) = text.split_once(':') {
+            let label = label.trim();
+            if label.is_empty() || label.contains(char::is_whitespace) {
+                return Err(format!("line {}: invalid label '{}'", index + 1, label).into());
+            }
+            if labels.insert(label, address as u16).is_some() {
+                return Err(format!("line {}: duplicate label '{}'", index + 1, label).into());
+            }
+            text = rest.trim();
+        }
+        if text.is_empty() {
+            continue;
+        }
+
+        let (mnemonic, operands) = text.split_once(char::is_whitespace).unwrap_or((text, ""));
+        let statement = Statement {
+            line: index + 1,
+            mnemonic: mnemonic.to_uppercase(),
+            operands: operands
+                .split(',')
+                .map(str::trim)
+                .filter(|operand| !operand.is_empty())
+                .collect(),
+        };
+        address += statement.size();
+        statements.push(statement);
+    }
+    if address > 4096 {
+        return Err(format!("Program is too large ({} bytes)", address - START as usize).into());
+    }
+
+    let mut rom = Vec::new();
+    for statement in &statements {
+        let value = |text: &str, max: u32| -> Result<u32, String> {
+            let value = match labels.get(text) {
+                Some(&address) => address as u32,
+                None => number(text).ok_or_else(|| format!("unknown label '{}'", text))?,
+            };
+            if value > max {
+                return Err(format!("{} is larger than {:#X}", text, max));
+            }
+            Ok(value)
+        };
+        encode(statement, &mut rom, value)
+            .map_err(|err| format!("line {}: {}", statement.line, err))?;
+    }
+    Ok(rom)
+}
+
+fn encode(
+    statement: &Statement,
+    rom: &mut Vec<u8>,
+    value: impl Fn(&str, u32) -> Result<u32, String>,
+) -> Result<(), String> {
+    use Operand::*;
+    let operands: Vec<Operand> = statement.operands.iter().map(|o| operand(o)).collect();
+    let address = |text| value(text, 0xFFF).map(|v| v as u16);
+    let byte = |text| value(text, 0xFF).map(|v| v as u8);
+
+    let instruction = match (statement.mnemonic.as_str(), &operands[..]) {
+        ("DB", bytes) => {
+            for &operand in bytes {
+                match operand {
+                    Value(text) => rom.push(byte(text)?),
+                    _ => return Err("DB expects numbers".to_string()),
+                }
+            }
+            return Ok(());
+        }
+        ("DW", [Value(text)]) => Instruction::Unknown(value(text, 0xFFFF)? as u16),
+        ("SYS", [Value(a)]) => Instruction::Sys(address(a)?),
+        ("CLS", []) => Instruction::Clear,
+        ("RET", []) => Instruction::Return,
+        ("JP", [Value(a)]) => Instruction::Jump(address(a)?),
+        ("JP", [Register(0), Value(a)]) => Instruction::JumpOffset(address(a)?),
+        ("CALL", [Value(a)]) => Instruction::Call(address(a)?),
+        ("SE", [Register(x), Register(y)]) => Instruction::SkipEqualRegister(*x, *y),
+        ("SE", [Register(x), Value(nn)]) => Instruction::SkipEqual(*x, byte(nn)?),
+        ("SNE", [Register(x), Register(y)]) => Instruction::SkipNotEqualRegister(*x, *y),
+        ("SNE", [Register(x), Value(nn)]) => Instruction::SkipNotEqual(*x, byte(nn)?),
+        ("LD", [Register(x), Register(y)]) => Instruction::Move(*x, *y),
+        ("LD", [Register(x), Value(nn)]) => Instruction::Load(*x, byte(nn)?),
+        ("LD", [Register(x), Delay]) => Instruction::GetDelay(*x),
+        ("LD", [Register(x), Key]) => Instruction::WaitKey(*x),
+        ("LD", [Register(x), IndirectI]) => Instruction::Restore(*x),
+        ("LD", [I, Value(a)]) => Instruction::LoadIndex(address(a)?),
+        ("LD", [Delay, Register(x)]) => Instruction::SetDelay(*x),
+        ("LD", [Sound, Register(x)]) => Instruction::SetSound(*x),
+        ("LD", [Font, Register(x)]) => Instruction::Font(*x),
+        ("LD", [Bcd, Register(x)]) => Instruction::Bcd(*x),
+        ("LD", [IndirectI, Register(x)]) => Instruction::Store(*x),
+        ("ADD", [Register(x), Register(y)]) => Instruction::AddRegister(*x, *y),
+        ("ADD", [Register(x), Value(nn)]) => Instruction::Add(*x, byte(nn)?),
+        ("ADD", [I, Register(x)]) => Instruction::AddIndex(*x),
+        ("OR", [Register(x), Register(y)]) => Instruction::Or(*x, *y),
+        ("AND", [Register(x), Register(y)]) => Instruction::And(*x, *y),
+        ("XOR", [Register(x), Register(y)]) => Instruction::Xor(*x, *y),
+        ("SUB", [Register(x), Register(y)]) => Instruction::Sub(*x, *y),
+        ("SUBN", [Register(x), Register(y)]) => Instruction::SubReverse(*x, *y),
+        ("SHR", [Register(x)]) => Instruction::ShiftRight(*x, *x),
+        ("SHR", [Register(x), Register(y)]) => Instruction::ShiftRight(*x, *y),
+        ("SHL", [Register(x)]) => Instruction::ShiftLeft(*x, *x),
+        ("SHL", [Register(x), Register(y)]) => Instruction::ShiftLeft(*x, *y),
+        ("RND", [Register(x), Value(nn)]) => Instruction::Random(*x, byte(nn)?),
+        ("DRW", [Register(x), Register(y), Value(n)]) => {
+            Instruction::Draw(*x, *y, value(n, 0xF)? as u8)
+        }
+        ("SKP", [Register(x)]) => Instruction::SkipKey(*x),
+        ("SKNP", [Register(x)]) => Instruction::SkipNotKey(*x),
+        _ => {
+            return Err(format!(
+                "invalid instruction '{} {}'",
+                statement.mnemonic,
+                statement.operands.join(", ")
+            ))
+        }
+    };
+    rom.extend_from_slice(&instruction.encode().to_be_bytes());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn labels_and_data() {
+        let source = "
+            ; draws a box forever
+            start:  LD I, box
+                    drw v0, v1, 2   ; lower case works as well
+            loop:   JP loop
+            box:    DB 0b11110000, 0x90
+        ";
+        let rom = assemble(source).unwrap();
+        assert_eq!(rom, [0xA2, 0x06, 0xD0, 0x12, 0x12, 0x04, 0xF0, 0x90]);
+    }
+
+    #[test]
+    fn disassembly_round_trip() {
+        let opcodes = [
+            0x00E0, 0x00EE, 0x0123, 0x1228, 0x2300, 0x3A0F, 0x4B10, 0x5120, 0x6A05, 0x7001, 0x8120,
+            0x8121, 0x8122, 0x8123, 0x8124, 0x8125, 0x8126, 0x8127, 0x812E, 0x9120, 0xA22A, 0xB300,
+            0xC1FF, 0xD01F, 0xE19E, 0xE1A1, 0xF107, 0xF10A, 0xF115, 0xF118, 0xF11E, 0xF129, 0xF133,
+            0xF155, 0xF165, 0x5121,
+        ];
+        let source: String = opcodes
+            .iter()
+            .map(|&opcode| format!("{}\n", Instruction::decode(opcode)))
+            .collect();
+        let rom = assemble(&source).unwrap();
+        let assembled: Vec<u16> = rom
+            .chunks(2)
+            .map(|word| u16::from_be_bytes([word[0], word[1]]))
+            .collect();
+        assert_eq!(assembled, opcodes);
+    }
+
+    #[test]
+    fn errors() {
+        assert!(assemble("JP nowhere").is_err());
+        assert!(assemble("LD V0, 256").is_err());
+        assert!(assemble("a: CLS\na: CLS").is_err());
+        let err = assemble("CLS\nMOV V0, V1").unwrap_err();
+        assert_eq!(err.to_string(), "line 2: invalid instruction 'MOV V0, V1'");
+    }
+}
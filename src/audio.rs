@@ -0,0 +1,104 @@
+// The beep of the window, a square wave queued to the default audio device while the sound
+// timer is active. The window of simple owns the SDL context, so the audio device is opened
+// through the bindings of sdl2-sys, which simple links anyway.
+use sdl2_sys::{
+    SDL_AudioDeviceID, SDL_AudioSpec, SDL_ClearQueuedAudio, SDL_CloseAudioDevice,
+    SDL_GetQueuedAudioSize, SDL_InitSubSystem, SDL_OpenAudioDevice, SDL_PauseAudioDevice,
+    SDL_QueueAudio, SDL_QuitSubSystem, AUDIO_S8, SDL_INIT_AUDIO,
+};
+use std::{mem, ptr};
+
+const RATE: u32 = 44_100;
+const PITCH: u32 = 440;
+const VOLUME: i8 = 24;
+// samples kept in the queue, two frames so a late frame doesn't cut the beep
+const AHEAD: u32 = 2 * RATE / 60;
+
+pub struct Beeper {
+    device: SDL_AudioDeviceID,
+    // position within the period of the wave, in samples
+    phase: u32,
+}
+
+impl Beeper {
+    // None if SDL has no audio device
+    pub fn new() -> Option<Beeper> {
+        unsafe {
+            if SDL_InitSubSystem(SDL_INIT_AUDIO) != 0 {
+                return None;
+            }
+            let mut desired: SDL_AudioSpec = mem::zeroed();
+            desired.freq = RATE as i32;
+            desired.format = AUDIO_S8 as u16;
+            desired.channels = 1;
+            desired.samples = 512;
+            // without a callback the samples are queued with SDL_QueueAudio
+            let device = SDL_OpenAudioDevice(ptr::null(), 0, &desired, ptr::null_mut(), 0);
+            if device == 0 {
+                SDL_QuitSubSystem(SDL_INIT_AUDIO);
+                return None;
+            }
+            SDL_PauseAudioDevice(device, 0);
+            Some(Beeper { device, phase: 0 })
+        }
+    }
+
+    // called every frame with whether the sound timer is active
+    pub fn update(&mut self, on: bool) {
+        unsafe {
+            if !on {
+                SDL_ClearQueuedAudio(self.device);
+                self.phase = 0;
+                return;
+            }
+            let queued = SDL_GetQueuedAudioSize(self.device);
+            if queued < AHEAD {
+                let samples = square(&mut self.phase, (AHEAD - queued) as usize);
+                SDL_QueueAudio(self.device, samples.as_ptr().cast(), samples.len() as u32);
+            }
+        }
+    }
+}
+
+impl Drop for Beeper {
+    fn drop(&mut self) {
+        unsafe {
+            SDL_CloseAudioDevice(self.device);
+            SDL_QuitSubSystem(SDL_INIT_AUDIO);
+        }
+    }
+}
+
+// the next samples of the wave, continuing at phase
+fn square(phase: &mut u32, len: usize) -> Vec<i8> {
+    let period = RATE / PITCH;
+    (0..len)
+        .map(|_| {
+            let high = *phase < period / 2;
+            *phase = (*phase + 1) % period;
+            if high {
+                VOLUME
+            } else {
+                -VOLUME
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn square_wave() {
+        let mut phase = 0;
+        let first = square(&mut phase, 60);
+        assert!(first[..50].iter().all(|&sample| sample == VOLUME));
+        assert!(first[50..].iter().all(|&sample| sample == -VOLUME));
+
+        // the next call continues the period
+        let next = square(&mut phase, 60);
+        assert!(next[..40].iter().all(|&sample| sample == -VOLUME));
+        assert!(next[40..].iter().all(|&sample| sample == VOLUME));
+    }
+}
@@ -0,0 +1,268 @@
+// Cached interpreter for running ROMs much faster than real time.
+//
+// The instructions from an address up to the next branch are decoded once into a block of
+// resolved handlers, which is cached by its start address. Blocks also end after DXYN, so
+// the vblank quirk can end the frame, and after FX33/FX55. Their writes invalidate every
+// cached block that covers the written addresses, so self-modifying code is decoded again.
+use crate::cpu::{Cpu, OpcodeFunction};
+use crate::instruction::Instruction;
+
+const MEMORY: usize = 4096;
+// the longest block, which keeps partly executed blocks cheap
+const MAX_BLOCK: usize = 32;
+
+#[derive(Debug, Clone)]
+struct Block {
+    instructions: Vec<(OpcodeFunction, u16)>,
+    // the address after the last instruction
+    end: usize,
+}
+
+impl Block {
+    fn decode(memory: &[u8; MEMORY], start: usize) -> Block {
+        let mut instructions = Vec::new();
+        let mut address = start;
+        while instructions.len() < MAX_BLOCK && address + 1 < MEMORY {
+            let opcode = u16::from_be_bytes([memory[address], memory[address + 1]]);
+            instructions.push((Cpu::handler(opcode), opcode));
+            address += 2;
+            if ends_block(Instruction::decode(opcode)) {
+                break;
+            }
+        }
+        if instructions.is_empty() {
+            // the last byte of the memory, the opcode wraps around
+            let opcode = u16::from_be_bytes([memory[address], memory[0]]);
+            instructions.push((Cpu::handler(opcode), opcode));
+            address += 2;
+        }
+        Block {
+            instructions,
+            end: address,
+        }
+    }
+}
+
+// instructions after which the next address isn't known in advance, or which can change the
+// code or end the frame
+fn ends_block(instruction: Instruction) -> bool {
+    use Instruction::*;
+    instruction.is_skip()
+        || matches!(
+            instruction,
+            Return | Jump(_) | Call(_) | JumpOffset(_) | WaitKey(_) | Draw(..) | Bcd(_) | Store(_)
+        )
+}
+
+// the addresses written by an opcode at I
+fn writes(opcode: u16) -> usize {
+    match opcode & 0xF0FF {
+        0xF033 => 3,
+        0xF055 => ((opcode >> 8) & 0xF) as usize + 1,
+        _ => 0,
+    }
+}
+
+pub struct BlockCache {
+    blocks: Vec<Option<Block>>,
+    // the number of cached blocks that contain each address
+    code: Vec<u16>,
+    len: usize,
+}
+
+impl Default for BlockCache {
+    fn default() -> BlockCache {
+        BlockCache {
+            blocks: vec![None; MEMORY],
+            code: vec![0; MEMORY],
+            len: 0,
+        }
+    }
+}
+
+impl BlockCache {
+    // executes up to max instructions and returns how many were executed. With the vblank
+    // quirk a draw ends the run, like in Chip8::run_frame.
+    pub fn run(&mut self, cpu: &mut Cpu, max: u32) -> u32 {
+        let mut executed = 0;
+        while executed < max {
+            let start = cpu.pc as usize;
+            if self.blocks[start].is_none() {
+                self.insert(start, Block::decode(&cpu.memory, start));
+            }
+            let block = self.blocks[start].as_ref().unwrap();
+
+            let count = block.instructions.len().min((max - executed) as usize);
+            let (mut last_i, mut last) = (cpu.i, 0);
+            for &(handler, opcode) in &block.instructions[..count] {
+                (last_i, last) = (cpu.i, opcode);
+                cpu.pc += 2;
+                handler(cpu, opcode);
+            }
+            cpu.pc &= 0xFFF;
+            executed += count as u32;
+
+            // only the last instruction of a block writes to the memory
+            let written = writes(last);
+            if written > 0 {
+                self.invalidate(last_i as usize, written);
+            }
+            if cpu.quirks.vblank && last & 0xF000 == 0xD000 {
+                break;
+            }
+        }
+        executed
+    }
+
+    fn insert(&mut self, start: usize, block: Block) {
+        for address in start..block.end {
+            self.code[address % MEMORY] += 1;
+        }
+        self.blocks[start] = Some(block);
+        self.len += 1;
+    }
+
+    // removes the blocks that contain any of the len addresses from start, to be called
+    // whenever the memory changes outside of run()
+    pub fn invalidate(&mut self, start: usize, len: usize) {
+        let hit = (start..start + len).any(|address| self.code[address % MEMORY] > 0);
+        if !hit {
+            return;
+        }
+        // blocks at the end of the memory can wrap around
+        let overlaps = |address: usize, block: &Block| {
+            (start..start + len).any(|written| {
+                let written = written % MEMORY;
+                let range = address..block.end;
+                range.contains(&written) || range.contains(&(written + MEMORY))
+            })
+        };
+        for address in 0..MEMORY {
+            if let Some(block) = &self.blocks[address] {
+                if overlaps(address, block) {
+                    let block = self.blocks[address].take().unwrap();
+                    for a in address..block.end {
+                        self.code[a % MEMORY] -= 1;
+                    }
+                    self.len -= 1;
+                }
+            }
+        }
+    }
+
+    pub fn clear(&mut self) {
+        if self.len > 0 {
+            *self = BlockCache::default();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cpu(program: &[u8]) -> Cpu {
+        let mut cpu = Cpu::new();
+        cpu.load_font();
+        cpu.memory[0x200..0x200 + program.len()].copy_from_slice(program);
+        cpu
+    }
+
+    fn assert_same(a: &Cpu, b: &Cpu) {
+        assert_eq!(a.pc, b.pc);
+        assert_eq!(a.i, b.i);
+        assert_eq!(a.v, b.v);
+        assert_eq!(a.sp, b.sp);
+        assert_eq!(a.stack, b.stack);
+        assert_eq!(a.memory, b.memory);
+        assert_eq!(a.graphics, b.graphics);
+    }
+
+    // runs the program on the interpreter and the cache in slices of different sizes
+    fn compare(program: &[u8], slices: &[u32]) {
+        let mut interpreted = cpu(program);
+        let mut cached = cpu(program);
+        let mut cache = BlockCache::default();
+        for &slice in slices {
+            for _ in 0..slice {
+                interpreted.step();
+            }
+            assert_eq!(cache.run(&mut cached, slice), slice);
+            assert_same(&interpreted, &cached);
+        }
+    }
+
+    #[test]
+    fn loops_and_calls() {
+        compare(
+            &[
+                0x60, 0x00, // 0x200 ld v0, 0
+                0x22, 0x0C, // 0x202 call 0x20C
+                0x30, 0x20, // 0x204 se v0, 32
+                0x12, 0x02, // 0x206 jp 0x202
+                0x12, 0x08, // 0x208 jp 0x208
+                0x00, 0x00, // 0x20A
+                0x70, 0x01, // 0x20C add v0, 1
+                0xF0, 0x29, // 0x20E ld f, v0
+                0xD0, 0x05, // 0x210 drw v0, v0, 5
+                0x00, 0xEE, // 0x212 ret
+            ],
+            &[1, 3, 7, 10, 100, 1000],
+        );
+    }
+
+    #[test]
+    fn register_skips_with_low_nibble() {
+        // 5XY1 and 9XY1 aren't skips, so neither engine skips the add after them
+        compare(
+            &[
+                0x60, 0x01, // 0x200 ld v0, 1
+                0x61, 0x01, // 0x202 ld v1, 1
+                0x50, 0x11, // 0x204 5011
+                0x72, 0x01, // 0x206 add v2, 1
+                0x90, 0x21, // 0x208 9021
+                0x73, 0x01, // 0x20A add v3, 1
+                0x12, 0x0C, // 0x20C jp 0x20C
+            ],
+            &[1, 3, 10],
+        );
+    }
+
+    #[test]
+    fn self_modifying_code() {
+        // the loop rewrites its own add v2, N with an increasing N
+        compare(
+            &[
+                0x60, 0x72, // 0x200 ld v0, 0x72
+                0x61, 0x00, // 0x202 ld v1, 0
+                0xA2, 0x0C, // 0x204 ld i, 0x20C
+                0xF1, 0x55, // 0x206 ld [i], v1 rewrites 0x20C
+                0x71, 0x01, // 0x208 add v1, 1
+                0x12, 0x0C, // 0x20A jp 0x20C
+                0x72, 0x00, // 0x20C add v2, N
+                0x12, 0x04, // 0x20E jp 0x204
+            ],
+            &[2, 5, 50, 500],
+        );
+    }
+
+    #[test]
+    fn draw_ends_the_run_with_vblank() {
+        let program = [
+            0xD0, 0x05, // 0x200 drw v0, v0, 5
+            0x70, 0x01, // 0x202 add v0, 1
+            0x12, 0x00, // 0x204 jp 0x200
+        ];
+        let mut cpu = cpu(&program);
+        cpu.quirks.vblank = true;
+        let mut cache = BlockCache::default();
+        assert_eq!(cache.run(&mut cpu, 100), 1);
+        assert_eq!(cache.run(&mut cpu, 100), 3);
+
+        // writes from outside have to be invalidated by hand
+        cpu.memory[0x202] = 0x71;
+        cache.invalidate(0x202, 1);
+        cache.run(&mut cpu, 100);
+        assert_eq!(cpu.v[1], 1);
+    }
+}
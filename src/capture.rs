@@ -0,0 +1,320 @@
+// Screenshots and recordings of the framebuffer, drawn at the configured scale and palette.
+use crate::chip8::{COL, ROW};
+use crate::options::{rgb, Palette, MAX_SCALE};
+use std::borrow::Cow;
+use std::env;
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::PathBuf;
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+pub fn save_png(
+    path: &str,
+    map: &[u8; ROW * COL],
+    scale: u32,
+    palette: &Palette,
+) -> Result<(), Box<dyn Error>> {
+    let mut encoder = png_encoder(path, scale)?;
+    encoder.set_color(png::ColorType::Rgb);
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(&render_rgb(map, scale, palette))?;
+    writer.finish()?;
+    Ok(())
+}
+
+// a frame shown longer is split, so its delay in 1/100s fits in the 16 bits of a GIF
+const MAX_REPEATS: u32 = (u16::MAX as u32 - 1) * 60 / 100;
+
+// a framebuffer and the number of 60Hz frames it was shown
+type Frame = ([u8; ROW * COL], u32);
+
+enum Output {
+    Gif {
+        encoder: gif::Encoder<BufWriter<File>>,
+        // 60Hz frames written so far
+        elapsed: u32,
+    },
+    Apng(Spool),
+}
+
+// Writes the frames to an animated GIF or APNG, depending on the extension of the path, until
+// finish() is called. Repeated frames are merged into a longer delay.
+pub struct Recorder {
+    path: String,
+    scale: u32,
+    palette: Palette,
+    output: Output,
+    // the frame shown at the moment, counting up while it doesn't change
+    current: Option<Frame>,
+}
+
+impl Recorder {
+    pub fn new(path: &str, scale: u32, palette: Palette) -> Result<Recorder, Box<dyn Error>> {
+        let lower = path.to_lowercase();
+        let output = if lower.ends_with(".gif") {
+            if scale > MAX_SCALE {
+                return Err(format!(
+                    "Can't record a GIF at scale {}, at most {}",
+                    scale, MAX_SCALE
+                )
+                .into());
+            }
+            let width = COL as u16 * scale as u16;
+            let height = ROW as u16 * scale as u16;
+            let mut colors = Vec::new();
+            for color in [palette.background, palette.fill] {
+                let (r, g, b) = rgb(color);
+                colors.extend_from_slice(&[r, g, b]);
+            }
+            let file = BufWriter::new(File::create(path)?);
+            let mut encoder = gif::Encoder::new(file, width, height, &colors)?;
+            encoder.set_repeat(gif::Repeat::Infinite)?;
+            Output::Gif {
+                encoder,
+                elapsed: 0,
+            }
+        } else if lower.ends_with(".png") || lower.ends_with(".apng") {
+            // fail before recording if the file can't be written
+            File::create(path)?;
+            Output::Apng(Spool::new()?)
+        } else {
+            return Err(format!("Can't record to '{}', use a .gif or .png file", path).into());
+        };
+
+        Ok(Recorder {
+            path: path.to_string(),
+            scale,
+            palette,
+            output,
+            current: None,
+        })
+    }
+
+    pub fn add_frame(&mut self, map: &[u8; ROW * COL]) -> Result<(), Box<dyn Error>> {
+        match &mut self.current {
+            Some((current, count)) if current == map && *count < MAX_REPEATS => *count += 1,
+            current => {
+                if let Some((previous, count)) = current.replace((*map, 1)) {
+                    self.write(&previous, count)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn write(&mut self, map: &[u8; ROW * COL], count: u32) -> Result<(), Box<dyn Error>> {
+        match &mut self.output {
+            Output::Gif { encoder, elapsed } => {
+                // round the running total so the timing doesn't drift
+                let start = *elapsed * 100 / 60;
+                *elapsed += count;
+                let frame = gif::Frame {
+                    width: (COL as u32 * self.scale) as u16,
+                    height: (ROW as u32 * self.scale) as u16,
+                    delay: (*elapsed * 100 / 60 - start) as u16,
+                    buffer: Cow::Owned(render_indexed(map, self.scale)),
+                    ..gif::Frame::default()
+                };
+                encoder.write_frame(&frame)?;
+            }
+            Output::Apng(spool) => spool.push(map, count)?,
+        }
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> Result<(), Box<dyn Error>> {
+        let (map, count) = self.current.take().ok_or("Nothing was recorded")?;
+        self.write(&map, count)?;
+        match self.output {
+            Output::Gif { encoder, .. } => {
+                encoder.into_inner()?.flush()?;
+            }
+            Output::Apng(mut spool) => {
+                let mut encoder = png_encoder(&self.path, self.scale)?;
+                encoder.set_color(png::ColorType::Rgb);
+                encoder.set_animated(spool.frames, 0)?;
+                let mut writer = encoder.write_header()?;
+                for frame in spool.read()? {
+                    let (map, count) = frame?;
+                    writer.set_frame_delay(count as u16, 60)?;
+                    writer.write_image_data(&render_rgb(&map, self.scale, &self.palette))?;
+                }
+                writer.finish()?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// APNG needs the number of frames before the first one, so the frames wait in a temporary
+// file with one bit per pixel until the recording is finished
+struct Spool {
+    path: PathBuf,
+    file: BufWriter<File>,
+    frames: u32,
+}
+
+impl Spool {
+    fn new() -> Result<Spool, Box<dyn Error>> {
+        static SPOOLS: AtomicUsize = AtomicUsize::new(0);
+        let name = format!(
+            "rc8-record-{}-{}.tmp",
+            process::id(),
+            SPOOLS.fetch_add(1, Ordering::Relaxed)
+        );
+        let path = env::temp_dir().join(name);
+        let file = BufWriter::new(File::create(&path)?);
+        Ok(Spool {
+            path,
+            file,
+            frames: 0,
+        })
+    }
+
+    fn push(&mut self, map: &[u8; ROW * COL], count: u32) -> io::Result<()> {
+        let mut bits = [0; ROW * COL / 8];
+        for (index, &pixel) in map.iter().enumerate() {
+            bits[index / 8] |= ((pixel != 0) as u8) << (7 - index % 8);
+        }
+        self.file.write_all(&count.to_le_bytes())?;
+        self.file.write_all(&bits)?;
+        self.frames += 1;
+        Ok(())
+    }
+
+    fn read(&mut self) -> io::Result<impl Iterator<Item = io::Result<Frame>>> {
+        self.file.flush()?;
+        let mut reader = BufReader::new(File::open(&self.path)?);
+        Ok((0..self.frames).map(move |_| {
+            let mut count = [0; 4];
+            let mut bits = [0; ROW * COL / 8];
+            reader.read_exact(&mut count)?;
+            reader.read_exact(&mut bits)?;
+            let mut map = [0; ROW * COL];
+            for (index, pixel) in map.iter_mut().enumerate() {
+                *pixel = (bits[index / 8] >> (7 - index % 8)) & 1;
+            }
+            Ok((map, u32::from_le_bytes(count)))
+        }))
+    }
+}
+
+impl Drop for Spool {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+fn png_encoder(
+    path: &str,
+    scale: u32,
+) -> Result<png::Encoder<'static, BufWriter<File>>, Box<dyn Error>> {
+    let file = BufWriter::new(File::create(path)?);
+    let mut encoder = png::Encoder::new(file, COL as u32 * scale, ROW as u32 * scale);
+    encoder.set_depth(png::BitDepth::Eight);
+    Ok(encoder)
+}
+
+// one byte per pixel, 0 for the background and 1 for the fill color
+fn render_indexed(map: &[u8; ROW * COL], scale: u32) -> Vec<u8> {
+    let scale = scale as usize;
+    let mut pixels = Vec::with_capacity(ROW * COL * scale * scale);
+    for row in map.chunks(COL) {
+        for _ in 0..scale {
+            for &value in row {
+                pixels.extend(std::iter::repeat_n((value != 0) as u8, scale));
+            }
+        }
+    }
+    pixels
+}
+
+fn render_rgb(map: &[u8; ROW * COL], scale: u32, palette: &Palette) -> Vec<u8> {
+    let colors = [rgb(palette.background), rgb(palette.fill)];
+    render_indexed(map, scale)
+        .into_iter()
+        .flat_map(|index| {
+            let (r, g, b) = colors[index as usize];
+            [r, g, b]
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(extension: &str) -> String {
+        let path = env::temp_dir().join(format!("rc8-capture-{}.{}", process::id(), extension));
+        let path = path.to_str().unwrap().to_string();
+        let mut recorder = Recorder::new(&path, 1, Palette::default()).unwrap();
+        let mut map = [0; ROW * COL];
+        recorder.add_frame(&map).unwrap();
+        recorder.add_frame(&map).unwrap();
+        map[5] = 1;
+        recorder.add_frame(&map).unwrap();
+        recorder.finish().unwrap();
+        path
+    }
+
+    #[test]
+    fn gif_merges_repeated_frames() {
+        let path = record("gif");
+        let mut decoder = gif::DecodeOptions::new();
+        decoder.set_color_output(gif::ColorOutput::Indexed);
+        let mut decoder = decoder.read_info(File::open(&path).unwrap()).unwrap();
+        let mut frames = Vec::new();
+        while let Some(frame) = decoder.read_next_frame().unwrap() {
+            frames.push((frame.delay, frame.buffer[5]));
+        }
+        fs::remove_file(&path).unwrap();
+        // 2/60s and 1/60s in 1/100s, rounded so the total stays 5
+        assert_eq!(frames, [(3, 0), (2, 1)]);
+    }
+
+    #[test]
+    fn apng_merges_repeated_frames() {
+        let path = record("png");
+        let decoder = png::Decoder::new(BufReader::new(File::open(&path).unwrap()));
+        let reader = decoder.read_info().unwrap();
+        let animation = reader.info().animation_control.unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(animation.num_frames, 2);
+    }
+
+    #[test]
+    fn spool_round_trip() {
+        let mut spool = Spool::new().unwrap();
+        let mut map = [0; ROW * COL];
+        map[0] = 1;
+        map[ROW * COL - 1] = 1;
+        spool.push(&map, 7).unwrap();
+        let frames: Vec<Frame> = spool.read().unwrap().map(Result::unwrap).collect();
+        assert_eq!(frames, [(map, 7)]);
+        let path = spool.path.clone();
+        drop(spool);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn scales_pixels() {
+        let mut map = [0; ROW * COL];
+        map[1] = 1;
+        let pixels = render_indexed(&map, 2);
+        assert_eq!(pixels.len(), ROW * COL * 4);
+        assert_eq!(&pixels[..6], &[0, 0, 1, 1, 0, 0]);
+        assert_eq!(&pixels[COL * 2..COL * 2 + 6], &[0, 0, 1, 1, 0, 0]);
+    }
+
+    #[test]
+    fn rejects_unknown_format_and_large_gifs() {
+        assert!(Recorder::new("out.mp4", 1, Palette::default()).is_err());
+        let error = Recorder::new("out.gif", 1100, Palette::default()).err();
+        assert_eq!(
+            error.unwrap().to_string(),
+            "Can't record a GIF at scale 1100, at most 1023"
+        );
+    }
+}
@@ -0,0 +1,307 @@
+// Octo cartridges are GIF images with the program and its options hidden in the pixel data.
+//
+// Every frame is a 128x64 picture of a cartridge label drawn with 4 shades. The palette has
+// 16 entries, 4 (nearly identical) colors per shade, so the lower 2 bits of each palette index
+// are free to carry data. Reading the frames pixel by pixel yields 2 bits at a time, most
+// significant pair first. The decoded bytes start with a big-endian u32 length followed by a
+// JSON object of the form `{"options": {...}, "program": "<octo source>"}`.
+//
+// The program is assembled with octo.rs. Exported programs are byte listings, which Octo
+// assembles as they are.
+use crate::octo;
+use crate::options::{format_color, parse_color, rgb, Options};
+use serde_json::{json, Map, Value};
+use std::borrow::Cow;
+use std::error::Error;
+use std::fs;
+
+const WIDTH: u16 = 128;
+const HEIGHT: u16 = 64;
+const BYTES_PER_FRAME: usize = WIDTH as usize * HEIGHT as usize / 4;
+// Octo's fastest speed is 1000 instructions per frame, anything far above it would stall
+const MAX_TICKRATE: u64 = 10_000;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cartridge {
+    pub rom: Vec<u8>,
+    pub options: Options,
+}
+
+pub fn is_cartridge(data: &[u8]) -> bool {
+    data.starts_with(b"GIF87a") || data.starts_with(b"GIF89a")
+}
+
+impl Cartridge {
+    pub fn new(rom: &[u8], options: Options) -> Cartridge {
+        Cartridge {
+            rom: rom.to_vec(),
+            options,
+        }
+    }
+
+    pub fn load(path: &str, defaults: Options) -> Result<Cartridge, Box<dyn Error>> {
+        Cartridge::decode(&fs::read(path)?, defaults)
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), Box<dyn Error>> {
+        fs::write(path, self.encode()?)?;
+        Ok(())
+    }
+
+    // options that are missing in the cartridge keep the values of defaults
+    pub fn decode(data: &[u8], defaults: Options) -> Result<Cartridge, Box<dyn Error>> {
+        let payload = read_payload(data)?;
+        let json: Value = serde_json::from_slice(&payload)?;
+
+        let program = json["program"]
+            .as_str()
+            .ok_or("Cartridge does not contain a program")?;
+        let mut options = defaults;
+        if let Some(map) = json["options"].as_object() {
+            apply_octo_options(&mut options, map)?;
+        }
+
+        Ok(Cartridge {
+            rom: octo::assemble(program)?,
+            options,
+        })
+    }
+
+    pub fn encode(&self) -> Result<Vec<u8>, Box<dyn Error>> {
+        let json = json!({
+            "options": octo_options(&self.options),
+            "program": disassemble_bytes(&self.rom),
+        });
+        let text = serde_json::to_vec(&json)?;
+
+        let mut payload = (text.len() as u32).to_be_bytes().to_vec();
+        payload.extend_from_slice(&text);
+        write_payload(&payload, &self.options)
+    }
+}
+
+fn read_payload(data: &[u8]) -> Result<Vec<u8>, Box<dyn Error>> {
+    let mut decoder = gif::DecodeOptions::new();
+    decoder.set_color_output(gif::ColorOutput::Indexed);
+    let mut decoder = decoder.read_info(data)?;
+
+    let mut pairs: Vec<u8> = Vec::new();
+    while let Some(frame) = decoder.read_next_frame()? {
+        pairs.extend(frame.buffer.iter().map(|&index| index & 0x3));
+    }
+
+    let bytes: Vec<u8> = pairs
+        .chunks_exact(4)
+        .map(|p| (p[0] << 6) | (p[1] << 4) | (p[2] << 2) | p[3])
+        .collect();
+    if bytes.len() < 4 {
+        return Err("Cartridge is too small".into());
+    }
+
+    let length = u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as usize;
+    if length > bytes.len() - 4 {
+        return Err("Cartridge payload is truncated".into());
+    }
+    Ok(bytes[4..4 + length].to_vec())
+}
+
+fn write_payload(payload: &[u8], options: &Options) -> Result<Vec<u8>, Box<dyn Error>> {
+    let shades = [
+        options.palette.background,
+        options.palette.blend,
+        options.palette.fill,
+        options.palette.fill2,
+    ];
+    let mut palette = Vec::with_capacity(16 * 3);
+    for shade in shades {
+        for bits in 0..4 {
+            let (r, g, b) = rgb(shade ^ bits);
+            palette.extend_from_slice(&[r, g, b]);
+        }
+    }
+
+    let label = draw_label();
+    let mut out = Vec::new();
+    {
+        let mut encoder = gif::Encoder::new(&mut out, WIDTH, HEIGHT, &palette)?;
+        for chunk in payload.chunks(BYTES_PER_FRAME) {
+            let mut buffer = label.clone();
+            let pairs = chunk
+                .iter()
+                .flat_map(|&b| [b >> 6, (b >> 4) & 0x3, (b >> 2) & 0x3, b & 0x3]);
+            for (pixel, bits) in buffer.iter_mut().zip(pairs) {
+                *pixel |= bits;
+            }
+
+            let frame = gif::Frame {
+                width: WIDTH,
+                height: HEIGHT,
+                buffer: Cow::Owned(buffer),
+                ..gif::Frame::default()
+            };
+            encoder.write_frame(&frame)?;
+        }
+    }
+    Ok(out)
+}
+
+// palette indices of the label picture, with the lower 2 bits left empty
+fn draw_label() -> Vec<u8> {
+    let (w, h) = (WIDTH as usize, HEIGHT as usize);
+    let mut label = vec![0; w * h];
+    for y in 0..h {
+        for x in 0..w {
+            let shade = if !(4..w - 4).contains(&x) || !(4..h - 4).contains(&y) {
+                0
+            } else if (12..w - 12).contains(&x) && (10..h - 20).contains(&y) {
+                2
+            } else if (12..w - 12).contains(&x) && y >= h - 14 && y % 3 == 0 {
+                3
+            } else {
+                1
+            };
+            label[x + y * w] = shade << 2;
+        }
+    }
+    label
+}
+
+fn apply_octo_options(
+    options: &mut Options,
+    map: &Map<String, Value>,
+) -> Result<(), Box<dyn Error>> {
+    if let Some(tickrate) = map.get("tickrate").and_then(Value::as_u64) {
+        if !(1..=MAX_TICKRATE).contains(&tickrate) {
+            return Err(format!(
+                "Cartridge tickrate {} is not between 1 and {}",
+                tickrate, MAX_TICKRATE
+            )
+            .into());
+        }
+        options.tickrate = tickrate as u32;
+    }
+
+    let palette = &mut options.palette;
+    let colors = [
+        ("backgroundColor", &mut palette.background),
+        ("fillColor", &mut palette.fill),
+        ("fillColor2", &mut palette.fill2),
+        ("blendColor", &mut palette.blend),
+        ("buzzColor", &mut palette.buzz),
+        ("quietColor", &mut palette.quiet),
+    ];
+    for (key, color) in colors {
+        if let Some(text) = map.get(key).and_then(Value::as_str) {
+            *color = parse_color(text)?;
+        }
+    }
+
+    let quirks = &mut options.quirks;
+    let flags = [
+        ("shiftQuirks", &mut quirks.shift),
+        ("loadStoreQuirks", &mut quirks.load_store),
+        ("vfOrderQuirks", &mut quirks.vf_order),
+        ("clipQuirks", &mut quirks.clip),
+        ("vBlankQuirks", &mut quirks.vblank),
+        ("jumpQuirks", &mut quirks.jump),
+        ("logicQuirks", &mut quirks.logic),
+    ];
+    for (key, flag) in flags {
+        if let Some(value) = map.get(key).and_then(Value::as_bool) {
+            *flag = value;
+        }
+    }
+    Ok(())
+}
+
+fn octo_options(options: &Options) -> Value {
+    let palette = &options.palette;
+    let quirks = &options.quirks;
+    json!({
+        "tickrate": options.tickrate,
+        "fillColor": format_color(palette.fill),
+        "fillColor2": format_color(palette.fill2),
+        "blendColor": format_color(palette.blend),
+        "backgroundColor": format_color(palette.background),
+        "buzzColor": format_color(palette.buzz),
+        "quietColor": format_color(palette.quiet),
+        "shiftQuirks": quirks.shift,
+        "loadStoreQuirks": quirks.load_store,
+        "vfOrderQuirks": quirks.vf_order,
+        "clipQuirks": quirks.clip,
+        "vBlankQuirks": quirks.vblank,
+        "jumpQuirks": quirks.jump,
+        "logicQuirks": quirks.logic,
+        "screenRotation": 0,
+        "maxSize": 3584,
+        "touchInputMode": "none",
+        "fontStyle": "octo",
+    })
+}
+
+// Octo emits bare numbers as bytes, so a ROM can be expressed as a byte listing
+fn disassemble_bytes(rom: &[u8]) -> String {
+    let mut source = String::from("# exported by rc8\n: main\n");
+    for line in rom.chunks(16) {
+        let bytes: Vec<String> = line.iter().map(|b| format!("0x{:02X}", b)).collect();
+        source.push_str(&bytes.join(" "));
+        source.push('\n');
+    }
+    source
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn roundtrip() {
+        let mut options = Options {
+            tickrate: 500,
+            ..Options::default()
+        };
+        options.quirks.shift = true;
+        options.quirks.clip = true;
+        options.palette.fill = 0xFFCC00;
+
+        let rom: Vec<u8> = (0..3000).map(|i| (i * 7) as u8).collect();
+        let cartridge = Cartridge::new(&rom, options);
+        let data = cartridge.encode().unwrap();
+
+        assert!(is_cartridge(&data));
+        assert_eq!(
+            Cartridge::decode(&data, Options::default()).unwrap(),
+            cartridge
+        );
+    }
+
+    #[test]
+    fn reject_bad_tickrate() {
+        for tickrate in [0, MAX_TICKRATE + 1, u64::MAX] {
+            let map = json!({ "tickrate": tickrate });
+            let mut options = Options::default();
+            assert!(apply_octo_options(&mut options, map.as_object().unwrap()).is_err());
+            assert_eq!(options.tickrate, Options::default().tickrate);
+        }
+    }
+
+    fn with_program(program: &str) -> Vec<u8> {
+        let text = serde_json::to_vec(&json!({ "options": {}, "program": program })).unwrap();
+        let mut payload = (text.len() as u32).to_be_bytes().to_vec();
+        payload.extend_from_slice(&text);
+        write_payload(&payload, &Options::default()).unwrap()
+    }
+
+    #[test]
+    fn assemble_octo_source() {
+        let program = "# draws a sprite\n: main\n  i := box\n  sprite v0 v0 1\n: box 0xFF\n";
+        let cartridge = Cartridge::decode(&with_program(program), Options::default()).unwrap();
+        assert_eq!(cartridge.rom, [0xA2, 0x04, 0xD0, 0x01, 0xFF]);
+
+        let error = Cartridge::decode(&with_program(": main\n  jump nowhere"), Options::default());
+        assert_eq!(
+            error.unwrap_err().to_string(),
+            "line 2: undefined label 'nowhere'"
+        );
+    }
+}
@@ -0,0 +1,228 @@
+// Control-flow graph of a ROM, built by following every path from the entry point.
+//
+// Blocks end at jumps, calls, returns and skips, and before every address another
+// instruction branches to. BNNN jumps to an address computed at runtime, so its block is
+// flagged as indirect and has no successors. Code that is only reached through BNNN is
+// missing from the graph.
+use crate::instruction::Instruction;
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EdgeKind {
+    // the next instruction
+    Next,
+    Jump,
+    Call,
+    // the instruction after a call, where the subroutine returns to
+    Return,
+    // the next instruction is skipped
+    Skip,
+}
+
+impl EdgeKind {
+    fn name(self) -> &'static str {
+        match self {
+            EdgeKind::Next => "next",
+            EdgeKind::Jump => "jump",
+            EdgeKind::Call => "call",
+            EdgeKind::Return => "return",
+            EdgeKind::Skip => "skip",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Block {
+    pub start: u16,
+    pub instructions: Vec<(u16, Instruction)>,
+    pub successors: Vec<(u16, EdgeKind)>,
+    // ends with BNNN
+    pub indirect: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct Cfg {
+    pub entry: u16,
+    pub blocks: BTreeMap<u16, Block>,
+}
+
+// the successors of a single instruction
+fn successors(address: u16, instruction: Instruction) -> Vec<(u16, EdgeKind)> {
+    let next = address + 2;
+    match instruction {
+        Instruction::Jump(target) => vec![(target, EdgeKind::Jump)],
+        Instruction::Call(target) => vec![(target, EdgeKind::Call), (next, EdgeKind::Return)],
+        Instruction::Return | Instruction::JumpOffset(_) | Instruction::Unknown(_) => vec![],
+        instruction if instruction.is_skip() => {
+            vec![(next, EdgeKind::Next), (next + 2, EdgeKind::Skip)]
+        }
+        _ => vec![(next, EdgeKind::Next)],
+    }
+}
+
+impl Cfg {
+    pub fn build(memory: &[u8], entry: u16) -> Cfg {
+        let decode = |address: u16| {
+            let address = address as usize;
+            Instruction::decode(u16::from_be_bytes([memory[address], memory[address + 1]]))
+        };
+        let valid = |address: u16| (address as usize) + 1 < memory.len();
+
+        // every reachable instruction, and the addresses that start a block
+        let mut reachable = BTreeSet::new();
+        let mut leaders = BTreeSet::from([entry]);
+        let mut pending = vec![entry];
+        while let Some(address) = pending.pop() {
+            if !valid(address) || !reachable.insert(address) {
+                continue;
+            }
+            let edges = successors(address, decode(address));
+            let branches = edges.len() != 1 || edges[0].1 != EdgeKind::Next;
+            for (target, _) in edges {
+                if branches {
+                    leaders.insert(target);
+                }
+                pending.push(target);
+            }
+        }
+
+        let mut blocks = BTreeMap::new();
+        for &start in leaders.iter().filter(|&&a| reachable.contains(&a)) {
+            let mut block = Block {
+                start,
+                instructions: Vec::new(),
+                successors: Vec::new(),
+                indirect: false,
+            };
+            let mut address = start;
+            loop {
+                let instruction = decode(address);
+                block.instructions.push((address, instruction));
+                let edges = successors(address, instruction);
+                let next = address + 2;
+                let falls_through = edges == [(next, EdgeKind::Next)];
+                if !falls_through || leaders.contains(&next) || !reachable.contains(&next) {
+                    block.indirect = matches!(instruction, Instruction::JumpOffset(_));
+                    block.successors = edges.into_iter().filter(|&(a, _)| valid(a)).collect();
+                    break;
+                }
+                address = next;
+            }
+            blocks.insert(start, block);
+        }
+        Cfg { entry, blocks }
+    }
+
+    pub fn to_dot(&self) -> String {
+        let mut out = String::from(
+            "digraph cfg {\n    node [shape=box, fontname=\"monospace\"];\n    \
+             edge [fontname=\"monospace\"];\n",
+        );
+        for block in self.blocks.values() {
+            let mut label = String::new();
+            for (address, instruction) in &block.instructions {
+                write!(label, "{:#05X}  {}\\l", address, instruction).unwrap();
+            }
+            let style = if block.indirect {
+                ", color=red, xlabel=\"indirect\""
+            } else if block.start == self.entry {
+                ", style=bold"
+            } else {
+                ""
+            };
+            writeln!(
+                out,
+                "    \"{:#05X}\" [label=\"{}\"{}];",
+                block.start, label, style
+            )
+            .unwrap();
+        }
+        for block in self.blocks.values() {
+            for &(target, kind) in &block.successors {
+                let style = match kind {
+                    EdgeKind::Next => "",
+                    EdgeKind::Jump => " [label=\"jump\"]",
+                    EdgeKind::Call => " [label=\"call\", style=dashed]",
+                    EdgeKind::Return => " [label=\"return\", style=dotted]",
+                    EdgeKind::Skip => " [label=\"skip\"]",
+                };
+                writeln!(
+                    out,
+                    "    \"{:#05X}\" -> \"{:#05X}\"{};",
+                    block.start, target, style
+                )
+                .unwrap();
+            }
+        }
+        out += "}\n";
+        out
+    }
+
+    pub fn to_json(&self) -> Value {
+        let blocks: Vec<Value> = self
+            .blocks
+            .values()
+            .map(|block| {
+                json!({
+                    "start": block.start,
+                    "indirect": block.indirect,
+                    "instructions": block.instructions.iter().map(|(address, instruction)| {
+                        json!({"address": address, "text": instruction.to_string()})
+                    }).collect::<Vec<_>>(),
+                    "successors": block.successors.iter().map(|(target, kind)| {
+                        json!({"target": target, "kind": kind.name()})
+                    }).collect::<Vec<_>>(),
+                })
+            })
+            .collect();
+        json!({"entry": self.entry, "blocks": blocks})
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn build(program: &[u8]) -> Cfg {
+        let mut memory = [0; 4096];
+        memory[0x200..0x200 + program.len()].copy_from_slice(program);
+        Cfg::build(&memory, 0x200)
+    }
+
+    #[test]
+    fn blocks_and_edges() {
+        let cfg = build(&[
+            0x60, 0x00, // 0x200 ld v0, 0
+            0x22, 0x0C, // 0x202 call 0x20C
+            0x30, 0x05, // 0x204 se v0, 5
+            0x12, 0x02, // 0x206 jp 0x202
+            0xB3, 0x00, // 0x208 jp v0, 0x300
+            0x00, 0x00, // 0x20A never reached
+            0x70, 0x01, // 0x20C add v0, 1
+            0x00, 0xEE, // 0x20E ret
+        ]);
+
+        let starts: Vec<u16> = cfg.blocks.keys().copied().collect();
+        assert_eq!(starts, [0x200, 0x202, 0x204, 0x206, 0x208, 0x20C]);
+        assert_eq!(cfg.blocks[&0x200].instructions.len(), 1);
+        assert_eq!(
+            cfg.blocks[&0x202].successors,
+            [(0x20C, EdgeKind::Call), (0x204, EdgeKind::Return)]
+        );
+        assert_eq!(
+            cfg.blocks[&0x204].successors,
+            [(0x206, EdgeKind::Next), (0x208, EdgeKind::Skip)]
+        );
+        assert!(cfg.blocks[&0x208].indirect);
+        assert!(cfg.blocks[&0x208].successors.is_empty());
+        assert_eq!(cfg.blocks[&0x20C].instructions.len(), 2);
+
+        let dot = cfg.to_dot();
+        assert!(dot.contains("\"0x204\" -> \"0x208\" [label=\"skip\"];"));
+        let json = cfg.to_json();
+        assert_eq!(json["blocks"][0]["successors"][0]["target"], 0x202);
+        assert_eq!(json["blocks"][1]["successors"][0]["kind"], "call");
+    }
+}
@@ -0,0 +1,404 @@
+// Memory search and cheats.
+//
+// A search starts with every address of the memory as a candidate. Each following step
+// compares the memory with the snapshot of the previous step and keeps the addresses that
+// match the condition, e.g. "changed" after losing a life, until only the game variable is left.
+// The snapshots are taken from the memory at the end of the last frame, so a debugger halting
+// in the middle of one doesn't matter. Cheats freeze an address to a value, they are written
+// into the memory every frame.
+//
+// Both the monitor command of the debugger and the remote control search and freeze.
+//
+// The cheats of a ROM are saved in $XDG_CONFIG_HOME/rc8/cheats/<sha1 of the ROM>.toml:
+//
+//     [[cheat]]
+//     name = "lives"
+//     address = 752
+//     value = 3
+use crate::config;
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Condition {
+    Changed,
+    Unchanged,
+    Increased,
+    Decreased,
+    Equal(u8),
+}
+
+impl Condition {
+    pub fn parse(text: &str) -> Option<Condition> {
+        match text {
+            "changed" => Some(Condition::Changed),
+            "unchanged" => Some(Condition::Unchanged),
+            "increased" => Some(Condition::Increased),
+            "decreased" => Some(Condition::Decreased),
+            _ => parse_byte(text).map(Condition::Equal),
+        }
+    }
+
+    fn matches(self, old: u8, new: u8) -> bool {
+        match self {
+            Condition::Changed => old != new,
+            Condition::Unchanged => old == new,
+            Condition::Increased => new > old,
+            Condition::Decreased => new < old,
+            Condition::Equal(value) => new == value,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Search {
+    snapshot: Vec<u8>,
+    candidates: Vec<u16>,
+}
+
+impl Search {
+    pub fn new(memory: &[u8]) -> Search {
+        Search {
+            snapshot: memory.to_vec(),
+            candidates: (0..memory.len() as u16).collect(),
+        }
+    }
+
+    // keeps the candidates matching the condition and takes a new snapshot
+    pub fn filter(&mut self, memory: &[u8], condition: Condition) {
+        let snapshot = &self.snapshot;
+        self.candidates.retain(|&address| {
+            let address = address as usize;
+            condition.matches(snapshot[address], memory[address])
+        });
+        self.snapshot = memory.to_vec();
+    }
+
+    pub fn candidates(&self) -> &[u16] {
+        &self.candidates
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cheat {
+    pub name: String,
+    pub address: u16,
+    pub value: u8,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Cheats {
+    cheats: Vec<Cheat>,
+    search: Option<Search>,
+    // the memory at the end of the last frame, empty before the first one
+    frame: Vec<u8>,
+    // where save() writes the cheats to
+    path: Option<PathBuf>,
+}
+
+// the default location of the cheats of a ROM
+pub fn path(rom: &[u8]) -> Option<PathBuf> {
+    Some(
+        config::dir()?
+            .join("cheats")
+            .join(format!("{}.toml", config::rom_id(rom))),
+    )
+}
+
+impl Cheats {
+    // a missing file is the same as an empty one, save() will create it
+    pub fn load(path: &Path) -> Result<Cheats, Box<dyn Error>> {
+        let mut cheats = Cheats {
+            path: Some(path.to_path_buf()),
+            ..Cheats::default()
+        };
+        if path.exists() {
+            let text = fs::read_to_string(path)?;
+            cheats.cheats = parse(&text).map_err(|err| format!("{}: {}", path.display(), err))?;
+        }
+        Ok(cheats)
+    }
+
+    pub fn save(&self) -> Result<(), Box<dyn Error>> {
+        let path = self
+            .path
+            .as_ref()
+            .ok_or("There is no file for the cheats")?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(path, format(&self.cheats))?;
+        Ok(())
+    }
+
+    pub fn cheats(&self) -> &[Cheat] {
+        &self.cheats
+    }
+
+    // replaces an existing cheat for the same address
+    pub fn freeze(&mut self, cheat: Cheat) {
+        self.cheats.retain(|other| other.address != cheat.address);
+        self.cheats.push(cheat);
+    }
+
+    pub fn unfreeze(&mut self, address: u16) -> bool {
+        let len = self.cheats.len();
+        self.cheats.retain(|cheat| cheat.address != address);
+        self.cheats.len() != len
+    }
+
+    pub fn end_frame(&mut self, memory: &[u8]) {
+        self.frame.clear();
+        self.frame.extend_from_slice(memory);
+    }
+
+    // starts a new search without a condition or narrows the running one, returns the
+    // candidates with their values at the end of the last frame
+    pub fn search(&mut self, condition: Option<Condition>, memory: &[u8]) -> Vec<(u16, u8)> {
+        let memory = if self.frame.is_empty() {
+            memory
+        } else {
+            &self.frame
+        };
+        let search = match condition {
+            Some(condition) => {
+                let search = self.search.get_or_insert_with(|| Search::new(memory));
+                search.filter(memory, condition);
+                search
+            }
+            None => self.search.insert(Search::new(memory)),
+        };
+        search
+            .candidates()
+            .iter()
+            .map(|&address| (address, memory[address as usize]))
+            .collect()
+    }
+
+    // writes the frozen values, called every frame
+    pub fn apply(&self, memory: &mut [u8]) {
+        for cheat in &self.cheats {
+            if let Some(byte) = memory.get_mut(cheat.address as usize) {
+                *byte = cheat.value;
+            }
+        }
+    }
+
+    // Runs a command and returns its output, used by the monitor command of the debugger:
+    //
+    //     search                      start a new search
+    //     search CONDITION            changed, unchanged, increased, decreased or a value
+    //     freeze ADDRESS VALUE [NAME]
+    //     unfreeze ADDRESS
+    //     cheats                      list the frozen addresses
+    //     save                        save the cheats of the ROM
+    pub fn command(&mut self, line: &str, memory: &[u8]) -> Result<String, Box<dyn Error>> {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match words[..] {
+            ["search"] => {
+                let candidates = self.search(None, memory);
+                Ok(format!(
+                    "Started a search over {} addresses\n",
+                    candidates.len()
+                ))
+            }
+            ["search", condition] => {
+                let condition = Condition::parse(condition)
+                    .ok_or_else(|| format!("Invalid search condition '{}'", condition))?;
+                Ok(list_candidates(&self.search(Some(condition), memory)))
+            }
+            ["freeze", address, value, ref name @ ..] => {
+                let address = parse_address(address)?;
+                let value =
+                    parse_byte(value).ok_or_else(|| format!("Invalid value '{}'", value))?;
+                self.freeze(Cheat {
+                    name: name.join(" "),
+                    address,
+                    value,
+                });
+                Ok(format!("Froze {:#05X} to {}\n", address, value))
+            }
+            ["unfreeze", address] => {
+                let address = parse_address(address)?;
+                if self.unfreeze(address) {
+                    Ok(format!("Unfroze {:#05X}\n", address))
+                } else {
+                    Err(format!("{:#05X} isn't frozen", address).into())
+                }
+            }
+            ["cheats"] => Ok(self
+                .cheats
+                .iter()
+                .map(|cheat| format!("{:#05X} = {} {}\n", cheat.address, cheat.value, cheat.name))
+                .collect()),
+            ["save"] => {
+                self.save()?;
+                Ok(format!("Saved {} cheats\n", self.cheats.len()))
+            }
+            _ => Err(format!("Unknown command '{}'", line).into()),
+        }
+    }
+}
+
+// shows the first candidates with their current values
+fn list_candidates(candidates: &[(u16, u8)]) -> String {
+    const SHOWN: usize = 16;
+    let mut out = format!("{} candidates\n", candidates.len());
+    for &(address, value) in candidates.iter().take(SHOWN) {
+        out += &format!("{:#05X} = {}\n", address, value);
+    }
+    if candidates.len() > SHOWN {
+        out += "...\n";
+    }
+    out
+}
+
+fn parse(text: &str) -> Result<Vec<Cheat>, Box<dyn Error>> {
+    let table: toml::Table = text.parse()?;
+    let list = match table.get("cheat") {
+        Some(toml::Value::Array(list)) => list.as_slice(),
+        Some(_) => return Err("'cheat' has to be an array of tables".into()),
+        None => &[],
+    };
+
+    let mut cheats = Vec::new();
+    for cheat in list {
+        let number = |name: &str, max: i64| {
+            cheat
+                .get(name)
+                .and_then(toml::Value::as_integer)
+                .filter(|&value| (0..=max).contains(&value))
+                .ok_or_else(|| format!("Every cheat needs an '{}' between 0 and {}", name, max))
+        };
+        cheats.push(Cheat {
+            name: cheat
+                .get("name")
+                .and_then(toml::Value::as_str)
+                .unwrap_or_default()
+                .to_string(),
+            address: number("address", 0xFFF)? as u16,
+            value: number("value", 0xFF)? as u8,
+        });
+    }
+    Ok(cheats)
+}
+
+fn format(cheats: &[Cheat]) -> String {
+    let list = cheats
+        .iter()
+        .map(|cheat| {
+            let mut table = toml::Table::new();
+            table.insert("name".into(), cheat.name.clone().into());
+            table.insert("address".into(), (cheat.address as i64).into());
+            table.insert("value".into(), (cheat.value as i64).into());
+            toml::Value::Table(table)
+        })
+        .collect::<Vec<_>>();
+
+    let mut table = toml::Table::new();
+    table.insert("cheat".into(), toml::Value::Array(list));
+    table.to_string()
+}
+
+// decimal or hexadecimal with 0x prefix
+fn parse_number(text: &str) -> Option<u16> {
+    match text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        Some(hex) => u16::from_str_radix(hex, 16).ok(),
+        None => text.parse().ok(),
+    }
+}
+
+fn parse_byte(text: &str) -> Option<u8> {
+    parse_number(text)?.try_into().ok()
+}
+
+fn parse_address(text: &str) -> Result<u16, Box<dyn Error>> {
+    parse_number(text)
+        .filter(|&address| address <= 0xFFF)
+        .ok_or_else(|| format!("Invalid address '{}'", text).into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn search_narrows_candidates() {
+        let mut memory = [0u8; 8];
+        memory[2] = 3;
+        memory[5] = 7;
+        let mut search = Search::new(&memory);
+
+        memory[2] = 2;
+        memory[5] = 8;
+        search.filter(&memory, Condition::Changed);
+        assert_eq!(search.candidates(), [2, 5]);
+
+        memory[2] = 1;
+        search.filter(&memory, Condition::Decreased);
+        assert_eq!(search.candidates(), [2]);
+
+        search.filter(&memory, Condition::Unchanged);
+        assert_eq!(search.candidates(), [2]);
+        search.filter(&memory, Condition::Equal(0));
+        assert!(search.candidates().is_empty());
+    }
+
+    #[test]
+    fn search_compares_frames() {
+        let mut cheats = Cheats::default();
+        let mut memory = [0u8; 16];
+        cheats.end_frame(&memory);
+        assert_eq!(cheats.search(None, &memory).len(), 16);
+
+        // changed in the middle of a frame
+        memory[3] = 1;
+        assert!(cheats.search(Some(Condition::Changed), &memory).is_empty());
+        cheats.search(None, &memory);
+        memory[4] = 2;
+        cheats.end_frame(&memory);
+        assert_eq!(
+            cheats.search(Some(Condition::Changed), &memory),
+            [(3, 1), (4, 2)]
+        );
+        let output = cheats.command("search 2", &memory).unwrap();
+        assert_eq!(output, "1 candidates\n0x004 = 2\n");
+    }
+
+    #[test]
+    fn freeze_overrides_memory() {
+        let mut cheats = Cheats::default();
+        let mut memory = [0u8; 4096];
+        cheats.command("freeze 0x2F0 3 lives", &memory).unwrap();
+        cheats.command("freeze 0x2F0 5 lives", &memory).unwrap();
+        cheats.apply(&mut memory);
+        assert_eq!(memory[0x2F0], 5);
+        assert_eq!(cheats.cheats().len(), 1);
+        assert_eq!(cheats.cheats()[0].name, "lives");
+
+        assert!(cheats.command("unfreeze 0x2F0", &memory).is_ok());
+        assert!(cheats.command("unfreeze 0x2F0", &memory).is_err());
+        assert!(cheats.command("freeze 0x1000 1", &memory).is_err());
+        assert!(cheats.command("freeze 0x200 256", &memory).is_err());
+    }
+
+    #[test]
+    fn file_round_trip() {
+        let cheats = vec![
+            Cheat {
+                name: "lives".to_string(),
+                address: 0x2F0,
+                value: 3,
+            },
+            Cheat {
+                name: String::new(),
+                address: 0xFFF,
+                value: 255,
+            },
+        ];
+        assert_eq!(parse(&format(&cheats)).unwrap(), cheats);
+        assert!(parse("[[cheat]]\naddress = 4096\nvalue = 1\n").is_err());
+        assert!(parse("").unwrap().is_empty());
+    }
+}
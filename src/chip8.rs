@@ -1,8 +1,37 @@
+#[cfg(feature = "sdl")]
+use crate::audio::Beeper;
+use crate::cache::BlockCache;
+use crate::capture::{self, Recorder};
+use crate::cartridge::{self, Cartridge};
+use crate::cheat::{Cheat, Cheats, Condition};
+use crate::coverage::Coverage;
 use crate::cpu::Cpu;
+use crate::framebuffer::Rect;
+use crate::gdb::{self, GdbStub};
+#[cfg(feature = "sdl")]
 use crate::graphics::Graphics;
+use crate::instruction::Instruction;
+use crate::keymap::Keymap;
+use crate::lint::{self, Finding, Linter};
+use crate::options::{Engine, Options};
+#[cfg(any(feature = "sdl", feature = "terminal"))]
+use crate::phosphor::Phosphor;
+use crate::profiler::Profiler;
+use crate::remote::{self, Remote};
+use crate::state;
+#[cfg(feature = "terminal")]
+use crate::terminal::{Glyphs, Terminal};
+use crate::timing::Timing;
+use crate::watch::Watch;
+use serde_json::{json, Value};
 use std::error::Error;
 use std::fs;
-use std::io::Read;
+use std::mem;
+use std::ops::Range;
+use std::thread;
+use std::time::Duration;
+#[cfg(any(feature = "sdl", feature = "terminal"))]
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
 
 pub const COL: usize = 64;
 pub const ROW: usize = 32;
@@ -25,35 +54,880 @@ pub const FONTSET: [u8; 80] = [
     0xF0, 0x80, 0xF0, 0x80, 0x80, // F
 ];
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Hotkey {
+    Quit,
+    Screenshot,
+    Overlay,
+}
+
+// sees every executed instruction and frame, e.g. the hooks of scripts. The instruction
+// calls return false to stop the frame like a breakpoint.
+pub trait Watcher {
+    // called before every instruction, with PC at it
+    fn before(&mut self, cpu: &mut Cpu) -> bool;
+
+    // called after every instruction with the addresses it wrote
+    fn after(&mut self, cpu: &mut Cpu, written: Range<u16>) -> bool;
+
+    fn end_frame(&mut self, cpu: &mut Cpu);
+}
+
+// applies the settings of a ROM after it was loaded, e.g. its section of the configuration
+// file and its cheats, see load() in main.rs
+pub type Configure = Box<dyn Fn(&mut Chip8) -> Result<(), Box<dyn Error>> + Send>;
+
 pub struct Chip8 {
     cpu: Cpu,
+    options: Options,
+    rom: Vec<u8>,
+    rom_path: String,
+    keymap: Keymap,
+    recorder: Option<Recorder>,
+    gdb: Option<GdbStub>,
+    remote: Option<Remote>,
+    // set by the remote control to end the frontend
+    quit: bool,
+    watch: Option<Watch>,
+    // the options a reloaded ROM starts from and what applies its own settings
+    configure: Option<(Options, Configure)>,
+    // a message for the user, e.g. after the ROM was reloaded
+    notice: Option<String>,
+    cheats: Cheats,
+    profiler: Option<Profiler>,
+    coverage: Option<Coverage>,
+    linter: Option<Linter>,
+    watcher: Option<Box<dyn Watcher + Send>>,
+    timing: Timing,
+    cache: BlockCache,
+}
+
+impl Default for Chip8 {
+    fn default() -> Chip8 {
+        Chip8::new()
+    }
 }
 
 impl Chip8 {
     pub fn new() -> Chip8 {
-        Chip8 { cpu: Cpu::new() }
+        Chip8 {
+            cpu: Cpu::new(),
+            options: Options::default(),
+            rom: Vec::new(),
+            rom_path: String::new(),
+            keymap: Keymap::default(),
+            recorder: None,
+            gdb: None,
+            remote: None,
+            quit: false,
+            watch: None,
+            configure: None,
+            notice: None,
+            cheats: Cheats::default(),
+            profiler: None,
+            coverage: None,
+            linter: None,
+            watcher: None,
+            timing: Timing::default(),
+            cache: BlockCache::default(),
+        }
     }
 
+    // loads a raw ROM or an Octo cartridge, whose options replace the current ones
     pub fn load_program(&mut self, program_name: &str) -> Result<usize, Box<dyn Error>> {
-        let mut file = fs::File::open(program_name)?;
-        let read_bytes = file.read(&mut self.cpu.memory[512..])?;
-        println!("Read {} bytes from file {}", read_bytes, program_name);
+        self.load_data(fs::read(program_name)?)?;
+        eprintln!("Read {} bytes from file {}", self.rom.len(), program_name);
+        self.rom_path = program_name.to_string();
+        Ok(self.rom.len())
+    }
+
+    // load_program() for the contents of a file. The options of a cartridge only apply once
+    // its ROM fits into the memory.
+    pub fn load_data(&mut self, data: Vec<u8>) -> Result<(), Box<dyn Error>> {
+        if !cartridge::is_cartridge(&data) {
+            return self.load_rom(data);
+        }
+        let cartridge = Cartridge::decode(&data, self.options)?;
+        self.load_rom(cartridge.rom)?;
+        self.set_options(cartridge.options);
+        Ok(())
+    }
+
+    // starts the program in data from a reset, or from a save state with the program copied
+    // over the one in the state. The machine is left untouched if either is invalid.
+    fn restart(&mut self, data: Vec<u8>, state: Option<&[u8]>) -> Result<(), Box<dyn Error>> {
+        let mut cpu = Cpu::new();
+        if let Some(state) = state {
+            state::load(&mut cpu, state)?;
+        }
+        cpu.quirks = self.options.quirks;
+        let previous = mem::replace(&mut self.cpu, cpu);
+        if let Err(err) = self.load_data(data) {
+            self.cpu = previous;
+            return Err(err);
+        }
+        self.timing = Timing::default();
+        Ok(())
+    }
+
+    // loads a raw ROM from memory, e.g. one built by the assembler
+    pub fn load_rom(&mut self, rom: Vec<u8>) -> Result<(), Box<dyn Error>> {
+        let memory = &mut self.cpu.memory[512..];
+        if rom.len() > memory.len() {
+            return Err(format!("Program is too large ({} bytes)", rom.len()).into());
+        }
+        memory[..rom.len()].copy_from_slice(&rom);
         self.cpu.load_font();
-        Ok(read_bytes)
+        self.cache.clear();
+        self.rom = rom;
+        Ok(())
+    }
+
+    pub fn rom(&self) -> &[u8] {
+        &self.rom
+    }
+
+    pub fn memory(&self) -> &[u8] {
+        &self.cpu.memory
+    }
+
+    // one byte per pixel, row by row
+    pub fn framebuffer(&self) -> [u8; ROW * COL] {
+        self.cpu.graphics.pixels()
+    }
+
+    // the area of the framebuffer that changed since the last call, see Framebuffer
+    pub fn take_dirty(&mut self) -> Option<Rect> {
+        self.cpu.graphics.take_dirty()
+    }
+
+    pub fn sound_active(&self) -> bool {
+        self.cpu.sound_active()
+    }
+
+    pub fn cpu(&self) -> &Cpu {
+        &self.cpu
+    }
+
+    // for embedders changing registers or memory, which may hold cached code
+    pub fn cpu_mut(&mut self) -> &mut Cpu {
+        self.cache.clear();
+        &mut self.cpu
+    }
+
+    pub fn save_state(&self) -> Vec<u8> {
+        state::save(&self.cpu)
+    }
+
+    pub fn load_state(&mut self, data: &[u8]) -> Result<(), Box<dyn Error>> {
+        state::load(&mut self.cpu, data)?;
+        self.cache.clear();
+        Ok(())
+    }
+
+    // one bit per key of the keypad, for frontends without a keymap
+    pub fn set_keypad(&mut self, keys: u16) {
+        for (key, state) in self.cpu.keypad.iter_mut().enumerate() {
+            *state = (keys >> key & 1) as u8;
+        }
+    }
+
+    // makes the random numbers of CXNN reproducible
+    pub fn seed(&mut self, seed: u64) {
+        self.cpu.seed(seed);
+    }
+
+    pub fn set_keymap(&mut self, keymap: Keymap) {
+        self.keymap = keymap;
+    }
+
+    pub fn set_cheats(&mut self, cheats: Cheats) {
+        self.cheats = cheats;
+    }
+
+    pub fn export_cartridge(&self, path: &str) -> Result<(), Box<dyn Error>> {
+        Cartridge::new(&self.rom, self.options).save(path)
+    }
+
+    pub fn options(&self) -> Options {
+        self.options
+    }
+
+    pub fn set_options(&mut self, options: Options) {
+        self.options = options;
+        self.cpu.quirks = options.quirks;
+    }
+
+    // waits for a debugger to connect, see GdbStub
+    pub fn attach_debugger(&mut self, port: u16) -> Result<(), Box<dyn Error>> {
+        self.gdb = Some(GdbStub::listen(port)?);
+        Ok(())
+    }
+
+    // accepts remote control clients from now on, see Remote
+    pub fn start_remote(&mut self, port: u16) -> Result<(), Box<dyn Error>> {
+        self.remote = Some(Remote::listen(port)?);
+        Ok(())
+    }
+
+    // whether the cpu is halted by the debugger or paused by the remote control
+    pub fn is_stopped(&self) -> bool {
+        self.gdb.as_ref().is_some_and(|gdb| !gdb.is_running())
+            || self.remote.as_ref().is_some_and(Remote::is_paused)
+    }
+
+    // restarts the ROM whenever its file changes, from the save state if there is one.
+    // Breakpoints of the debugger and the remote control stay.
+    pub fn watch(&mut self, state: Option<Vec<u8>>) -> Result<(), Box<dyn Error>> {
+        if self.rom_path.is_empty() {
+            return Err("Only ROMs loaded from a file can be watched".into());
+        }
+        if let Some(state) = &state {
+            state::load(&mut Cpu::default(), state)?;
+        }
+        self.watch = Some(Watch::new(self.rom_path.as_ref(), state));
+        Ok(())
+    }
+
+    // ROMs reloaded by --watch start from options instead of the ones of the previous ROM,
+    // then configure applies their settings
+    pub fn configure_reloads(&mut self, options: Options, configure: Configure) {
+        self.configure = Some((options, configure));
+    }
+
+    // restart() with the settings of the new ROM, which may differ from the old one's
+    fn reload(&mut self, data: Vec<u8>, state: Option<&[u8]>) -> Result<(), Box<dyn Error>> {
+        let Some((options, configure)) = self.configure.take() else {
+            return self.restart(data, state);
+        };
+        let previous = self.options;
+        self.set_options(options);
+        let result = match self.restart(data, state) {
+            Ok(()) => configure(self),
+            Err(err) => {
+                self.set_options(previous);
+                Err(err)
+            }
+        };
+        self.configure = Some((options, configure));
+        result
+    }
+
+    fn poll_watch(&mut self) {
+        let Some(watch) = &mut self.watch else {
+            return;
+        };
+        if !watch.changed() {
+            return;
+        }
+        let path = watch.path().display().to_string();
+        let state = watch.state.take();
+        let result = fs::read(&path)
+            .map_err(Box::from)
+            .and_then(|data| self.reload(data, state.as_deref()));
+        if let Some(watch) = &mut self.watch {
+            watch.state = state;
+        }
+        let notice = match result {
+            Ok(()) => format!("Reloaded {} ({} bytes)", path, self.rom.len()),
+            Err(err) => format!("Error occured during reloading {}: {}", path, err),
+        };
+        eprintln!("{}", notice);
+        self.notice = Some(notice);
+    }
+
+    // the last message for the user, which frontends show for a moment
+    pub fn take_notice(&mut self) -> Option<String> {
+        self.notice.take()
+    }
+
+    // whether the remote control asked to end the frontend
+    pub fn should_quit(&self) -> bool {
+        self.quit
+    }
+
+    pub fn run_frame(&mut self) {
+        if let Err(err) = self.poll_debugger() {
+            eprintln!("Debugger disconnected: {}", err);
+            self.gdb = None;
+        }
+        self.poll_watch();
+        self.poll_remote();
+        if self.is_stopped() {
+            return;
+        }
+        self.advance();
+    }
+
+    // runs one frame, returns false if the debugger or a breakpoint of the remote control
+    // stopped it early
+    fn advance(&mut self) -> bool {
+        let mut completed = true;
+        if let Some(remote) = &self.remote {
+            remote.apply_keys(&mut self.cpu);
+        }
+        self.cheats.apply(&mut self.cpu.memory);
+        if self.uses_cache() {
+            for cheat in self.cheats.cheats() {
+                self.cache.invalidate(cheat.address as usize, 1);
+            }
+            self.cache.run(&mut self.cpu, self.options.tickrate);
+        } else if self.options.vip_timing {
+            self.timing.start_frame();
+            while let Some(cycles) = self.timing.next(&self.cpu) {
+                self.timing.charge(cycles);
+                if !self.step() {
+                    self.timing.skip_frame();
+                    completed = false;
+                    break;
+                }
+            }
+        } else {
+            // the debugger or the tools may have changed the memory
+            self.cache.clear();
+            for _ in 0..self.options.tickrate {
+                // with the vblank quirk a draw ends the frame
+                let waits = self.cpu.quirks.vblank
+                    && matches!(self.next_instruction(), Instruction::Draw(..));
+                if !self.step() {
+                    completed = false;
+                    break;
+                }
+                if waits {
+                    break;
+                }
+            }
+        }
+        self.cpu.update_timers();
+        self.cheats.end_frame(&self.cpu.memory);
+        if let Some(profiler) = &mut self.profiler {
+            profiler.end_frame();
+        }
+        if let Some(recorder) = &mut self.recorder {
+            if let Err(err) = recorder.add_frame(&self.cpu.graphics.pixels()) {
+                eprintln!("Recording stopped: {}", err);
+                self.recorder = None;
+            }
+        }
+        if let Some(remote) = &mut self.remote {
+            remote.end_frame(&self.cpu);
+        }
+        if let Some(watcher) = &mut self.watcher {
+            watcher.end_frame(&mut self.cpu);
+        }
+        completed
+    }
+
+    // executes single instructions without updating the timers, stops early if the debugger
+    // halts the cpu
+    pub fn run_instructions(&mut self, count: u32) {
+        for _ in 0..count {
+            if self.is_stopped() || !self.step() {
+                break;
+            }
+        }
+    }
+
+    // the cached engine runs whole blocks, which is only possible if nothing has to see
+    // every single instruction
+    fn uses_cache(&self) -> bool {
+        self.options.engine == Engine::Cached
+            && !self.options.vip_timing
+            && self.gdb.is_none()
+            && self.profiler.is_none()
+            && self.coverage.is_none()
+            && self.linter.is_none()
+            && self.watcher.is_none()
+            && self
+                .remote
+                .as_ref()
+                .is_none_or(|remote| !remote.has_breakpoints())
     }
 
+    // executes a single instruction, returns false if the debugger or a breakpoint of the
+    // remote control stopped the cpu
+    fn step(&mut self) -> bool {
+        if let Some(watcher) = &mut self.watcher {
+            if !watcher.before(&mut self.cpu) {
+                return false;
+            }
+        }
+        let (pc, i) = (self.cpu.pc, self.cpu.i);
+        let written = match self.watcher {
+            Some(_) => lint::writes(self.next_instruction(), i),
+            None => 0..0,
+        };
+        if let Some(linter) = &mut self.linter {
+            linter.inspect(&self.cpu);
+        }
+        self.cpu.step();
+        if let Some(profiler) = &mut self.profiler {
+            profiler.record(pc, &self.cpu);
+        }
+        if let Some(coverage) = &mut self.coverage {
+            coverage.record(pc, i, &self.cpu);
+        }
+        // everything sees the instruction even if something before stopped the cpu
+        let mut running = true;
+        if let Some(gdb) = &mut self.gdb {
+            if let Err(err) = gdb.check_breakpoint(&self.cpu) {
+                eprintln!("Debugger disconnected: {}", err);
+                self.gdb = None;
+            } else if !gdb.is_running() {
+                running = false;
+            }
+        }
+        if let Some(remote) = &mut self.remote {
+            if remote.check_breakpoint(&self.cpu) {
+                running = false;
+            }
+        }
+        if let Some(watcher) = &mut self.watcher {
+            if !watcher.after(&mut self.cpu, written) {
+                running = false;
+            }
+        }
+        running
+    }
+
+    fn next_instruction(&self) -> Instruction {
+        let pc = self.cpu.pc as usize;
+        let memory = &self.cpu.memory;
+        Instruction::decode(u16::from_be_bytes([
+            memory[pc % memory.len()],
+            memory[(pc + 1) % memory.len()],
+        ]))
+    }
+
+    // machine cycles of the COSMAC VIP since the start, only counted with vip_timing
+    pub fn cycles(&self) -> u64 {
+        self.timing.cycles
+    }
+
+    fn poll_debugger(&mut self) -> Result<(), Box<dyn Error>> {
+        if let Some(gdb) = &mut self.gdb {
+            if !gdb.poll(&mut self.cpu, &mut self.cheats)? {
+                println!("Debugger detached");
+                self.gdb = None;
+            }
+        }
+        // a step runs through step() for the tools and hooks, with the frozen values applied
+        if self.gdb.as_mut().is_some_and(|gdb| gdb.take_step()) {
+            self.cheats.apply(&mut self.cpu.memory);
+            self.step();
+            if let Some(gdb) = &mut self.gdb {
+                gdb.stepped()?;
+            }
+        }
+        Ok(())
+    }
+
+    // answers the requests of the remote control clients
+    fn poll_remote(&mut self) {
+        let Some(remote) = &mut self.remote else {
+            return;
+        };
+        for (client, request) in remote.poll(&mut self.cpu) {
+            let result = self.remote_request(&request);
+            if let Some(remote) = &mut self.remote {
+                remote.reply(client, &request, result);
+            }
+        }
+    }
+
+    // the requests of the remote control that need the whole machine, see Remote
+    fn remote_request(&mut self, request: &Value) -> Result<Value, Box<dyn Error>> {
+        let path = request["path"].as_str();
+        match request["cmd"].as_str().unwrap_or("") {
+            "load" => {
+                let data = match path {
+                    Some(path) => fs::read(path)?,
+                    None => remote::bytes(request, "rom")?,
+                };
+                // like a reload of --watch, with the settings and cheats of the new ROM
+                self.reload(data, None)?;
+                // a ROM sent as bytes has no file for --watch and the reports
+                self.rom_path = path.unwrap_or_default().to_string();
+                self.watch = match (self.watch.take(), path) {
+                    (Some(watch), Some(path)) => Some(Watch::new(path.as_ref(), watch.state)),
+                    _ => None,
+                };
+                Ok(json!({"size": self.rom.len()}))
+            }
+            "step" => {
+                let count = match request["count"] {
+                    Value::Null => 1,
+                    _ => remote::number(request, "count", remote::MAX_STEPS)?,
+                };
+                if let Some(remote) = &mut self.remote {
+                    remote.pause();
+                }
+                for _ in 0..count {
+                    if !self.step() {
+                        break;
+                    }
+                }
+                Ok(json!({"pc": self.cpu.pc}))
+            }
+            "run_frames" => {
+                let frames = remote::number(request, "frames", remote::MAX_FRAMES)?;
+                let mut done = 0;
+                while done < frames {
+                    done += 1;
+                    if !self.advance() {
+                        break;
+                    }
+                }
+                Ok(json!({"frames": done}))
+            }
+            "read_mem" => {
+                let address = remote::number(request, "address", 0xFFF)? as usize;
+                let length = remote::number(request, "length", 0x1000)? as usize;
+                let data = self
+                    .cpu
+                    .memory
+                    .get(address..address + length)
+                    .ok_or("The range is outside of the memory")?;
+                Ok(json!({"data": data}))
+            }
+            "write_mem" => {
+                let address = remote::number(request, "address", 0xFFF)? as usize;
+                let data = remote::bytes(request, "data")?;
+                self.cpu
+                    .memory
+                    .get_mut(address..address + data.len())
+                    .ok_or("The range is outside of the memory")?
+                    .copy_from_slice(&data);
+                self.cache.clear();
+                Ok(json!({}))
+            }
+            "screenshot" => match path {
+                Some(path) => {
+                    self.screenshot(path)?;
+                    Ok(json!({}))
+                }
+                None => {
+                    let rows: Vec<String> = self
+                        .cpu
+                        .graphics
+                        .pixels()
+                        .chunks(COL)
+                        .map(|row| row.iter().map(|&p| char::from(b'0' + p)).collect())
+                        .collect();
+                    Ok(json!({"width": COL, "height": ROW, "rows": rows}))
+                }
+            },
+            "save_state" => {
+                let state = self.save_state();
+                match path {
+                    Some(path) => {
+                        fs::write(path, state)?;
+                        Ok(json!({}))
+                    }
+                    None => {
+                        let hex: String = state.iter().map(|b| format!("{:02x}", b)).collect();
+                        Ok(json!({"state": hex}))
+                    }
+                }
+            }
+            "load_state" => {
+                let data = match path {
+                    Some(path) => fs::read(path)?,
+                    None => request["state"]
+                        .as_str()
+                        .and_then(gdb::decode_hex)
+                        .ok_or("'state' has to be a hex string")?,
+                };
+                self.load_state(&data)?;
+                Ok(json!({}))
+            }
+            "search" => {
+                let condition = match &request["condition"] {
+                    Value::Null => None,
+                    Value::String(text) => Some(
+                        Condition::parse(text)
+                            .ok_or_else(|| format!("Invalid search condition '{}'", text))?,
+                    ),
+                    _ => Some(Condition::Equal(
+                        remote::number(request, "condition", 0xFF)? as u8,
+                    )),
+                };
+                let candidates = self.cheats.search(condition, &self.cpu.memory);
+                Ok(json!({"candidates": candidates}))
+            }
+            "freeze" => {
+                let address = remote::number(request, "address", 0xFFF)? as u16;
+                let value = remote::number(request, "value", 0xFF)? as u8;
+                let name = request["name"].as_str().unwrap_or("").to_string();
+                self.cheats.freeze(Cheat {
+                    name,
+                    address,
+                    value,
+                });
+                Ok(json!({}))
+            }
+            "unfreeze" => {
+                let address = remote::number(request, "address", 0xFFF)? as u16;
+                if !self.cheats.unfreeze(address) {
+                    return Err(format!("{:#05X} isn't frozen", address).into());
+                }
+                Ok(json!({}))
+            }
+            "cheats" => {
+                let cheats: Vec<Value> = self
+                    .cheats
+                    .cheats()
+                    .iter()
+                    .map(|cheat| {
+                        json!({"address": cheat.address, "value": cheat.value, "name": cheat.name})
+                    })
+                    .collect();
+                Ok(json!({"cheats": cheats}))
+            }
+            "save_cheats" => {
+                self.cheats.save()?;
+                Ok(json!({}))
+            }
+            "quit" => {
+                self.quit = true;
+                Ok(json!({}))
+            }
+            "" => Err("The request has no 'cmd'".into()),
+            name => Err(format!("Unknown command '{}'", name).into()),
+        }
+    }
+
+    // profiles every instruction from now on, see Profiler
+    pub fn start_profiling(&mut self) {
+        self.profiler = Some(Profiler::new(self.cpu.pc));
+    }
+
+    pub fn profile_report(&self) -> Option<String> {
+        Some(self.profiler.as_ref()?.report(&self.cpu.memory))
+    }
+
+    pub fn profile_folded(&self) -> Option<String> {
+        Some(self.profiler.as_ref()?.report_folded())
+    }
+
+    // watches the executed instructions from now on, see Linter
+    pub fn start_linting(&mut self) {
+        self.linter = Some(Linter::default());
+    }
+
+    pub fn lint_findings(&self) -> Vec<Finding> {
+        match &self.linter {
+            Some(linter) => linter.findings().cloned().collect(),
+            None => Vec::new(),
+        }
+    }
+
+    // reports every instruction and frame from now on, see Watcher
+    pub fn set_watcher(&mut self, watcher: Box<dyn Watcher + Send>) {
+        self.watcher = Some(watcher);
+    }
+
+    // marks the executed instructions and data from now on, see Coverage
+    pub fn start_coverage(&mut self) {
+        self.coverage = Some(Coverage::new());
+    }
+
+    // writes an annotated disassembly, as HTML if the path ends with .html
+    pub fn save_coverage(&self, path: &str) -> Result<(), Box<dyn Error>> {
+        let coverage = self.coverage.as_ref().ok_or("Coverage wasn't enabled")?;
+        let memory = &self.cpu.memory;
+        let report = if path.to_lowercase().ends_with(".html") {
+            coverage.report_html(memory, self.rom.len(), &self.rom_path)
+        } else {
+            coverage.report_text(memory, self.rom.len())
+        };
+        fs::write(path, report)?;
+        Ok(())
+    }
+
+    pub fn save_lcov(&self, path: &str) -> Result<(), Box<dyn Error>> {
+        let coverage = self.coverage.as_ref().ok_or("Coverage wasn't enabled")?;
+        let report = coverage.report_lcov(&self.cpu.memory, self.rom.len(), &self.rom_path);
+        fs::write(path, report)?;
+        Ok(())
+    }
+
+    pub fn screenshot(&self, path: &str) -> Result<(), Box<dyn Error>> {
+        capture::save_png(
+            path,
+            &self.cpu.graphics.pixels(),
+            self.options.scale,
+            &self.options.palette,
+        )
+    }
+
+    // records every frame until stop_recording is called, see Recorder
+    pub fn start_recording(&mut self, path: &str) -> Result<(), Box<dyn Error>> {
+        let recorder = Recorder::new(path, self.options.scale, self.options.palette)?;
+        self.recorder = Some(recorder);
+        Ok(())
+    }
+
+    pub fn stop_recording(&mut self) -> Result<(), Box<dyn Error>> {
+        match self.recorder.take() {
+            Some(recorder) => recorder.finish(),
+            None => Ok(()),
+        }
+    }
+
+    // saves a screenshot with a timestamped name and returns a message for the user
+    #[cfg(any(feature = "sdl", feature = "terminal"))]
+    fn hotkey_screenshot(&self) -> String {
+        let millis = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|time| time.as_millis())
+            .unwrap_or(0);
+        let path = format!("rc8-{}.png", millis);
+        match self.screenshot(&path) {
+            Ok(()) => format!("Saved screenshot to {}", path),
+            Err(err) => format!("Error occured during saving the screenshot: {}", err),
+        }
+    }
+
+    // runs without any frontend, the keypad stays released. Frames while a debugger halts
+    // the cpu don't count.
+    pub fn headless_loop(&mut self, frames: u32) {
+        let mut frame = 0;
+        while frame < frames && !self.quit {
+            self.run_frame();
+            self.cpu.should_redraw = false;
+            if self.is_stopped() {
+                thread::sleep(Duration::from_millis(10));
+            } else {
+                frame += 1;
+            }
+        }
+    }
+
+    #[cfg(feature = "sdl")]
     pub fn gameloop(&mut self) {
-        let mut graphics = Graphics::new();
-        while graphics.app.next_frame() {
-            let opcode = self.cpu.fetch_opcode();
-            self.cpu
-                .decode_and_execute_graphic(opcode, Some(&mut graphics));
-            self.cpu.update_timers();
-            self.cpu.update_timers();
-            if self.cpu.should_redraw {
-                graphics.draw(&self.cpu.graphics);
-                self.cpu.should_redraw = false;
+        let mut graphics = Graphics::new(&self.options, &self.keymap);
+        let mut phosphor = Phosphor::new(self.options.persistence);
+        graphics.draw(phosphor.frame(), Rect::FULL);
+        let mut beeper = None;
+        if self.options.audio {
+            beeper = Beeper::new();
+            if beeper.is_none() {
+                eprintln!("There is no audio device, the sound is off");
+            }
+        }
+        let mut notice: Option<(String, Instant)> = None;
+        // --watch may load a ROM with another keymap
+        let mut keymap = self.keymap.clone();
+        while !self.quit && graphics.app.next_frame() {
+            for hotkey in graphics.hotkeys() {
+                match hotkey {
+                    Hotkey::Screenshot => println!("{}", self.hotkey_screenshot()),
+                    Hotkey::Overlay => graphics.toggle_overlay(),
+                    Hotkey::Quit => {}
+                }
+            }
+            graphics.update_keypad(&mut self.cpu.keypad);
+            self.run_frame();
+            if self.keymap != keymap {
+                keymap = self.keymap.clone();
+                graphics.set_keymap(&keymap);
+            }
+            if let Some(beeper) = &mut beeper {
+                beeper.update(self.cpu.sound_active() && self.options.audio);
+            }
+            if let Some(text) = self.take_notice() {
+                notice = Some((text, Instant::now()));
+            }
+            let dirty = self.cpu.graphics.take_dirty();
+            if let Some(rect) = phosphor.update(&self.cpu.graphics.pixels(), dirty) {
+                graphics.draw(phosphor.frame(), rect);
+            }
+            match &notice {
+                Some((text, since)) if since.elapsed() < Duration::from_secs(3) => {
+                    graphics.notice(text)
+                }
+                Some(_) => {
+                    graphics.draw(phosphor.frame(), Rect::FULL);
+                    notice = None;
+                }
+                None => {}
+            }
+            graphics.draw_overlay(&self.cpu);
+            self.cpu.should_redraw = false;
+        }
+    }
+
+    #[cfg(feature = "terminal")]
+    pub fn terminal_loop(&mut self, glyphs: Glyphs) -> Result<(), Box<dyn Error>> {
+        let frame_time = Duration::from_secs(1) / 60;
+        let mut terminal = Terminal::new(self.options.palette, glyphs, &self.keymap)?;
+        let mut phosphor = Phosphor::new(self.options.persistence);
+        terminal.draw(phosphor.frame(), Rect::FULL)?;
+
+        let mut fps = 0.0;
+        let mut frames = 0;
+        let mut second = Instant::now();
+        let mut sound = false;
+        let mut message: Option<(String, Instant)> = None;
+        let mut keymap = self.keymap.clone();
+        while !self.quit {
+            let start = Instant::now();
+            for hotkey in terminal.update_keypad(&mut self.cpu.keypad)? {
+                match hotkey {
+                    Hotkey::Quit => return Ok(()),
+                    Hotkey::Screenshot => {
+                        message = Some((self.hotkey_screenshot(), Instant::now()))
+                    }
+                    Hotkey::Overlay => {}
+                }
+            }
+            self.run_frame();
+            if self.keymap != keymap {
+                keymap = self.keymap.clone();
+                terminal.set_keymap(&keymap);
+            }
+            if let Some(text) = self.take_notice() {
+                message = Some((text, Instant::now()));
+            }
+
+            let dirty = self.cpu.graphics.take_dirty();
+            if let Some(rect) = phosphor.update(&self.cpu.graphics.pixels(), dirty) {
+                terminal.draw(phosphor.frame(), rect)?;
+            }
+            self.cpu.should_redraw = false;
+            if self.cpu.sound_active() && !sound && self.options.audio {
+                terminal.beep()?;
+            }
+            sound = self.cpu.sound_active();
+
+            frames += 1;
+            if second.elapsed() >= Duration::from_secs(1) {
+                fps = frames as f64 / second.elapsed().as_secs_f64();
+                frames = 0;
+                second = Instant::now();
+            }
+            match &message {
+                Some((text, since)) if since.elapsed() < Duration::from_secs(3) => {
+                    terminal.status(text)?
+                }
+                _ => {
+                    let cycles = if self.options.vip_timing {
+                        format!("  cycles {}", self.timing.cycles)
+                    } else {
+                        String::new()
+                    };
+                    terminal.status(&format!(
+                        "FPS {:5.1}  PC {:#05X}{}  (Esc to quit, F12 screenshot)",
+                        fps, self.cpu.pc, cycles
+                    ))?
+                }
+            }
+
+            if let Some(rest) = frame_time.checked_sub(start.elapsed()) {
+                thread::sleep(rest);
             }
         }
+        Ok(())
     }
 }
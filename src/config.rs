@@ -0,0 +1,331 @@
+// The configuration file lives at $XDG_CONFIG_HOME/rc8/config.toml, or
+// ~/.config/rc8/config.toml if XDG_CONFIG_HOME isn't set. Every setting is optional:
+//
+//     scale = 10              # size of a CHIP-8 pixel on screen
+//     speed = 10              # instructions per frame
+//     audio = true            # beep while the sound timer runs
+//     vip_timing = false      # run as fast as a COSMAC VIP instead of 'speed' per frame
+//     engine = "interpreter"  # or "cached"
+//     persistence = "off"     # "off", "or" or a decay factor like 0.6
+//     overlay = false         # show registers, stack and keypad next to the screen
+//
+//     [palette]               # background, fill, fill2, blend, buzz and quiet
+//     fill = "#FFCC00"
+//
+//     [quirks]                # shift, load_store, vf_order, clip, vblank, jump and logic
+//     shift = true
+//
+//     [keymap]                # CHIP-8 key = host key(s)
+//     5 = ["W", "Up"]
+//
+// Settings of a single game go into a section named after the SHA-1 of the ROM, which can
+// contain everything listed above:
+//
+//     [rom."0d5e5bd8ee6a2a4fab6fdd3ab32e4e84283ec3d9"]
+//     speed = 30
+//     keymap = { 5 = "Space" }
+//
+// Settings are applied in this order, later ones win:
+//
+//     1. built-in defaults
+//     2. the global settings of the configuration file
+//     3. the options embedded in an Octo cartridge
+//     4. the [rom."<sha1>"] section of the ROM
+//     5. command line flags
+use crate::keymap::Keymap;
+use crate::options::{parse_color, Engine, Options, Quirks, MAX_SCALE};
+use crate::phosphor::Persistence;
+use std::env;
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone, Default)]
+pub struct Config {
+    table: toml::Table,
+}
+
+pub fn rom_id(rom: &[u8]) -> String {
+    sha1_smol::Sha1::from(rom).digest().to_string()
+}
+
+// the directory of rc8 in the configuration directory of the user
+pub fn dir() -> Option<PathBuf> {
+    let dir = match env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(env::var_os("HOME")?).join(".config"),
+    };
+    Some(dir.join("rc8"))
+}
+
+impl Config {
+    pub fn path() -> Option<PathBuf> {
+        Some(dir()?.join("config.toml"))
+    }
+
+    // a missing configuration file is the same as an empty one
+    pub fn load() -> Result<Config, Box<dyn Error>> {
+        match Config::path() {
+            Some(path) if path.exists() => Config::load_from(&path),
+            _ => Ok(Config::default()),
+        }
+    }
+
+    pub fn load_from(path: &Path) -> Result<Config, Box<dyn Error>> {
+        let text = fs::read_to_string(path)?;
+        Config::parse(&text).map_err(|err| format!("{}: {}", path.display(), err).into())
+    }
+
+    pub fn parse(text: &str) -> Result<Config, Box<dyn Error>> {
+        let config = Config {
+            table: text.parse()?,
+        };
+        check_keys(&config.table, true)?;
+        if let Some(roms) = section(&config.table, "rom")? {
+            for (id, profile) in roms {
+                match profile {
+                    toml::Value::Table(profile) => check_keys(profile, false)?,
+                    _ => return Err(format!("'rom.{}' has to be a table", id).into()),
+                }
+            }
+        }
+        Ok(config)
+    }
+
+    pub fn apply_global(&self, options: &mut Options) -> Result<(), Box<dyn Error>> {
+        apply_options(&self.table, options)
+    }
+
+    pub fn apply_rom(&self, rom: &[u8], options: &mut Options) -> Result<(), Box<dyn Error>> {
+        match self.profile(rom)? {
+            Some(profile) => apply_options(profile, options),
+            None => Ok(()),
+        }
+    }
+
+    pub fn keymap(&self, rom: &[u8]) -> Result<Keymap, Box<dyn Error>> {
+        let mut keymap = Keymap::default();
+        if let Some(table) = section(&self.table, "keymap")? {
+            keymap.apply(table)?;
+        }
+        if let Some(profile) = self.profile(rom)? {
+            if let Some(table) = section(profile, "keymap")? {
+                keymap.apply(table)?;
+            }
+        }
+        Ok(keymap)
+    }
+
+    // the section with the settings of a single ROM
+    fn profile(&self, rom: &[u8]) -> Result<Option<&toml::Table>, Box<dyn Error>> {
+        match section(&self.table, "rom")? {
+            Some(roms) => section(roms, &rom_id(rom)),
+            None => Ok(None),
+        }
+    }
+}
+
+// catches typos, which would otherwise be silently ignored
+fn check_keys(table: &toml::Table, global: bool) -> Result<(), Box<dyn Error>> {
+    const KEYS: [&str; 10] = [
+        "scale",
+        "speed",
+        "audio",
+        "vip_timing",
+        "engine",
+        "persistence",
+        "overlay",
+        "palette",
+        "quirks",
+        "keymap",
+    ];
+    for key in table.keys() {
+        let known = KEYS.contains(&key.as_str()) || (global && key == "rom");
+        if !known {
+            return Err(format!("Unknown setting '{}'", key).into());
+        }
+    }
+    Ok(())
+}
+
+fn apply_options(table: &toml::Table, options: &mut Options) -> Result<(), Box<dyn Error>> {
+    if let Some(scale) = integer(table, "scale")? {
+        if scale > MAX_SCALE {
+            return Err(format!("'scale' has to be at most {}", MAX_SCALE).into());
+        }
+        options.scale = scale;
+    }
+    if let Some(speed) = integer(table, "speed")? {
+        options.tickrate = speed;
+    }
+    if let Some(audio) = table.get("audio") {
+        options.audio = audio.as_bool().ok_or("'audio' has to be true or false")?;
+    }
+    if let Some(timing) = table.get("vip_timing") {
+        options.vip_timing = timing
+            .as_bool()
+            .ok_or("'vip_timing' has to be true or false")?;
+    }
+    if let Some(overlay) = table.get("overlay") {
+        options.overlay = overlay
+            .as_bool()
+            .ok_or("'overlay' has to be true or false")?;
+    }
+    if let Some(engine) = table.get("engine") {
+        options.engine = engine
+            .as_str()
+            .and_then(Engine::parse)
+            .ok_or("'engine' has to be \"interpreter\" or \"cached\"")?;
+    }
+    if let Some(persistence) = table.get("persistence") {
+        let mode = match persistence {
+            toml::Value::Float(decay) => decay.to_string(),
+            toml::Value::Integer(decay) => decay.to_string(),
+            toml::Value::String(mode) => mode.clone(),
+            _ => String::new(),
+        };
+        options.persistence = Persistence::parse(&mode)
+            .ok_or("'persistence' has to be \"off\", \"or\" or a number between 0 and 1")?;
+    }
+
+    if let Some(palette) = section(table, "palette")? {
+        for (name, value) in palette {
+            let color = options
+                .palette
+                .color_mut(name)
+                .ok_or_else(|| format!("Unknown color '{}'", name))?;
+            let text = value
+                .as_str()
+                .ok_or_else(|| format!("Color '{}' has to be a string like \"#FFCC00\"", name))?;
+            *color = parse_color(text)?;
+        }
+    }
+
+    if let Some(quirks) = section(table, "quirks")? {
+        for (name, value) in quirks {
+            let flag = options.quirks.flag_mut(name).ok_or_else(|| {
+                format!(
+                    "Unknown quirk '{}', use one of {}",
+                    name,
+                    Quirks::NAMES.join(", ")
+                )
+            })?;
+            *flag = value
+                .as_bool()
+                .ok_or_else(|| format!("Quirk '{}' has to be true or false", name))?;
+        }
+    }
+    Ok(())
+}
+
+fn integer(table: &toml::Table, name: &str) -> Result<Option<u32>, Box<dyn Error>> {
+    match table.get(name) {
+        Some(value) => match value.as_integer() {
+            Some(value) if value > 0 && value <= u32::MAX as i64 => Ok(Some(value as u32)),
+            _ => Err(format!("'{}' has to be a positive number", name).into()),
+        },
+        None => Ok(None),
+    }
+}
+
+fn section<'a>(
+    table: &'a toml::Table,
+    name: &str,
+) -> Result<Option<&'a toml::Table>, Box<dyn Error>> {
+    match table.get(name) {
+        Some(toml::Value::Table(section)) => Ok(Some(section)),
+        Some(_) => Err(format!("'{}' has to be a table", name).into()),
+        None => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ROM: &[u8] = &[0x00, 0xE0];
+
+    #[test]
+    fn default_keymap() {
+        let keymap = Config::parse("").unwrap().keymap(ROM).unwrap();
+        assert_eq!(keymap, Keymap::default());
+        assert_eq!(keymap.keys(0x0), ["X"]);
+        assert_eq!(keymap.keys(0xC), ["4"]);
+    }
+
+    #[test]
+    fn rom_overrides_global_keymap() {
+        let text = format!(
+            "[keymap]\n5 = [\"W\", \"Up\"]\n6 = \"E\"\n\n[rom.\"{}\".keymap]\n6 = \"Space\"\n",
+            rom_id(ROM)
+        );
+        let config = Config::parse(&text).unwrap();
+
+        let keymap = config.keymap(ROM).unwrap();
+        assert_eq!(keymap.keys(0x5), ["W", "Up"]);
+        assert_eq!(keymap.keys(0x6), ["Space"]);
+
+        let other = config.keymap(&[0x12, 0x00]).unwrap();
+        assert_eq!(other.keys(0x6), ["E"]);
+    }
+
+    #[test]
+    fn invalid_keymap() {
+        let config = Config::parse("[keymap]\nG = \"W\"\n").unwrap();
+        assert!(config.keymap(ROM).is_err());
+
+        let config = Config::parse("[keymap]\n1 = 5\n").unwrap();
+        assert!(config.keymap(ROM).is_err());
+    }
+
+    #[test]
+    fn rom_overrides_global_options() {
+        let text = format!(
+            "scale = 4\nspeed = 20\npersistence = 0.5\noverlay = true\n\n[palette]\nfill = \"#FFCC00\"\n\n\
+             [quirks]\nshift = true\n\n[rom.\"{}\"]\nspeed = 30\naudio = false\n\
+             quirks = {{ shift = false, jump = true }}\n",
+            rom_id(ROM)
+        );
+        let config = Config::parse(&text).unwrap();
+
+        let mut options = Options::default();
+        config.apply_global(&mut options).unwrap();
+        assert_eq!(options.scale, 4);
+        assert_eq!(options.tickrate, 20);
+        assert_eq!(options.persistence, Persistence::Decay(0.5));
+        assert!(options.overlay);
+        assert_eq!(options.palette.fill, 0xFFCC00);
+        assert!(options.quirks.shift);
+
+        config.apply_rom(ROM, &mut options).unwrap();
+        assert_eq!(options.scale, 4);
+        assert_eq!(options.tickrate, 30);
+        assert!(!options.audio);
+        assert!(!options.quirks.shift);
+        assert!(options.quirks.jump);
+    }
+
+    #[test]
+    fn unknown_settings() {
+        assert!(Config::parse("sped = 20\n").is_err());
+        assert!(Config::parse("[rom.abc]\nrom = 1\n").is_err());
+
+        let config = Config::parse("[quirks]\nwrap = true\n").unwrap();
+        assert!(config.apply_global(&mut Options::default()).is_err());
+
+        let config = Config::parse("scale = 0\n").unwrap();
+        assert!(config.apply_global(&mut Options::default()).is_err());
+    }
+
+    #[test]
+    fn scale_fits_the_window() {
+        let mut options = Options::default();
+        let config = Config::parse(&format!("scale = {}\n", MAX_SCALE)).unwrap();
+        config.apply_global(&mut options).unwrap();
+        assert_eq!(options.scale, 1023);
+
+        let config = Config::parse(&format!("scale = {}\n", MAX_SCALE + 1)).unwrap();
+        let err = config.apply_global(&mut options).unwrap_err();
+        assert_eq!(err.to_string(), "'scale' has to be at most 1023");
+    }
+}
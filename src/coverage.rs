@@ -0,0 +1,273 @@
+// Marks which bytes of the memory were executed and which were accessed as data through I.
+//
+// The reports list the ROM as an annotated disassembly. Bytes that were only accessed as data
+// are listed as DB, bytes that were never touched are disassembled as if they were code.
+// In the lcov report the "line" of an instruction is its address (512 for 0x200), which source
+// maps of an assembler can map back to the source.
+use crate::cpu::Cpu;
+use crate::instruction::Instruction;
+use std::fmt::Write;
+
+const START: usize = 0x200;
+
+#[derive(Debug, Clone)]
+pub struct Coverage {
+    // number of executions of the instruction starting at an address
+    hits: Vec<u64>,
+    data: Vec<bool>,
+    // how often skip instructions skipped and didn't skip
+    branches: Vec<(u64, u64)>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Line {
+    Code(Instruction),
+    Data(u8),
+}
+
+impl Default for Coverage {
+    fn default() -> Coverage {
+        Coverage::new()
+    }
+}
+
+impl Coverage {
+    pub fn new() -> Coverage {
+        Coverage {
+            hits: vec![0; 4096],
+            data: vec![false; 4096],
+            branches: vec![(0, 0); 4096],
+        }
+    }
+
+    // called after the instruction at pc was executed, i is the index register before it
+    pub fn record(&mut self, pc: u16, i: u16, cpu: &Cpu) {
+        let address = pc as usize % self.hits.len();
+        self.hits[address] += 1;
+
+        let opcode = u16::from_be_bytes([
+            cpu.memory[address],
+            cpu.memory[(address + 1) % cpu.memory.len()],
+        ]);
+        let accessed = match Instruction::decode(opcode) {
+            Instruction::Draw(_, _, n) => n as usize,
+            Instruction::Bcd(_) => 3,
+            Instruction::Store(x) | Instruction::Restore(x) => x as usize + 1,
+            instruction if instruction.is_skip() => {
+                let branch = &mut self.branches[address];
+                if cpu.pc == pc.wrapping_add(4) {
+                    branch.0 += 1;
+                } else {
+                    branch.1 += 1;
+                }
+                0
+            }
+            _ => 0,
+        };
+        let len = self.data.len();
+        for offset in 0..accessed {
+            self.data[(i as usize + offset) % len] = true;
+        }
+    }
+
+    // the ROM split into instructions and data bytes
+    fn listing(&self, memory: &[u8], len: usize) -> Vec<(usize, Line)> {
+        let end = (START + len).min(memory.len());
+        let mut lines = Vec::new();
+        let mut address = START;
+        while address < end {
+            let code = self.hits[address] > 0
+                || (address + 1 < end && !self.data[address] && !self.data[address + 1]);
+            if code {
+                let next = memory[(address + 1) % memory.len()];
+                let opcode = u16::from_be_bytes([memory[address], next]);
+                lines.push((address, Line::Code(Instruction::decode(opcode))));
+                address += 2;
+            } else {
+                lines.push((address, Line::Data(memory[address])));
+                address += 1;
+            }
+        }
+        lines
+    }
+
+    fn summary(&self, lines: &[(usize, Line)]) -> (usize, usize) {
+        let code: Vec<usize> = lines
+            .iter()
+            .filter(|(_, line)| matches!(line, Line::Code(_)))
+            .map(|&(address, _)| address)
+            .collect();
+        let executed = code.iter().filter(|&&a| self.hits[a] > 0).count();
+        (executed, code.len())
+    }
+
+    // annotated disassembly, one line per instruction or data byte
+    pub fn report_text(&self, memory: &[u8], len: usize) -> String {
+        let lines = self.listing(memory, len);
+        let (executed, total) = self.summary(&lines);
+        let mut out = format!("{} of {} instructions executed\n\n", executed, total);
+        for &(address, line) in &lines {
+            let (marker, text) = self.annotate(address, line, memory);
+            writeln!(out, "{} {}", marker, text).unwrap();
+        }
+        out
+    }
+
+    pub fn report_html(&self, memory: &[u8], len: usize, title: &str) -> String {
+        let lines = self.listing(memory, len);
+        let (executed, total) = self.summary(&lines);
+        let mut out = format!(
+            "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>Coverage of {}</title>\n\
+             <style>\nbody {{ font-family: monospace; }}\npre span {{ display: block; }}\n\
+             .executed {{ background: #c8f7c5; }}\n.missed {{ background: #f7c5c5; }}\n\
+             .data {{ background: #c5d8f7; }}\n</style>\n</head>\n<body>\n\
+             <h1>Coverage of {}</h1>\n<p>{} of {} instructions executed</p>\n<pre>\n",
+            escape(title),
+            escape(title),
+            executed,
+            total
+        );
+        for &(address, line) in &lines {
+            let (marker, text) = self.annotate(address, line, memory);
+            let class = match marker {
+                '+' => "executed",
+                '-' => "missed",
+                _ => "data",
+            };
+            writeln!(out, "<span class=\"{}\">{}</span>", class, escape(&text)).unwrap();
+        }
+        out += "</pre>\n</body>\n</html>\n";
+        out
+    }
+
+    // lcov tracefile with the addresses as line numbers and the skips as branches
+    pub fn report_lcov(&self, memory: &[u8], len: usize, source: &str) -> String {
+        let lines = self.listing(memory, len);
+        let mut out = format!("TN:\nSF:{}\n", source);
+        let (mut branches, mut taken) = (0, 0);
+        for &(address, line) in &lines {
+            let Line::Code(instruction) = line else {
+                continue;
+            };
+            let hits = self.hits[address];
+            writeln!(out, "DA:{},{}", address, hits).unwrap();
+            if instruction.is_skip() {
+                let (skipped, not_skipped) = self.branches[address];
+                for (branch, count) in [(0, skipped), (1, not_skipped)] {
+                    let count = if hits == 0 {
+                        "-".to_string()
+                    } else {
+                        count.to_string()
+                    };
+                    writeln!(out, "BRDA:{},0,{},{}", address, branch, count).unwrap();
+                }
+                branches += 2;
+                taken += (skipped > 0) as usize + (not_skipped > 0) as usize;
+            }
+        }
+        let (executed, total) = self.summary(&lines);
+        writeln!(out, "BRF:{}\nBRH:{}", branches, taken).unwrap();
+        writeln!(out, "LF:{}\nLH:{}\nend_of_record", total, executed).unwrap();
+        out
+    }
+
+    // '+' for executed, '-' for missed instructions and '.' for data
+    fn annotate(&self, address: usize, line: Line, memory: &[u8]) -> (char, String) {
+        match line {
+            Line::Code(instruction) => {
+                let hits = self.hits[address];
+                let marker = if hits > 0 { '+' } else { '-' };
+                let text = format!(
+                    "{:#05X}  {:02X}{:02X}  {:<18} {:>10}",
+                    address,
+                    memory[address],
+                    memory[(address + 1) % memory.len()],
+                    instruction.to_string(),
+                    hits
+                );
+                (marker, text)
+            }
+            Line::Data(byte) => (
+                '.',
+                format!("{:#05X}  {:02X}    DB {:#04X}", address, byte, byte),
+            ),
+        }
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(program: &[u8], steps: usize) -> (Coverage, Cpu) {
+        let mut cpu = Cpu::new();
+        cpu.memory[START..START + program.len()].copy_from_slice(program);
+        let mut coverage = Coverage::new();
+        for _ in 0..steps {
+            let (pc, i) = (cpu.pc, cpu.i);
+            cpu.step();
+            coverage.record(pc, i, &cpu);
+        }
+        (coverage, cpu)
+    }
+
+    // ld i, sprite; drw v0, v0, 1; se v0, 0; cls; jp self; sprite: 0xFF
+    const PROGRAM: [u8; 11] = [
+        0xA2, 0x0A, 0xD0, 0x01, 0x30, 0x00, 0x00, 0xE0, 0x12, 0x08, 0xFF,
+    ];
+
+    #[test]
+    fn separates_code_and_data() {
+        let (coverage, cpu) = run(&PROGRAM, 5);
+        assert_eq!(coverage.hits[0x208], 2);
+        assert_eq!(coverage.hits[0x206], 0);
+        assert!(coverage.data[0x20A]);
+        assert_eq!(coverage.branches[0x204], (1, 0));
+
+        let text = coverage.report_text(&cpu.memory, PROGRAM.len());
+        assert!(text.starts_with("4 of 5 instructions executed"));
+        assert!(text.contains("- 0x206  00E0  CLS"));
+        assert!(text.contains(". 0x20A  FF    DB 0xFF"));
+    }
+
+    #[test]
+    fn lcov() {
+        let (coverage, cpu) = run(&PROGRAM, 5);
+        let lcov = coverage.report_lcov(&cpu.memory, PROGRAM.len(), "test.ch8");
+        assert!(lcov.starts_with("TN:\nSF:test.ch8\nDA:512,1\n"));
+        assert!(lcov.contains("DA:518,0\n"));
+        assert!(lcov.contains("BRDA:516,0,0,1\nBRDA:516,0,1,0\n"));
+        assert!(lcov.ends_with("BRF:2\nBRH:1\nLF:5\nLH:4\nend_of_record\n"));
+
+        let html = coverage.report_html(&cpu.memory, PROGRAM.len(), "<test>");
+        assert!(html.contains("Coverage of &lt;test&gt;"));
+        assert!(html.contains("<span class=\"missed\">0x206"));
+    }
+
+    #[test]
+    fn instruction_at_end_of_memory() {
+        let mut cpu = Cpu::new();
+        cpu.memory[0x200..0x202].copy_from_slice(&[0x1F, 0xFF]);
+        cpu.memory[0xFFF] = 0x12;
+        cpu.memory[0] = 0x00;
+        let mut coverage = Coverage::new();
+        for _ in 0..2 {
+            let (pc, i) = (cpu.pc, cpu.i);
+            cpu.step();
+            coverage.record(pc, i, &cpu);
+        }
+        assert_eq!(coverage.hits[0xFFF], 1);
+
+        // a data byte shifts the listing to odd addresses
+        coverage.data[0x202] = true;
+        let text = coverage.report_text(&cpu.memory, 0x1000 - START);
+        assert!(text.contains("+ 0xFFF  1200  JP 0x200"));
+    }
+}
@@ -1,45 +1,38 @@
-#![allow(dead_code)]
 #![allow(non_snake_case)]
 
-use crate::chip8::{COL, FONTSET, ROW};
-use crate::graphics::Graphics;
-use rand::Rng;
+use crate::chip8::FONTSET;
+use crate::framebuffer::Framebuffer;
+use crate::options::Quirks;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::collections::hash_map::RandomState;
 use std::collections::HashMap;
-
-const KEYMAP: [simple::Key; 16] = [
-    simple::Key::A,
-    simple::Key::S,
-    simple::Key::D,
-    simple::Key::F,
-    simple::Key::Up,
-    simple::Key::Right,
-    simple::Key::Down,
-    simple::Key::Left,
-    simple::Key::Num1,
-    simple::Key::Num2,
-    simple::Key::Num3,
-    simple::Key::Num4,
-    simple::Key::Num5,
-    simple::Key::Num6,
-    simple::Key::Num7,
-    simple::Key::Num8,
-];
+use std::hash::{BuildHasher, Hasher};
 
 pub struct Cpu {
-    pub graphics: [u8; ROW * COL],
+    pub graphics: Framebuffer,
     pub memory: [u8; 4096],
     pub should_redraw: bool,
-    stack: [u16; 16],
-    sp: u16,
-    v: [u8; 16],
-    i: u16,
-    pc: u16,
-    keypad: [u8; 16],
-    delay_timer: u8,
-    sound_timer: u8,
+    pub quirks: Quirks,
+    pub pc: u16,
+    pub keypad: [u8; 16],
+    pub stack: [u16; 16],
+    pub sp: u16,
+    pub v: [u8; 16],
+    pub i: u16,
+    pub delay_timer: u8,
+    pub sound_timer: u8,
     opcode_function: HashMap<u16, OpcodeFunction>,
+    // the source of CXNN, seeded by the random keys of std's HashMap unless seed() is called
+    rng: StdRng,
+}
+pub type OpcodeFunction = fn(&mut Cpu, u16);
+
+impl Default for Cpu {
+    fn default() -> Cpu {
+        Cpu::new()
+    }
 }
-type OpcodeFunction = fn(&mut Cpu, u16);
 
 impl Cpu {
     pub fn new() -> Cpu {
@@ -68,15 +61,22 @@ impl Cpu {
             i: 0,
             pc: 0x200,
             v: [0; 16],
-            graphics: [0; ROW * COL],
+            graphics: Framebuffer::default(),
             keypad: [0; 16],
             delay_timer: 0,
             sound_timer: 0,
             opcode_function: map,
             should_redraw: false,
+            quirks: Quirks::default(),
+            rng: StdRng::seed_from_u64(RandomState::new().build_hasher().finish()),
         }
     }
 
+    // makes CXNN return the same sequence for the same seed
+    pub fn seed(&mut self, seed: u64) {
+        self.rng = StdRng::seed_from_u64(seed);
+    }
+
     pub fn load_font(&mut self) {
         for (i, &value) in FONTSET.iter().enumerate() {
             self.memory[i] = value;
@@ -85,9 +85,10 @@ impl Cpu {
 }
 
 impl Cpu {
+    // the address space is 4K, addresses past the end wrap around
     pub fn fetch_opcode(&mut self) -> u16 {
-        let left: u16 = self.memory[self.pc as usize].into();
-        let right: u16 = (self.memory[self.pc as usize + 1]).into();
+        let left: u16 = self.memory[self.pc as usize % 4096].into();
+        let right: u16 = (self.memory[(self.pc as usize + 1) % 4096]).into();
         let opcode: u16 = (left << 8) | right;
         self.pc += 2;
         opcode
@@ -100,17 +101,22 @@ impl Cpu {
 
         if self.sound_timer > 0 {
             self.sound_timer -= 1;
-            println!("some sound ...");
         }
     }
+
+    pub fn sound_active(&self) -> bool {
+        self.sound_timer > 0
+    }
+
+    pub fn step(&mut self) {
+        let opcode = self.fetch_opcode();
+        self.decode_and_execute(opcode);
+        self.pc &= 0xFFF;
+    }
 }
 
 impl Cpu {
-    pub fn decode_and_execute_graphic(&mut self, opcode: u16, graphics: Option<&mut Graphics>) {
-        if opcode & 0xF000 == 0xF000 && opcode & 0x00FF == 0x0A {
-            self.f_0xFX0A(opcode, &mut graphics.unwrap());
-            return;
-        }
+    pub fn decode_and_execute(&mut self, opcode: u16) {
         match self.opcode_function.get(&(opcode & 0xF000)) {
             Some(func) => func(self, opcode),
             None => {
@@ -118,36 +124,78 @@ impl Cpu {
             }
         }
     }
-    pub fn decode_and_execute(&mut self, opcode: u16) {
-        self.decode_and_execute_graphic(opcode, None);
-    }
 }
 
 impl Cpu {
+    // the function that executes an opcode, resolved once so it can be cached, see BlockCache
+    pub fn handler(opcode: u16) -> OpcodeFunction {
+        match opcode >> 12 {
+            0x0 => match opcode {
+                0x00E0 => |cpu, _| cpu.clear_screen(),
+                0x00EE => |cpu, _| cpu.return_from_subroutine(),
+                _ => Cpu::f_0x0000,
+            },
+            0x1 => Cpu::f_0x1000,
+            0x2 => Cpu::f_0x2000,
+            0x3 => Cpu::f_0x3000,
+            0x4 => Cpu::f_0x4000,
+            0x5 => Cpu::f_0x5000,
+            0x6 => Cpu::f_0x6000,
+            0x7 => Cpu::f_0x7000,
+            0x8 => match opcode & 0x000F {
+                0x0 => Cpu::f_0x8XY0,
+                0x1 => Cpu::f_0x8XY1,
+                0x2 => Cpu::f_0x8XY2,
+                0x3 => Cpu::f_0x8XY3,
+                0x4 => Cpu::f_0x8XY4,
+                0x5 => Cpu::f_0x8XY5,
+                0x6 => Cpu::f_0x8XY6,
+                0x7 => Cpu::f_0x8XY7,
+                0xE => Cpu::f_0x8XYE,
+                _ => Cpu::f_0x8000,
+            },
+            0x9 => Cpu::f_0x9000,
+            0xA => Cpu::f_0xA000,
+            0xB => Cpu::f_0xB000,
+            0xC => Cpu::f_0xC000,
+            0xD => Cpu::f_0xD000,
+            0xE => Cpu::f_0xE000,
+            _ => match opcode & 0x00FF {
+                0x07 => Cpu::f_0xFX07,
+                0x0A => Cpu::f_0xFX0A,
+                0x15 => Cpu::f_0xFX15,
+                0x18 => Cpu::f_0xFX18,
+                0x1E => Cpu::f_0xFX1E,
+                0x29 => Cpu::f_0xFX29,
+                0x33 => Cpu::f_0xFX33,
+                0x55 => Cpu::f_0xFX55,
+                0x65 => Cpu::f_0xFX65,
+                _ => Cpu::f_0xF000,
+            },
+        }
+    }
+
     pub fn f_0x0000(&mut self, opcode: u16) {
-        match opcode & 0x000F {
+        match opcode {
             // 0x00E0: Clears the screen
-            0x0000 => self.clear_screen(),
+            0x00E0 => self.clear_screen(),
 
             // 0x00EE: Returns from subroutine
-            0x000E => self.return_from_subroutine(),
-            _ => eprintln!("Opcode '{:#X}' not found", opcode),
+            0x00EE => self.return_from_subroutine(),
+
+            // 0x0NNN calls machine code on the COSMAC VIP, which isn't supported
+            _ => (),
         }
     }
 
     fn clear_screen(&mut self) {
-        self.graphics.fill(0);
+        self.graphics.clear();
     }
 
+    // the stack wraps around after 16 entries like the stack of the COSMAC VIP, which is
+    // just memory, so too many calls or returns don't stop the program
     fn return_from_subroutine(&mut self) {
-        if self.sp == 0 && self.stack[0] == 0 {
-            panic!("Stack is empty");
-        }
-
-        if self.sp > 0 {
-            self.sp -= 1;
-        }
-
+        self.sp = (self.sp + 15) % 16;
         self.pc = self.stack[self.sp as usize];
         self.stack[self.sp as usize] = 0;
     }
@@ -156,8 +204,6 @@ impl Cpu {
     fn f_0x1000(&mut self, opcode: u16) {
         let address = opcode & 0x0FFF;
 
-        println!("Jumping to address {:#X}", address);
-
         self.pc = address;
     }
 
@@ -166,7 +212,7 @@ impl Cpu {
         let address = opcode & 0x0FFF;
 
         self.stack[self.sp as usize] = self.pc;
-        self.sp += 1;
+        self.sp = (self.sp + 1) % 16;
 
         self.pc = address;
     }
@@ -193,6 +239,10 @@ impl Cpu {
 
     // 0x5XY0 skips the next instruction if v[X] == v[Y]
     fn f_0x5000(&mut self, opcode: u16) {
+        if opcode & 0x000F != 0 {
+            eprintln!("Opcode '{:#X}' not found", opcode);
+            return;
+        }
         let X = (opcode & 0x0F00) >> 8;
         let Y = (opcode & 0x00F0) >> 4;
 
@@ -203,16 +253,16 @@ impl Cpu {
 
     // 0x6XNN set value v[X] = NN
     fn f_0x6000(&mut self, opcode: u16) {
-        let index: u16 = ((opcode & 0x0F00) >> 8).try_into().unwrap();
+        let index: u16 = (opcode & 0x0F00) >> 8;
         let value: u8 = (opcode & 0x00FF).try_into().unwrap();
         self.v[index as usize] = value;
     }
 
     // 0x7XNN add NN to v[X]
     fn f_0x7000(&mut self, opcode: u16) {
-        let index: u16 = ((opcode & 0x0F00) >> 8).try_into().unwrap();
+        let index: u16 = (opcode & 0x0F00) >> 8;
         let value: u8 = (opcode & 0x00FF).try_into().unwrap();
-        self.v[index as usize] += value;
+        self.v[index as usize] = self.v[index as usize].wrapping_add(value);
     }
 
     // 0x8000
@@ -227,7 +277,7 @@ impl Cpu {
             6 => self.f_0x8XY6(opcode),
             7 => self.f_0x8XY7(opcode),
             0xE => self.f_0x8XYE(opcode),
-            _ => unreachable!(),
+            _ => eprintln!("Opcode '{:#X}' not found", opcode),
         }
     }
 
@@ -245,6 +295,9 @@ impl Cpu {
         let Y = (opcode & 0x00F0) >> 4;
 
         self.v[X as usize] |= self.v[Y as usize];
+        if self.quirks.logic {
+            self.v[0xF] = 0;
+        }
     }
 
     // 0x8XY2 sets v[X] = v[X] & v[Y]
@@ -253,6 +306,9 @@ impl Cpu {
         let Y = (opcode & 0x00F0) >> 4;
 
         self.v[X as usize] &= self.v[Y as usize];
+        if self.quirks.logic {
+            self.v[0xF] = 0;
+        }
     }
 
     // 0x8XY3 sets v[X] = v[X] ^ v[Y]
@@ -261,6 +317,9 @@ impl Cpu {
         let Y = (opcode & 0x00F0) >> 4;
 
         self.v[X as usize] ^= self.v[Y as usize];
+        if self.quirks.logic {
+            self.v[0xF] = 0;
+        }
     }
 
     // 0x8XY4 sets v[X] = v[X] + v[Y] and set v[0xF] to 1 if there is a carry
@@ -268,16 +327,19 @@ impl Cpu {
         let X = (opcode & 0x0F00) >> 8;
         let Y = (opcode & 0x00F0) >> 4;
 
-        let sum: u16 = self.v[X as usize] as u16 + self.v[Y as usize] as u16;
+        let (sum, carry) = self.v[X as usize].overflowing_add(self.v[Y as usize]);
+        self.set_with_flag(X, sum, carry as u8);
+    }
 
-        if sum > u8::MAX.into() {
-            self.v[X as usize] = (sum >> 8) as u8;
-            self.v[0xF] = 0x1;
-            return;
+    // writes the result and the flag of an arithmetic instruction, the order matters if X is 0xF
+    fn set_with_flag(&mut self, X: u16, result: u8, flag: u8) {
+        if self.quirks.vf_order {
+            self.v[0xF] = flag;
+            self.v[X as usize] = result;
+        } else {
+            self.v[X as usize] = result;
+            self.v[0xF] = flag;
         }
-
-        self.v[0xF] = 0x0;
-        self.v[X as usize] = sum as u8;
     }
 
     // 0x8XY5 sets v[X] = v[X] - v[Y] and set v[0xF] t0 0x0 if there is a borrow and to 0x1 if not
@@ -285,21 +347,21 @@ impl Cpu {
         let X = (opcode & 0x0F00) >> 8;
         let Y = (opcode & 0x00F0) >> 4;
 
-        if self.v[X as usize] >= self.v[Y as usize] {
-            self.v[0xF] = 0x1;
-        } else {
-            self.v[0xF] = 0x0;
-        }
-        self.v[X as usize] = self.v[X as usize].wrapping_add(self.v[Y as usize].wrapping_neg());
+        let flag = (self.v[X as usize] >= self.v[Y as usize]) as u8;
+        let result = self.v[X as usize].wrapping_add(self.v[Y as usize].wrapping_neg());
+        self.set_with_flag(X, result, flag);
     }
 
-    // 0x8XY6 stores the least significant bit of v[X] in v[0xF] then shifts v[X] to the right by 1
+    // 0x8XY6 stores the least significant bit of v[Y] in v[0xF] then sets v[X] = v[Y] >> 1
+    // with the shift quirk v[X] is used instead of v[Y]
     fn f_0x8XY6(&mut self, opcode: u16) {
         let X = (opcode & 0x0F00) >> 8;
         let Y = (opcode & 0x00F0) >> 4;
-        // store least significant bit of v[X] in v[0xF]
-        self.v[0xF] = self.v[X as usize] & 0x1;
-        self.v[X as usize] = self.v[Y as usize] >> 1;
+        let source = if self.quirks.shift { X } else { Y };
+
+        let value = self.v[source as usize];
+        self.v[0xF] = value & 0x1;
+        self.v[X as usize] = value >> 1;
     }
 
     // 0x8XY7 sets v[X] = v[Y] - v[X], and set v[0xF] to 0 if there is a borrow if not then 1
@@ -307,26 +369,30 @@ impl Cpu {
         let X = (opcode & 0x0F00) >> 8;
         let Y = (opcode & 0x00F0) >> 4;
 
-        if self.v[X as usize] > self.v[Y as usize] {
-            self.v[0xF] = 0x0;
-        } else {
-            self.v[0xF] = 0x1;
-        }
-        self.v[X as usize] = self.v[Y as usize].wrapping_add(self.v[X as usize].wrapping_neg());
+        let flag = (self.v[X as usize] <= self.v[Y as usize]) as u8;
+        let result = self.v[Y as usize].wrapping_add(self.v[X as usize].wrapping_neg());
+        self.set_with_flag(X, result, flag);
     }
 
     // 0x8XYE stores the MOST significant bit of v[Y] in v[0xF]
     // then sets v[X] to v[Y] <<= 1
+    // with the shift quirk v[X] is used instead of v[Y]
     fn f_0x8XYE(&mut self, opcode: u16) {
         let X = (opcode & 0x0F00) >> 8;
         let Y = (opcode & 0x00F0) >> 4;
+        let source = if self.quirks.shift { X } else { Y };
 
-        self.v[0xF] = (self.v[Y as usize] & 0b10000000) >> 7;
-        self.v[X as usize] = self.v[Y as usize].wrapping_shl(1);
+        let value = self.v[source as usize];
+        self.v[0xF] = (value & 0b10000000) >> 7;
+        self.v[X as usize] = value.wrapping_shl(1);
     }
 
     // 0x9XY0 skips the next instruction if v[X] != v[Y]
     fn f_0x9000(&mut self, opcode: u16) {
+        if opcode & 0x000F != 0 {
+            eprintln!("Opcode '{:#X}' not found", opcode);
+            return;
+        }
         let X = (opcode & 0x0F00) >> 8;
         let Y = (opcode & 0x00F0) >> 4;
 
@@ -341,68 +407,75 @@ impl Cpu {
     }
 
     // 0xBNNN jumps to address NNN + v[0]
+    // with the jump quirk this becomes 0xBXNN and jumps to XNN + v[X]
     fn f_0xB000(&mut self, opcode: u16) {
-        self.pc = (opcode & 0x0FFF) + self.v[0] as u16;
+        let X = if self.quirks.jump {
+            (opcode & 0x0F00) >> 8
+        } else {
+            0
+        };
+        self.pc = (opcode & 0x0FFF) + self.v[X as usize] as u16;
     }
 
     // 0xCXNN set v[X] to rand(1..255) & NN
     fn f_0xC000(&mut self, opcode: u16) {
         let X = (opcode & 0x0F00) >> 8;
         let NN = opcode & 0x00FF;
-        let r: u8 = rand::thread_rng().gen_range(1..=255);
+        let r: u8 = self.rng.gen_range(1..=255);
         self.v[X as usize] = r & NN as u8;
     }
 
     // 0xDXYN: draw sprite at coordinate X,Y with height of N
+    // the start position wraps around the screen, pixels past the edge wrap as well
+    // unless the clip quirk is set
     fn f_0xD000(&mut self, opcode: u16) {
-        let x: u16 = self.v[((opcode & 0x0F00) >> 8) as usize].into();
-        let y: u16 = self.v[((opcode & 0x00F0) >> 4) as usize].into();
-        let height: u16 = opcode & 0x000F;
-
-        self.v[0xF] = 0;
-
-        for yline in 0..height {
-            let pixel = self.memory[(self.i + yline) as usize];
-            for xline in 0..8 {
-                if pixel & (0x80 >> xline) != 0 {
-                    let index = x + xline + ((y + yline) * (COL as u16));
-                    if self.graphics[index as usize] == 1 {
-                        self.v[0xF] = 1;
-                    }
-                    self.graphics[index as usize] ^= 1;
-                }
-            }
+        let x = self.v[((opcode & 0x0F00) >> 8) as usize] as usize;
+        let y = self.v[((opcode & 0x00F0) >> 4) as usize] as usize;
+        let height = (opcode & 0x000F) as usize;
+
+        let mut sprite = [0; 15];
+        for (row, byte) in sprite[..height].iter_mut().enumerate() {
+            *byte = self.memory[(self.i as usize + row) % 4096];
         }
+        let collision = self
+            .graphics
+            .draw(0, x, y, &sprite[..height], self.quirks.clip);
+        self.v[0xF] = collision as u8;
         self.should_redraw = true;
     }
 
+    // 0xEX9E/0xEXA1 skip the next instruction if the key in v[X] is pressed or not
     fn f_0xE000(&mut self, opcode: u16) {
+        let X = (opcode & 0x0F00) >> 8;
         match opcode & 0x00FF {
             0x9E => {
                 // if key is pressed
-                if self.keypad[((opcode & 0x0F00) >> 8) as usize] != 0 {
+                if self.keypad[self.v[X as usize] as usize & 0xF] != 0 {
                     self.pc += 2;
                 }
             }
             0xA1 => {
                 // if key is not pressed
-                if self.keypad[((opcode & 0x0F00) >> 8) as usize] == 0 {
+                if self.keypad[self.v[X as usize] as usize & 0xF] == 0 {
                     self.pc += 2;
                 }
             }
-            _ => unreachable!(),
+            _ => eprintln!("Opcode '{:#X}' not found", opcode),
         }
     }
 
     fn f_0xF000(&mut self, opcode: u16) {
         match opcode & 0x00FF {
-            0x0A => todo!(),
+            0x0A => self.f_0xFX0A(opcode),
             0x07 => self.f_0xFX07(opcode),
             0x15 => self.f_0xFX15(opcode),
             0x18 => self.f_0xFX18(opcode),
             0x1E => self.f_0xFX1E(opcode),
             0x29 => self.f_0xFX29(opcode),
-            _ => unreachable!(),
+            0x33 => self.f_0xFX33(opcode),
+            0x55 => self.f_0xFX55(opcode),
+            0x65 => self.f_0xFX65(opcode),
+            _ => eprintln!("Opcode '{:#X}' not found", opcode),
         }
     }
 
@@ -413,20 +486,14 @@ impl Cpu {
     }
 
     // 0xFX0A waits for keyboard input, and sets the value into v[X]
-    fn f_0xFX0A(&mut self, opcode: u16, graphics: &mut Graphics) -> Option<usize> {
+    // the instruction is repeated until one of the keys is pressed
+    fn f_0xFX0A(&mut self, opcode: u16) {
         let X = (opcode & 0x0F00) >> 8;
 
-        while graphics.app.has_event() {
-            match graphics.app.next_event() {
-                simple::Event::Keyboard { is_down: true, key } => {
-                    let pos = KEYMAP.iter().position(|&k| k == key)?;
-                    self.keypad[X as usize] = pos as u8;
-                    println!("Found key at pos {} -> {:?}", pos, KEYMAP[pos]);
-                }
-                _ => (),
-            }
+        match self.keypad.iter().position(|&k| k != 0) {
+            Some(key) => self.v[X as usize] = key as u8,
+            None => self.pc -= 2,
         }
-        None
     }
 
     // 0xFX15 sets the delay timer to the value in v[X]
@@ -444,36 +511,82 @@ impl Cpu {
     // 0xFX1E adds v[X] to self.i
     fn f_0xFX1E(&mut self, opcode: u16) {
         let X = (opcode & 0x0F00) >> 8;
-        self.i += self.v[X as usize] as u16;
+        self.i = self.i.wrapping_add(self.v[X as usize] as u16);
     }
 
     // 0xFX29 sets self.i to the location of the sprite of the character at v[X]
     // Since the sprite sheet is located at the beginning of the memory and has a size of 4x5
-    // we can just set the value of v[X] * 5
+    // we can just set the value of v[X] * 5, only the low nibble of v[X] is used
     fn f_0xFX29(&mut self, opcode: u16) {
         let X = (opcode & 0x0F00) >> 8;
-        self.i = (self.v[X as usize] * 5) as u16;
+        self.i = (self.v[X as usize] & 0xF) as u16 * 5;
+    }
+
+    // 0xFX33 stores the decimal digits of v[X] at I, I + 1 and I + 2
+    fn f_0xFX33(&mut self, opcode: u16) {
+        let X = (opcode & 0x0F00) >> 8;
+        let value = self.v[X as usize];
+        for (offset, digit) in [value / 100, value / 10 % 10, value % 10]
+            .into_iter()
+            .enumerate()
+        {
+            self.memory[(self.i as usize + offset) % 4096] = digit;
+        }
+    }
+
+    // 0xFX55 stores v[0] to v[X] at I, then I is incremented by X + 1
+    // with the load_store quirk I stays unchanged
+    fn f_0xFX55(&mut self, opcode: u16) {
+        let X = (opcode & 0x0F00) >> 8;
+        for offset in 0..=X as usize {
+            self.memory[(self.i as usize + offset) % 4096] = self.v[offset];
+        }
+        if !self.quirks.load_store {
+            self.i = self.i.wrapping_add(X + 1);
+        }
+    }
+
+    // 0xFX65 loads v[0] to v[X] from I, then I is incremented by X + 1
+    // with the load_store quirk I stays unchanged
+    fn f_0xFX65(&mut self, opcode: u16) {
+        let X = (opcode & 0x0F00) >> 8;
+        for offset in 0..=X as usize {
+            self.v[offset] = self.memory[(self.i as usize + offset) % 4096];
+        }
+        if !self.quirks.load_store {
+            self.i = self.i.wrapping_add(X + 1);
+        }
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::chip8::{COL, ROW};
+
+    fn fill(graphics: &mut Framebuffer) {
+        for y in 0..ROW {
+            for x in (0..COL).step_by(8) {
+                graphics.draw(0, x, y, &[0xFF], false);
+            }
+        }
+    }
+
     #[test]
     fn clear_screen() {
         let mut chip = Cpu::new();
-        chip.graphics.fill(1);
+        fill(&mut chip.graphics);
         chip.decode_and_execute(0x00E0);
-        assert_eq!(chip.graphics, [0; ROW * COL]);
+        assert_eq!(chip.graphics.pixels(), [0; ROW * COL]);
     }
 
     #[test]
     fn clear_screen2() {
         let mut chip = Cpu::new();
-        chip.graphics.fill(1);
-        assert_eq!(chip.graphics, [1; ROW * COL]);
+        fill(&mut chip.graphics);
+        assert_eq!(chip.graphics.pixels(), [1; ROW * COL]);
         chip.decode_and_execute(0xE0);
-        assert_eq!(chip.graphics, [0; ROW * COL]);
+        assert_eq!(chip.graphics.pixels(), [0; ROW * COL]);
     }
 
     #[test]
@@ -626,7 +739,7 @@ mod tests {
 
         chip.decode_and_execute(0x8234);
 
-        assert_eq!(chip.v[2], 0x1);
+        assert_eq!(chip.v[2], 0x0);
         assert_eq!(chip.v[0xF], 0x1);
     }
 
@@ -666,8 +779,8 @@ mod tests {
         assert_eq!(chip.v[3], 0x1);
         assert_eq!(chip.v[0xF], 0x1);
 
-        chip.v[2] = 3;
-        chip.v[3] = 4;
+        chip.v[2] = 2;
+        chip.v[3] = 5;
         chip.decode_and_execute(0x8326);
         assert_eq!(chip.v[3], 0x1);
         assert_eq!(chip.v[0xF], 0x0);
@@ -712,6 +825,22 @@ mod tests {
         assert_eq!(chip.v[3], 0x0);
     }
 
+    #[test]
+    fn shift_quirk_0x8XY6_0x8XYE() {
+        let mut chip = Cpu::new();
+        chip.quirks.shift = true;
+
+        chip.v[3] = 5;
+        chip.v[2] = 0x80;
+        chip.decode_and_execute(0x8326);
+        assert_eq!(chip.v[3], 0x2);
+        assert_eq!(chip.v[0xF], 0x1);
+
+        chip.decode_and_execute(0x832E);
+        assert_eq!(chip.v[3], 0x4);
+        assert_eq!(chip.v[0xF], 0x0);
+    }
+
     #[test]
     fn skip_if_xy_not_equal_0x9000() {
         let mut chip = Cpu::new();
@@ -747,11 +876,22 @@ mod tests {
         assert_eq!(chip.pc, 0x80 + 0x80);
     }
 
+    #[test]
+    fn jump_quirk_0xBXNN() {
+        let mut chip = Cpu::new();
+        chip.quirks.jump = true;
+        chip.v[0] = 0x10;
+        chip.v[3] = 0x02;
+        chip.decode_and_execute(0xB300);
+        assert_eq!(chip.pc, 0x302);
+    }
+
     #[test]
     fn is_key_pressed_0xE000() {
         let mut chip = Cpu::new();
         assert_eq!(chip.pc, 0x200);
 
+        chip.v[3] = 3;
         chip.keypad[3] = 1;
         chip.decode_and_execute(0xE39E);
         assert_eq!(chip.pc, 0x202);
@@ -762,6 +902,7 @@ mod tests {
         let mut chip = Cpu::new();
         assert_eq!(chip.pc, 0x200);
 
+        chip.v[3] = 3;
         chip.keypad[3] = 1;
         chip.decode_and_execute(0xE3A1);
         assert_eq!(chip.pc, 0x200);
@@ -781,6 +922,21 @@ mod tests {
         assert_eq!(chip.v[10], 10);
     }
 
+    #[test]
+    fn wait_for_key_0xFX0A() {
+        let mut chip = Cpu::new();
+        chip.memory[0x200] = 0xF5;
+        chip.memory[0x201] = 0x0A;
+
+        chip.step();
+        assert_eq!(chip.pc, 0x200);
+
+        chip.keypad[0xB] = 1;
+        chip.step();
+        assert_eq!(chip.pc, 0x202);
+        assert_eq!(chip.v[5], 0xB);
+    }
+
     #[test]
     fn set_delay_timer_to_vx_0xFX15() {
         let mut chip = Cpu::new();
@@ -812,6 +968,83 @@ mod tests {
         assert_eq!(chip.i, 10);
     }
 
+    #[test]
+    fn vf_order_quirk_0x8XY4() {
+        let mut chip = Cpu::new();
+        chip.v[0xF] = 200;
+        chip.v[1] = 10;
+        chip.decode_and_execute(0x8F14);
+        assert_eq!(chip.v[0xF], 0);
+
+        chip.quirks.vf_order = true;
+        chip.v[0xF] = 200;
+        chip.decode_and_execute(0x8F14);
+        assert_eq!(chip.v[0xF], 210);
+    }
+
+    #[test]
+    fn wrapping_at_the_limits() {
+        let mut chip = Cpu::new();
+        chip.v[1] = 250;
+        chip.decode_and_execute(0x710A);
+        assert_eq!(chip.v[1], 4);
+
+        chip.v[1] = 0x42;
+        chip.decode_and_execute(0xF129);
+        assert_eq!(chip.i, 10);
+
+        // the sprite data wraps around to the start of the memory
+        chip.i = 0xFFF;
+        chip.memory[0xFFF] = 0x80;
+        chip.memory[0] = 0x90;
+        chip.v[0] = 0;
+        chip.decode_and_execute(0xD002);
+        assert_eq!(chip.graphics.pixel(0, 0), 1);
+        assert_eq!(chip.graphics.pixels()[COL..COL + 4], [1, 0, 0, 1]);
+
+        // jumps past the end of the memory wrap around as well
+        chip.quirks.jump = false;
+        chip.v[0] = 0xFF;
+        chip.memory[0x200..0x202].copy_from_slice(&[0xBF, 0xFF]);
+        chip.pc = 0x200;
+        chip.step();
+        assert_eq!(chip.pc, 0x0FE);
+
+        // 17 calls overwrite the first return address
+        for _ in 0..17 {
+            chip.decode_and_execute(0x2300);
+        }
+        assert_eq!(chip.sp, 1);
+        chip.decode_and_execute(0x00EE);
+        assert_eq!(chip.sp, 0);
+    }
+
+    #[test]
+    fn bcd_0xFX33() {
+        let mut chip = Cpu::new();
+        chip.i = 0x300;
+        chip.v[4] = 251;
+        chip.decode_and_execute(0xF433);
+        assert_eq!(chip.memory[0x300..0x303], [2, 5, 1]);
+        assert_eq!(chip.i, 0x300);
+    }
+
+    #[test]
+    fn store_and_load_0xFX55_0xFX65() {
+        let mut chip = Cpu::new();
+        chip.i = 0x300;
+        chip.v[..3].copy_from_slice(&[7, 8, 9]);
+        chip.decode_and_execute(0xF155);
+        assert_eq!(chip.memory[0x300..0x303], [7, 8, 0]);
+        assert_eq!(chip.i, 0x302);
+
+        chip.quirks.load_store = true;
+        chip.i = 0x300;
+        chip.decode_and_execute(0xF265);
+        assert_eq!(chip.v[..3], [7, 8, 0]);
+        assert_eq!(chip.i, 0x300);
+    }
+
     #[test]
     fn jump_to_sprite_pos_0xFX29() {
         let mut chip = Cpu::new();
@@ -0,0 +1,324 @@
+// Gym-style environment for using CHIP-8 games as reinforcement learning benchmarks.
+//
+// An episode starts with reset(seed), which reloads the ROM and seeds CXNN, so the same seed
+// and the same actions always give the same episode. step() holds the action keys for a
+// number of frames and returns the framebuffer, the reward and whether the episode is over.
+// Both come from rules on the memory of the game, which can be loaded from a TOML file:
+//
+//     max_frames = 18000      # optional, ends the episode after this many frames
+//
+//     [score]                 # the reward of a step is the increase of the score
+//     address = 0x3F0
+//     digits = 3              # BCD digits, one per byte as FX33 writes them; a plain byte
+//                             # if omitted
+//
+//     [[done]]                # the episode ends as soon as any condition holds
+//     address = 0x3F4
+//     equals = 0              # or not_equals, below, above
+use crate::chip8::{Chip8, COL, ROW};
+use crate::options::Options;
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+
+// one byte per pixel, row by row
+pub type Observation = [u8; ROW * COL];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Score {
+    Byte(u16),
+    // the address of the most significant digit and the number of digits
+    Bcd(u16, usize),
+}
+
+impl Score {
+    fn read(self, memory: &[u8]) -> i64 {
+        let byte = |address: usize| memory[address % memory.len()] as i64;
+        match self {
+            Score::Byte(address) => byte(address as usize),
+            Score::Bcd(address, digits) => (0..digits)
+                .map(|digit| byte(address as usize + digit))
+                .fold(0, |score, digit| score * 10 + digit),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compare {
+    Equal(u8),
+    NotEqual(u8),
+    Below(u8),
+    Above(u8),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Done {
+    pub address: u16,
+    pub compare: Compare,
+}
+
+impl Done {
+    fn holds(self, memory: &[u8]) -> bool {
+        let value = memory[self.address as usize % memory.len()];
+        match self.compare {
+            Compare::Equal(other) => value == other,
+            Compare::NotEqual(other) => value != other,
+            Compare::Below(other) => value < other,
+            Compare::Above(other) => value > other,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Rules {
+    pub score: Option<Score>,
+    pub done: Vec<Done>,
+    pub max_frames: Option<u32>,
+}
+
+impl Rules {
+    pub fn load(path: &Path) -> Result<Rules, Box<dyn Error>> {
+        let text = fs::read_to_string(path)?;
+        Rules::parse(&text).map_err(|err| format!("{}: {}", path.display(), err).into())
+    }
+
+    pub fn parse(text: &str) -> Result<Rules, Box<dyn Error>> {
+        let table: toml::Table = text.parse()?;
+        let mut rules = Rules::default();
+        if let Some(max_frames) = table.get("max_frames") {
+            rules.max_frames = Some(
+                max_frames
+                    .as_integer()
+                    .filter(|&frames| frames > 0 && frames <= u32::MAX as i64)
+                    .ok_or("'max_frames' has to be a positive number")? as u32,
+            );
+        }
+
+        match table.get("score") {
+            Some(toml::Value::Table(score)) => {
+                let address = number(score, "address", 0xFFF)? as u16;
+                rules.score = Some(match score.get("digits") {
+                    Some(_) => Score::Bcd(address, number(score, "digits", 10)? as usize),
+                    None => Score::Byte(address),
+                });
+            }
+            Some(_) => return Err("'score' has to be a table".into()),
+            None => (),
+        }
+
+        let list = match table.get("done") {
+            Some(toml::Value::Array(list)) => list.as_slice(),
+            Some(_) => return Err("'done' has to be an array of tables".into()),
+            None => &[],
+        };
+        for done in list {
+            let done = done
+                .as_table()
+                .ok_or("'done' has to be an array of tables")?;
+            let compare = [
+                ("equals", Compare::Equal as fn(u8) -> Compare),
+                ("not_equals", Compare::NotEqual),
+                ("below", Compare::Below),
+                ("above", Compare::Above),
+            ]
+            .into_iter()
+            .find(|(name, _)| done.contains_key(*name))
+            .ok_or("Every done condition needs one of equals, not_equals, below or above")?;
+            rules.done.push(Done {
+                address: number(done, "address", 0xFFF)? as u16,
+                compare: compare.1(number(done, compare.0, 0xFF)? as u8),
+            });
+        }
+        Ok(rules)
+    }
+}
+
+fn number(table: &toml::Table, name: &str, max: i64) -> Result<i64, Box<dyn Error>> {
+    table
+        .get(name)
+        .and_then(toml::Value::as_integer)
+        .filter(|&value| (0..=max).contains(&value))
+        .ok_or_else(|| format!("'{}' has to be a number between 0 and {}", name, max).into())
+}
+
+pub struct Env {
+    chip: Chip8,
+    rom: Vec<u8>,
+    options: Options,
+    rules: Rules,
+    score: i64,
+    frames: u32,
+    done: bool,
+}
+
+impl Env {
+    // the episode starts with the first reset()
+    pub fn new(rom: Vec<u8>, options: Options, rules: Rules) -> Result<Env, Box<dyn Error>> {
+        let mut env = Env {
+            chip: Chip8::new(),
+            rom,
+            options,
+            rules,
+            score: 0,
+            frames: 0,
+            done: true,
+        };
+        env.chip.load_rom(env.rom.clone())?;
+        Ok(env)
+    }
+
+    // starts a new episode from the power-on state
+    pub fn reset(&mut self, seed: u64) -> Observation {
+        self.chip = Chip8::new();
+        self.chip.set_options(self.options);
+        self.chip
+            .load_rom(self.rom.clone())
+            .expect("the size was checked by new()");
+        self.chip.seed(seed);
+        self.score = self.score();
+        self.frames = 0;
+        self.done = false;
+        self.chip.framebuffer()
+    }
+
+    // holds the keys, one bit per key, for frameskip frames and returns the observation,
+    // the increase of the score and whether the episode is over. The episode can end before
+    // all frames ran. Steps after the end do nothing.
+    pub fn step(&mut self, keys: u16, frameskip: u32) -> (Observation, f64, bool) {
+        if self.done {
+            return (self.chip.framebuffer(), 0.0, true);
+        }
+        self.chip.set_keypad(keys);
+        for _ in 0..frameskip.max(1) {
+            self.chip.run_frame();
+            self.frames += 1;
+            self.done = self.is_over();
+            if self.done {
+                break;
+            }
+        }
+        let score = self.score();
+        let reward = (score - self.score) as f64;
+        self.score = score;
+        (self.chip.framebuffer(), reward, self.done)
+    }
+
+    pub fn frames(&self) -> u32 {
+        self.frames
+    }
+
+    pub fn memory(&self) -> &[u8] {
+        self.chip.memory()
+    }
+
+    fn score(&self) -> i64 {
+        self.rules
+            .score
+            .map_or(0, |score| score.read(self.chip.memory()))
+    }
+
+    fn is_over(&self) -> bool {
+        let memory = self.chip.memory();
+        self.rules.done.iter().any(|done| done.holds(memory))
+            || self.rules.max_frames.is_some_and(|max| self.frames >= max)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::assemble;
+
+    const COUNTER: &str = include_str!("../tests/roms/counter.asm");
+
+    #[test]
+    fn rules() {
+        let rules = Rules::parse(
+            "max_frames = 100
+            [score]
+            address = 0x300
+            digits = 3
+            [[done]]
+            address = 0x301
+            equals = 1",
+        )
+        .unwrap();
+        assert_eq!(
+            rules,
+            Rules {
+                score: Some(Score::Bcd(0x300, 3)),
+                done: vec![Done {
+                    address: 0x301,
+                    compare: Compare::Equal(1),
+                }],
+                max_frames: Some(100),
+            }
+        );
+        assert!(Rules::parse("[[done]]\naddress = 1").is_err());
+        assert!(Rules::parse("[score]\naddress = 0x1000").is_err());
+    }
+
+    #[test]
+    fn reward_and_done() {
+        let rules = Rules::parse(
+            "[score]
+            address = 0x300
+            digits = 3
+            [[done]]
+            address = 0x301
+            equals = 1",
+        )
+        .unwrap();
+        let mut env = Env::new(assemble(COUNTER).unwrap(), Options::default(), rules).unwrap();
+        env.reset(0);
+        assert_eq!(env.step(0, 4).1, 0.0);
+        let (_, reward, done) = env.step(1 << 5, 4);
+        assert!(reward >= 3.0 && !done);
+
+        // the tens digit becomes 1 after 10 frames with the key held
+        let mut frames = 0;
+        while !env.step(1 << 5, 1).2 {
+            frames += 1;
+            assert!(frames < 20);
+        }
+        assert_eq!(Score::Bcd(0x300, 3).read(env.memory()), 10);
+        assert!(env.step(1 << 5, 1).2);
+
+        env.reset(0);
+        assert_eq!(env.frames(), 0);
+        assert!(!env.step(0, 1).2);
+    }
+
+    #[test]
+    fn same_seed_same_episode() {
+        let rom = assemble(
+            "
+            loop:   RND V0, 63
+                    RND V1, 31
+                    RND V2, 15
+                    LD F, V2
+                    DRW V0, V1, 5
+                    JP loop
+            ",
+        )
+        .unwrap();
+        let episode = |seed| {
+            let rules = Rules {
+                max_frames: Some(10),
+                ..Rules::default()
+            };
+            let mut env = Env::new(rom.clone(), Options::default(), rules).unwrap();
+            let mut observations = vec![env.reset(seed)];
+            loop {
+                let (observation, _, done) = env.step(0, 2);
+                observations.push(observation);
+                if done {
+                    return observations;
+                }
+            }
+        };
+        assert_eq!(episode(1).len(), 6);
+        assert!(episode(1) == episode(1));
+        assert!(episode(1) != episode(2));
+    }
+}
@@ -0,0 +1,206 @@
+// The display of the CHIP-8, one bit per pixel.
+//
+// Every row is a u64 with the leftmost pixel in the most significant bit, so a sprite row is
+// XORed into the display with a single shift or rotate. XO-CHIP has two planes, which
+// combine into four colors: a pixel's value has bit 0 set if it is lit in the first plane
+// and bit 1 if it is lit in the second. The area that changed since the last call of
+// take_dirty() is tracked, so frontends only have to repaint that part.
+use crate::chip8::{COL, ROW};
+
+pub const PLANES: usize = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rect {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+impl Rect {
+    pub const FULL: Rect = Rect {
+        x: 0,
+        y: 0,
+        width: COL,
+        height: ROW,
+    };
+
+    // the smallest rectangle containing both
+    pub fn union(self, other: Rect) -> Rect {
+        let x = self.x.min(other.x);
+        let y = self.y.min(other.y);
+        Rect {
+            x,
+            y,
+            width: (self.x + self.width).max(other.x + other.width) - x,
+            height: (self.y + self.height).max(other.y + other.height) - y,
+        }
+    }
+
+    // the coordinates of every pixel, row by row
+    pub fn pixels(self) -> impl Iterator<Item = (usize, usize)> {
+        (self.y..self.y + self.height)
+            .flat_map(move |y| (self.x..self.x + self.width).map(move |x| (x, y)))
+    }
+}
+
+// the union of two optional rectangles
+pub fn union(a: Option<Rect>, b: Option<Rect>) -> Option<Rect> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(a.union(b)),
+        (a, b) => a.or(b),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Framebuffer {
+    planes: [[u64; ROW]; PLANES],
+    dirty: Option<Rect>,
+}
+
+impl Default for Framebuffer {
+    fn default() -> Framebuffer {
+        Framebuffer {
+            planes: [[0; ROW]; PLANES],
+            dirty: Some(Rect::FULL),
+        }
+    }
+}
+
+impl Framebuffer {
+    pub fn clear(&mut self) {
+        self.planes = [[0; ROW]; PLANES];
+        self.mark(Rect::FULL);
+    }
+
+    pub fn pixel(&self, x: usize, y: usize) -> u8 {
+        let bit = 63 - x;
+        self.planes
+            .iter()
+            .enumerate()
+            .map(|(plane, rows)| ((rows[y] >> bit) as u8 & 1) << plane)
+            .sum()
+    }
+
+    // one byte per pixel, row by row, for the frontends and captures
+    pub fn pixels(&self) -> [u8; ROW * COL] {
+        let mut map = [0; ROW * COL];
+        for (i, pixel) in map.iter_mut().enumerate() {
+            *pixel = self.pixel(i % COL, i / COL);
+        }
+        map
+    }
+
+    // the rows of every plane, for save states
+    pub fn planes(&self) -> &[[u64; ROW]; PLANES] {
+        &self.planes
+    }
+
+    pub fn set_planes(&mut self, planes: [[u64; ROW]; PLANES]) {
+        self.planes = planes;
+        self.mark(Rect::FULL);
+    }
+
+    // XORs a sprite, one byte per row, into a plane at x, y. The sprite wraps around the
+    // edges of the screen unless clip is set. Returns whether a lit pixel was turned off.
+    pub fn draw(&mut self, plane: usize, x: usize, y: usize, sprite: &[u8], clip: bool) -> bool {
+        let (x, y) = (x % COL, y % ROW);
+        let mut collision = false;
+        // bounding box of the drawn pixels
+        let (mut columns, mut top, mut bottom) = (0u64, ROW, 0);
+        for (row, &byte) in sprite.iter().enumerate() {
+            if clip && y + row >= ROW {
+                break;
+            }
+            let line = (y + row) % ROW;
+            let bits = (byte as u64) << 56;
+            let bits = if clip {
+                bits >> x
+            } else {
+                bits.rotate_right(x as u32)
+            };
+            if bits == 0 {
+                continue;
+            }
+            collision |= self.planes[plane][line] & bits != 0;
+            self.planes[plane][line] ^= bits;
+            columns |= bits;
+            top = top.min(line);
+            bottom = bottom.max(line);
+        }
+        if columns != 0 {
+            let left = columns.leading_zeros() as usize;
+            let right = 63 - columns.trailing_zeros() as usize;
+            self.mark(Rect {
+                x: left,
+                y: top,
+                width: right - left + 1,
+                height: bottom - top + 1,
+            });
+        }
+        collision
+    }
+
+    fn mark(&mut self, rect: Rect) {
+        self.dirty = union(self.dirty, Some(rect));
+    }
+
+    // the area that changed since the last call, None if nothing did
+    pub fn take_dirty(&mut self) -> Option<Rect> {
+        self.dirty.take()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn draw_and_collide() {
+        let mut framebuffer = Framebuffer::default();
+        assert_eq!(framebuffer.take_dirty(), Some(Rect::FULL));
+        assert_eq!(framebuffer.take_dirty(), None);
+
+        assert!(!framebuffer.draw(0, 10, 3, &[0b1010_0000, 0b0100_0000], false));
+        assert_eq!(framebuffer.pixel(10, 3), 1);
+        assert_eq!(framebuffer.pixel(11, 3), 0);
+        assert_eq!(framebuffer.pixel(11, 4), 1);
+        assert_eq!(
+            framebuffer.take_dirty(),
+            Some(Rect {
+                x: 10,
+                y: 3,
+                width: 3,
+                height: 2
+            })
+        );
+
+        assert!(framebuffer.draw(0, 11, 4, &[0b1000_0000], false));
+        assert_eq!(framebuffer.pixel(11, 4), 0);
+        assert!(!framebuffer.draw(1, 10, 3, &[0b1000_0000], false));
+        assert_eq!(framebuffer.pixel(10, 3), 3);
+    }
+
+    #[test]
+    fn wrap_and_clip() {
+        let mut framebuffer = Framebuffer::default();
+        framebuffer.draw(0, 62, 31, &[0xF0, 0xF0], false);
+        assert_eq!(framebuffer.pixel(63, 31), 1);
+        assert_eq!(framebuffer.pixel(1, 0), 1);
+        assert_eq!(framebuffer.take_dirty(), Some(Rect::FULL));
+
+        framebuffer.clear();
+        framebuffer.take_dirty();
+        framebuffer.draw(0, 62, 31, &[0xF0, 0xF0], true);
+        assert_eq!(framebuffer.pixels().iter().filter(|&&p| p != 0).count(), 2);
+        assert_eq!(
+            framebuffer.take_dirty(),
+            Some(Rect {
+                x: 62,
+                y: 31,
+                width: 2,
+                height: 1
+            })
+        );
+    }
+}
@@ -0,0 +1,548 @@
+// Stub for the GDB Remote Serial Protocol, so debuggers like gdb or lldb can control the cpu.
+//
+// The registers are described by target.xml, in this order: v0-vf, i, pc, sp, dt (delay timer)
+// and st (sound timer). Values are transferred little endian. Breakpoints don't touch the
+// memory, the stub just checks the program counter after every instruction. Single steps are
+// executed by the machine like every other instruction, so the tools and hooks see them.
+//
+// Monitor commands ("monitor search changed" in gdb) control the cheats, see Cheats::command.
+use crate::cheat::Cheats;
+use crate::cpu::Cpu;
+use std::collections::HashSet;
+use std::io::{self, ErrorKind, Read, Write};
+use std::mem;
+use std::net::{TcpListener, TcpStream};
+
+const TARGET_XML: &str = r#"<?xml version="1.0"?>
+<!DOCTYPE target SYSTEM "gdb-target.dtd">
+<target version="1.0">
+  <feature name="org.rc8.chip8">
+    <reg name="v0" bitsize="8" regnum="0" type="uint8" group="general"/>
+    <reg name="v1" bitsize="8" type="uint8" group="general"/>
+    <reg name="v2" bitsize="8" type="uint8" group="general"/>
+    <reg name="v3" bitsize="8" type="uint8" group="general"/>
+    <reg name="v4" bitsize="8" type="uint8" group="general"/>
+    <reg name="v5" bitsize="8" type="uint8" group="general"/>
+    <reg name="v6" bitsize="8" type="uint8" group="general"/>
+    <reg name="v7" bitsize="8" type="uint8" group="general"/>
+    <reg name="v8" bitsize="8" type="uint8" group="general"/>
+    <reg name="v9" bitsize="8" type="uint8" group="general"/>
+    <reg name="va" bitsize="8" type="uint8" group="general"/>
+    <reg name="vb" bitsize="8" type="uint8" group="general"/>
+    <reg name="vc" bitsize="8" type="uint8" group="general"/>
+    <reg name="vd" bitsize="8" type="uint8" group="general"/>
+    <reg name="ve" bitsize="8" type="uint8" group="general"/>
+    <reg name="vf" bitsize="8" type="uint8" group="general"/>
+    <reg name="i" bitsize="16" type="data_ptr" group="general"/>
+    <reg name="pc" bitsize="16" type="code_ptr" group="general"/>
+    <reg name="sp" bitsize="8" type="uint8" group="general"/>
+    <reg name="dt" bitsize="8" type="uint8" group="timers"/>
+    <reg name="st" bitsize="8" type="uint8" group="timers"/>
+  </feature>
+</target>
+"#;
+
+const REGISTERS: usize = 21;
+
+// SIGTRAP, reported whenever the cpu stops
+const STOP_REPLY: &str = "S05";
+
+// the protocol state, independent of the connection
+#[derive(Debug, Default)]
+struct Session {
+    breakpoints: HashSet<u16>,
+    running: bool,
+    // a single step to execute before the stop reply
+    stepping: bool,
+    no_ack: bool,
+    detached: bool,
+}
+
+pub struct GdbStub {
+    stream: TcpStream,
+    session: Session,
+    buffer: Vec<u8>,
+}
+
+impl GdbStub {
+    // blocks until a debugger connects, the cpu starts out stopped
+    pub fn listen(port: u16) -> io::Result<GdbStub> {
+        let listener = TcpListener::bind(("127.0.0.1", port))?;
+        println!("Waiting for a debugger on localhost:{}", port);
+        let (stream, address) = listener.accept()?;
+        println!("Debugger connected from {}", address);
+
+        stream.set_nonblocking(true)?;
+        stream.set_nodelay(true)?;
+        Ok(GdbStub {
+            stream,
+            session: Session::default(),
+            buffer: Vec::new(),
+        })
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.session.running
+    }
+
+    // handles everything the debugger sent, returns false once it detached
+    pub fn poll(&mut self, cpu: &mut Cpu, cheats: &mut Cheats) -> io::Result<bool> {
+        let mut data = [0; 4096];
+        loop {
+            match self.stream.read(&mut data) {
+                Ok(0) => return Ok(false),
+                Ok(len) => self.buffer.extend_from_slice(&data[..len]),
+                Err(err) if err.kind() == ErrorKind::WouldBlock => break,
+                Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+                Err(err) => return Err(err),
+            }
+        }
+
+        // the debugger waits for the stop reply of a step before it sends anything else
+        while !self.session.stepping {
+            let Some((packet, rest)) = next_packet(&self.buffer) else {
+                break;
+            };
+            self.buffer = rest;
+            match packet {
+                Packet::Interrupt => {
+                    if self.session.running {
+                        self.session.running = false;
+                        self.send(STOP_REPLY)?;
+                    }
+                }
+                Packet::Ack => (),
+                Packet::Invalid => self.write(b"-")?,
+                Packet::Command(command) => {
+                    if !self.session.no_ack {
+                        self.write(b"+")?;
+                    }
+                    if let Some(reply) = self.session.handle(&command, cpu, cheats) {
+                        self.send(&reply)?;
+                    }
+                }
+            }
+        }
+        Ok(!self.session.detached)
+    }
+
+    // whether the debugger asked for a single step, stepped() reports it as done
+    pub fn take_step(&mut self) -> bool {
+        mem::take(&mut self.session.stepping)
+    }
+
+    pub fn stepped(&mut self) -> io::Result<()> {
+        self.send(STOP_REPLY)
+    }
+
+    // called after every executed instruction while running
+    pub fn check_breakpoint(&mut self, cpu: &Cpu) -> io::Result<()> {
+        if self.session.running && self.session.breakpoints.contains(&cpu.pc) {
+            self.session.running = false;
+            self.send(STOP_REPLY)?;
+        }
+        Ok(())
+    }
+
+    fn send(&mut self, data: &str) -> io::Result<()> {
+        let packet = format!("${}#{:02x}", data, checksum(data.as_bytes()));
+        self.write(packet.as_bytes())
+    }
+
+    fn write(&mut self, data: &[u8]) -> io::Result<()> {
+        self.stream.set_nonblocking(false)?;
+        let result = self.stream.write_all(data);
+        self.stream.set_nonblocking(true)?;
+        result
+    }
+}
+
+impl Session {
+    // returns the reply, None if the debugger doesn't expect one right away
+    fn handle(&mut self, command: &str, cpu: &mut Cpu, cheats: &mut Cheats) -> Option<String> {
+        let reply = match command.split_at(command.len().min(1)) {
+            ("?", _) => STOP_REPLY.to_string(),
+            ("g", _) => (0..REGISTERS).map(|n| read_register(cpu, n)).collect(),
+            ("G", data) => {
+                let mut data = data;
+                for n in 0..REGISTERS {
+                    let size = register_size(n) * 2;
+                    if data.len() < size || write_register(cpu, n, &data[..size]).is_none() {
+                        return Some("E01".to_string());
+                    }
+                    data = &data[size..];
+                }
+                "OK".to_string()
+            }
+            ("p", n) => match usize::from_str_radix(n, 16) {
+                Ok(n) if n < REGISTERS => read_register(cpu, n),
+                _ => "E01".to_string(),
+            },
+            ("P", args) => {
+                let (n, value) = args.split_once('=').unwrap_or_default();
+                match usize::from_str_radix(n, 16) {
+                    Ok(n) if n < REGISTERS => match write_register(cpu, n, value) {
+                        Some(()) => "OK".to_string(),
+                        None => "E01".to_string(),
+                    },
+                    _ => "E01".to_string(),
+                }
+            }
+            ("m", args) => match parse_range(args) {
+                Some((address, len)) => cpu.memory[address..address + len]
+                    .iter()
+                    .map(|b| format!("{:02x}", b))
+                    .collect(),
+                None => "E01".to_string(),
+            },
+            ("M", args) => {
+                let (range, data) = args.split_once(':').unwrap_or_default();
+                match (parse_range(range), decode_hex(data)) {
+                    (Some((address, len)), Some(bytes)) if bytes.len() == len => {
+                        cpu.memory[address..address + len].copy_from_slice(&bytes);
+                        "OK".to_string()
+                    }
+                    _ => "E01".to_string(),
+                }
+            }
+            ("Z" | "z", args) => {
+                let (kind, rest) = args.split_once(',').unwrap_or_default();
+                let address = rest.split(',').next().unwrap_or_default();
+                // software and hardware breakpoints are the same thing here
+                match (kind, u16::from_str_radix(address, 16)) {
+                    ("0" | "1", Ok(address)) => {
+                        if command.starts_with('Z') {
+                            self.breakpoints.insert(address);
+                        } else {
+                            self.breakpoints.remove(&address);
+                        }
+                        "OK".to_string()
+                    }
+                    ("0" | "1", Err(_)) => "E01".to_string(),
+                    // watchpoints aren't supported
+                    _ => String::new(),
+                }
+            }
+            ("s", _) => {
+                self.stepping = true;
+                return None;
+            }
+            ("c", _) => {
+                self.running = true;
+                return None;
+            }
+            ("D", _) => {
+                self.running = true;
+                self.detached = true;
+                "OK".to_string()
+            }
+            ("k", _) => {
+                self.detached = true;
+                return None;
+            }
+            ("H", _) => "OK".to_string(),
+            _ => match command.strip_prefix("qRcmd,") {
+                Some(hex) => monitor(hex, cpu, cheats),
+                None => self.query(command),
+            },
+        };
+        Some(reply)
+    }
+
+    fn query(&mut self, command: &str) -> String {
+        if command.starts_with("qSupported") {
+            "PacketSize=4000;qXfer:features:read+;QStartNoAckMode+;swbreak+".to_string()
+        } else if command == "QStartNoAckMode" {
+            self.no_ack = true;
+            "OK".to_string()
+        } else if let Some(args) = command.strip_prefix("qXfer:features:read:target.xml:") {
+            let (offset, len) = args.split_once(',').unwrap_or_default();
+            match (
+                usize::from_str_radix(offset, 16),
+                usize::from_str_radix(len, 16),
+            ) {
+                (Ok(offset), Ok(len)) => {
+                    let start = offset.min(TARGET_XML.len());
+                    // the client picks both, so neither may overflow or slice backwards
+                    let end = offset.saturating_add(len).clamp(start, TARGET_XML.len());
+                    let marker = if end == TARGET_XML.len() { 'l' } else { 'm' };
+                    format!("{}{}", marker, &TARGET_XML[start..end])
+                }
+                _ => "E01".to_string(),
+            }
+        } else if command == "qAttached" {
+            "1".to_string()
+        } else if command == "qC" {
+            "QC1".to_string()
+        } else if command == "qfThreadInfo" {
+            "m1".to_string()
+        } else if command == "qsThreadInfo" {
+            "l".to_string()
+        } else {
+            // an empty reply tells the debugger that the command is not supported
+            String::new()
+        }
+    }
+}
+
+// runs a monitor command, the command and its output are hex encoded
+fn monitor(hex: &str, cpu: &mut Cpu, cheats: &mut Cheats) -> String {
+    let line = match decode_hex(hex) {
+        Some(bytes) => String::from_utf8_lossy(&bytes).into_owned(),
+        None => return "E01".to_string(),
+    };
+    let output = cheats
+        .command(&line, &cpu.memory)
+        .unwrap_or_else(|err| format!("{}\n", err));
+    output.bytes().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn register_size(n: usize) -> usize {
+    match n {
+        16 | 17 => 2,
+        _ => 1,
+    }
+}
+
+fn read_register(cpu: &Cpu, n: usize) -> String {
+    match n {
+        0..=15 => format!("{:02x}", cpu.v[n]),
+        16 => format!("{:02x}{:02x}", cpu.i as u8, cpu.i >> 8),
+        17 => format!("{:02x}{:02x}", cpu.pc as u8, cpu.pc >> 8),
+        18 => format!("{:02x}", cpu.sp),
+        19 => format!("{:02x}", cpu.delay_timer),
+        _ => format!("{:02x}", cpu.sound_timer),
+    }
+}
+
+fn write_register(cpu: &mut Cpu, n: usize, hex: &str) -> Option<()> {
+    let bytes = decode_hex(hex)?;
+    if bytes.len() != register_size(n) {
+        return None;
+    }
+    let word = || u16::from_le_bytes([bytes[0], bytes[1]]);
+    match n {
+        0..=15 => cpu.v[n] = bytes[0],
+        16 => cpu.i = word(),
+        17 => cpu.pc = word() & 0xFFF,
+        18 if (bytes[0] as usize) < cpu.stack.len() => cpu.sp = bytes[0] as u16,
+        18 => return None,
+        19 => cpu.delay_timer = bytes[0],
+        _ => cpu.sound_timer = bytes[0],
+    }
+    Some(())
+}
+
+// "addr,length" within the memory of the cpu
+fn parse_range(args: &str) -> Option<(usize, usize)> {
+    let (address, len) = args.split_once(',')?;
+    let address = usize::from_str_radix(address, 16).ok()?;
+    let len = usize::from_str_radix(len, 16).ok()?;
+    if address.checked_add(len)? > 4096 {
+        return None;
+    }
+    Some((address, len))
+}
+
+pub fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+fn checksum(data: &[u8]) -> u8 {
+    data.iter().fold(0, |sum, &b| sum.wrapping_add(b))
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Packet {
+    Command(String),
+    Interrupt,
+    Ack,
+    Invalid,
+}
+
+// splits the first packet off the received data, None if it isn't complete yet
+fn next_packet(data: &[u8]) -> Option<(Packet, Vec<u8>)> {
+    match *data.first()? {
+        0x03 => return Some((Packet::Interrupt, data[1..].to_vec())),
+        b'+' | b'-' => return Some((Packet::Ack, data[1..].to_vec())),
+        b'$' => (),
+        // garbage between packets
+        _ => return Some((Packet::Ack, data[1..].to_vec())),
+    }
+
+    let end = data.iter().position(|&b| b == b'#')?;
+    if data.len() < end + 3 {
+        return None;
+    }
+    let body = &data[1..end];
+    let rest = data[end + 3..].to_vec();
+
+    let expected = std::str::from_utf8(&data[end + 1..end + 3])
+        .ok()
+        .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+    if expected != Some(checksum(body)) {
+        return Some((Packet::Invalid, rest));
+    }
+    Some((Packet::Command(unescape(body)), rest))
+}
+
+// '}' escapes the following byte, which is XORed with 0x20
+fn unescape(body: &[u8]) -> String {
+    let mut out = Vec::with_capacity(body.len());
+    let mut bytes = body.iter();
+    while let Some(&b) = bytes.next() {
+        match b {
+            b'}' => out.push(bytes.next().map_or(0, |b| b ^ 0x20)),
+            b => out.push(b),
+        }
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_packets() {
+        let data = b"+$g#67\x03$m200,2#ff$c";
+        let (packet, rest) = next_packet(data).unwrap();
+        assert_eq!(packet, Packet::Ack);
+        let (packet, rest) = next_packet(&rest).unwrap();
+        assert_eq!(packet, Packet::Command("g".to_string()));
+        let (packet, rest) = next_packet(&rest).unwrap();
+        assert_eq!(packet, Packet::Interrupt);
+        let (packet, rest) = next_packet(&rest).unwrap();
+        assert_eq!(packet, Packet::Invalid);
+        assert_eq!(next_packet(&rest), None);
+    }
+
+    #[test]
+    fn registers() {
+        let mut session = Session::default();
+        let mut cpu = Cpu::new();
+        let mut cheats = Cheats::default();
+        cpu.v[0] = 0x12;
+        cpu.v[0xF] = 0x01;
+        cpu.i = 0x0345;
+
+        let reply = session.handle("g", &mut cpu, &mut cheats).unwrap();
+        assert_eq!(&reply[..2], "12");
+        assert_eq!(&reply[30..32], "01");
+        assert_eq!(&reply[32..40], "45030002");
+
+        assert_eq!(
+            session.handle("P11=2003", &mut cpu, &mut cheats).unwrap(),
+            "OK"
+        );
+        assert_eq!(cpu.pc, 0x320);
+        assert_eq!(
+            session.handle("p11", &mut cpu, &mut cheats).unwrap(),
+            "2003"
+        );
+        assert_eq!(session.handle("p15", &mut cpu, &mut cheats).unwrap(), "E01");
+    }
+
+    #[test]
+    fn memory() {
+        let mut session = Session::default();
+        let mut cpu = Cpu::new();
+        let mut cheats = Cheats::default();
+
+        assert_eq!(
+            session
+                .handle("M200,2:00e0", &mut cpu, &mut cheats)
+                .unwrap(),
+            "OK"
+        );
+        assert_eq!(cpu.memory[0x201], 0xE0);
+        assert_eq!(
+            session.handle("m1ff,3", &mut cpu, &mut cheats).unwrap(),
+            "0000e0"
+        );
+        assert_eq!(
+            session.handle("mfff,2", &mut cpu, &mut cheats).unwrap(),
+            "E01"
+        );
+    }
+
+    #[test]
+    fn breakpoints_and_stepping() {
+        let mut session = Session::default();
+        let mut cpu = Cpu::new();
+        let mut cheats = Cheats::default();
+        cpu.memory[0x200] = 0x60;
+        cpu.memory[0x201] = 0x05;
+
+        assert_eq!(
+            session.handle("Z0,204,2", &mut cpu, &mut cheats).unwrap(),
+            "OK"
+        );
+        assert!(session.breakpoints.contains(&0x204));
+        // the machine executes the step
+        assert_eq!(session.handle("s", &mut cpu, &mut cheats), None);
+        assert!(session.stepping);
+        assert_eq!(cpu.pc, 0x200);
+
+        assert_eq!(session.handle("c", &mut cpu, &mut cheats), None);
+        assert!(session.running);
+        assert_eq!(
+            session.handle("z0,204,2", &mut cpu, &mut cheats).unwrap(),
+            "OK"
+        );
+        assert!(session.breakpoints.is_empty());
+    }
+
+    #[test]
+    fn target_description() {
+        let mut session = Session::default();
+        let mut cpu = Cpu::new();
+        let mut cheats = Cheats::default();
+        let reply = session
+            .handle("qXfer:features:read:target.xml:0,10", &mut cpu, &mut cheats)
+            .unwrap();
+        assert_eq!(reply, "m<?xml version=\"1");
+
+        let reply = session
+            .handle(
+                "qXfer:features:read:target.xml:10,ffff",
+                &mut cpu,
+                &mut cheats,
+            )
+            .unwrap();
+        assert!(reply.starts_with("l.0\"?>"));
+        assert!(reply.ends_with("</target>\n"));
+
+        for args in [
+            "ffffffffffffffff,1",
+            "1,ffffffffffffffff",
+            "ffffffffffffffff,0",
+        ] {
+            let packet = format!("qXfer:features:read:target.xml:{}", args);
+            let reply = session.handle(&packet, &mut cpu, &mut cheats).unwrap();
+            assert!(reply.starts_with('l'), "{}", args);
+        }
+    }
+
+    #[test]
+    fn monitor_commands() {
+        let mut session = Session::default();
+        let mut cpu = Cpu::new();
+        let mut cheats = Cheats::default();
+        let command = |line: &str| {
+            let hex: String = line.bytes().map(|b| format!("{:02x}", b)).collect();
+            format!("qRcmd,{}", hex)
+        };
+
+        let reply = session.handle(&command("freeze 0x300 7"), &mut cpu, &mut cheats);
+        let output = decode_hex(&reply.unwrap()).unwrap();
+        assert_eq!(String::from_utf8(output).unwrap(), "Froze 0x300 to 7\n");
+        assert_eq!(cheats.cheats().len(), 1);
+    }
+}
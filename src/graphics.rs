@@ -1,26 +1,198 @@
-use crate::chip8::{COL, ROW};
+use crate::chip8::{Hotkey, COL, ROW};
+use crate::cpu::Cpu;
+use crate::framebuffer::Rect;
+use crate::keymap::Keymap;
+use crate::options::{rgb, Options, Palette};
+use crate::overlay::{self, KEYPAD};
+use crate::phosphor::blend;
+
+// the height of a line of the default font with a margin
+const NOTICE_HEIGHT: i32 = 18;
+
+// the debug overlay right of the screen, the text on the left and the keypad on the right
+const PANEL_WIDTH: usize = 380;
+const LINE_HEIGHT: usize = 16;
+const MARGIN: usize = 8;
+const KEY_SIZE: usize = 24;
 
 pub struct Graphics {
     pub app: simple::Window,
+    palette: Palette,
+    scale: usize,
+    // host key and the CHIP-8 key it is bound to
+    keys: Vec<(simple::Key, usize)>,
+    // whether the overlay is shown, None without room for it
+    overlay: Option<bool>,
+    height: usize,
 }
 
 impl Graphics {
-    pub fn new() -> Graphics {
+    pub fn new(options: &Options, keymap: &Keymap) -> Graphics {
+        let scale = options.scale as usize;
+        let keys = bindings(keymap);
+
+        // the scale is at most MAX_SCALE, but the overlay may not fit next to the screen
+        let overlay = options.overlay && COL * scale + PANEL_WIDTH <= u16::MAX as usize;
+        if options.overlay && !overlay {
+            eprintln!("There is no room for the overlay at scale {}", scale);
+        }
+        let (width, height) = if overlay {
+            let panel = 2 * MARGIN + overlay::LINES * LINE_HEIGHT;
+            (COL * scale + PANEL_WIDTH, (ROW * scale).max(panel))
+        } else {
+            (COL * scale, ROW * scale)
+        };
         Graphics {
-            app: simple::Window::new("Chip8", (COL * 10) as u16, (ROW * 10) as u16),
+            app: simple::Window::new("Chip8", width as u16, height as u16),
+            palette: options.palette,
+            scale,
+            keys,
+            overlay: overlay.then_some(true),
+            height,
         }
     }
-    pub fn draw(&mut self, map: &[u8; ROW * COL]) {
-        for (i, &value) in map.iter().enumerate() {
+    // e.g. the keymap of the ROM --watch reloaded
+    pub fn set_keymap(&mut self, keymap: &Keymap) {
+        self.keys = bindings(keymap);
+    }
+
+    // map holds the brightness of each pixel, see Phosphor. Only the pixels in rect are
+    // repainted, the rest of the window keeps the last frame.
+    pub fn draw(&mut self, map: &[u8; ROW * COL], rect: Rect) {
+        let (r, g, b) = rgb(self.palette.background);
+        self.app.set_color(r, g, b, 255);
+        self.app
+            .fill_rect(self.scaled(rect.x, rect.y, rect.width, rect.height));
+
+        for (x, y) in rect.pixels() {
+            let value = map[x + y * COL];
             if value == 0 {
                 continue;
             }
-            let x = i % COL;
-            let y = i / ROW;
+            let (r, g, b) = rgb(blend(self.palette.background, self.palette.fill, value));
+            self.app.set_color(r, g, b, 255);
+            let r = self.scaled(x, y, 1, 1);
+            self.app.fill_rect(r);
+        }
+    }
 
-            let r = simple::Point::new(x as i32, y as i32);
-            self.app.draw_point(r);
-            self.app.set_color(255, 255, 255, 255);
+    // a line of text over the bottom of the screen, which stays until the pixels below it are
+    // drawn again
+    pub fn notice(&mut self, text: &str) {
+        let top = (ROW * self.scale) as i32 - NOTICE_HEIGHT;
+        self.app.set_color(0, 0, 0, 255);
+        self.app.fill_rect(simple::Rect::new(
+            0,
+            top,
+            (COL * self.scale) as u32,
+            NOTICE_HEIGHT as u32,
+        ));
+        self.app.set_color(255, 255, 255, 255);
+        self.app.print(text, 4, top + 1);
+    }
+
+    pub fn toggle_overlay(&mut self) {
+        if let Some(shown) = &mut self.overlay {
+            *shown = !*shown;
         }
     }
+
+    // the state of the cpu next to the screen, see overlay.rs
+    pub fn draw_overlay(&mut self, cpu: &Cpu) {
+        let Some(shown) = self.overlay else {
+            return;
+        };
+        let left = COL * self.scale;
+        self.app.set_color(24, 24, 24, 255);
+        self.app.fill_rect(simple::Rect::new(
+            left as i32,
+            0,
+            PANEL_WIDTH as u32,
+            self.height as u32,
+        ));
+        if !shown {
+            return;
+        }
+
+        let (r, g, b) = rgb(self.palette.fill);
+        for (row, line) in overlay::lines(cpu).iter().enumerate() {
+            if line.current {
+                self.app.set_color(r, g, b, 255);
+            } else {
+                self.app.set_color(220, 220, 220, 255);
+            }
+            let y = MARGIN + row * LINE_HEIGHT;
+            self.app.print(&line.text, (left + MARGIN) as i32, y as i32);
+        }
+
+        let keypad = left + PANEL_WIDTH - MARGIN - 4 * KEY_SIZE;
+        for (row, keys) in KEYPAD.iter().enumerate() {
+            for (column, &key) in keys.iter().enumerate() {
+                let x = (keypad + column * KEY_SIZE) as i32;
+                let y = (MARGIN + row * KEY_SIZE) as i32;
+                let pressed = cpu.keypad[key] != 0;
+                if pressed {
+                    self.app.set_color(r, g, b, 255);
+                } else {
+                    self.app.set_color(64, 64, 64, 255);
+                }
+                let size = KEY_SIZE as u32 - 2;
+                self.app
+                    .fill_rect(simple::Rect::new(x + 1, y + 1, size, size));
+                if pressed {
+                    self.app.set_color(0, 0, 0, 255);
+                } else {
+                    self.app.set_color(220, 220, 220, 255);
+                }
+                self.app.print(&format!("{:X}", key), x + 8, y + 4);
+            }
+        }
+    }
+
+    fn scaled(&self, x: usize, y: usize, width: usize, height: usize) -> simple::Rect {
+        simple::Rect::new(
+            (x * self.scale) as i32,
+            (y * self.scale) as i32,
+            (width * self.scale) as u32,
+            (height * self.scale) as u32,
+        )
+    }
+
+    // drains the event queue of the window
+    pub fn hotkeys(&mut self) -> Vec<Hotkey> {
+        let mut hotkeys = Vec::new();
+        while self.app.has_event() {
+            if let simple::Event::Keyboard { is_down: true, key } = self.app.next_event() {
+                match key {
+                    simple::Key::F12 => hotkeys.push(Hotkey::Screenshot),
+                    simple::Key::F1 => hotkeys.push(Hotkey::Overlay),
+                    _ => {}
+                }
+            }
+        }
+        hotkeys
+    }
+
+    pub fn update_keypad(&self, keypad: &mut [u8; 16]) {
+        keypad.fill(0);
+        for &(code, key) in &self.keys {
+            if self.app.is_key_down(code) {
+                keypad[key] = 1;
+            }
+        }
+    }
+}
+
+// host key and CHIP-8 key of every binding simple knows
+fn bindings(keymap: &Keymap) -> Vec<(simple::Key, usize)> {
+    keymap
+        .bindings()
+        .filter_map(|(name, key)| match simple::Key::from_name(name) {
+            Some(code) => Some((code, key)),
+            None => {
+                eprintln!("Unknown key '{}' in keymap", name);
+                None
+            }
+        })
+        .collect()
 }
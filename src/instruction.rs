@@ -0,0 +1,312 @@
+// Decoded CHIP-8 instructions, displayed in the mnemonics of Cowgod's technical reference.
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Instruction {
+    // 0NNN, a machine code routine of the COSMAC VIP
+    Sys(u16),
+    // 00E0
+    Clear,
+    // 00EE
+    Return,
+    // 1NNN
+    Jump(u16),
+    // 2NNN
+    Call(u16),
+    // 3XNN
+    SkipEqual(u8, u8),
+    // 4XNN
+    SkipNotEqual(u8, u8),
+    // 5XY0
+    SkipEqualRegister(u8, u8),
+    // 6XNN
+    Load(u8, u8),
+    // 7XNN
+    Add(u8, u8),
+    // 8XY0
+    Move(u8, u8),
+    // 8XY1
+    Or(u8, u8),
+    // 8XY2
+    And(u8, u8),
+    // 8XY3
+    Xor(u8, u8),
+    // 8XY4
+    AddRegister(u8, u8),
+    // 8XY5
+    Sub(u8, u8),
+    // 8XY6
+    ShiftRight(u8, u8),
+    // 8XY7
+    SubReverse(u8, u8),
+    // 8XYE
+    ShiftLeft(u8, u8),
+    // 9XY0
+    SkipNotEqualRegister(u8, u8),
+    // ANNN
+    LoadIndex(u16),
+    // BNNN
+    JumpOffset(u16),
+    // CXNN
+    Random(u8, u8),
+    // DXYN
+    Draw(u8, u8, u8),
+    // EX9E
+    SkipKey(u8),
+    // EXA1
+    SkipNotKey(u8),
+    // FX07
+    GetDelay(u8),
+    // FX0A
+    WaitKey(u8),
+    // FX15
+    SetDelay(u8),
+    // FX18
+    SetSound(u8),
+    // FX1E
+    AddIndex(u8),
+    // FX29
+    Font(u8),
+    // FX33
+    Bcd(u8),
+    // FX55
+    Store(u8),
+    // FX65
+    Restore(u8),
+    Unknown(u16),
+}
+
+impl Instruction {
+    pub fn decode(opcode: u16) -> Instruction {
+        let x = ((opcode & 0x0F00) >> 8) as u8;
+        let y = ((opcode & 0x00F0) >> 4) as u8;
+        let n = (opcode & 0x000F) as u8;
+        let nn = (opcode & 0x00FF) as u8;
+        let nnn = opcode & 0x0FFF;
+
+        match (opcode >> 12, n) {
+            (0x0, _) => match opcode {
+                0x00E0 => Instruction::Clear,
+                0x00EE => Instruction::Return,
+                _ => Instruction::Sys(nnn),
+            },
+            (0x1, _) => Instruction::Jump(nnn),
+            (0x2, _) => Instruction::Call(nnn),
+            (0x3, _) => Instruction::SkipEqual(x, nn),
+            (0x4, _) => Instruction::SkipNotEqual(x, nn),
+            (0x5, 0x0) => Instruction::SkipEqualRegister(x, y),
+            (0x6, _) => Instruction::Load(x, nn),
+            (0x7, _) => Instruction::Add(x, nn),
+            (0x8, 0x0) => Instruction::Move(x, y),
+            (0x8, 0x1) => Instruction::Or(x, y),
+            (0x8, 0x2) => Instruction::And(x, y),
+            (0x8, 0x3) => Instruction::Xor(x, y),
+            (0x8, 0x4) => Instruction::AddRegister(x, y),
+            (0x8, 0x5) => Instruction::Sub(x, y),
+            (0x8, 0x6) => Instruction::ShiftRight(x, y),
+            (0x8, 0x7) => Instruction::SubReverse(x, y),
+            (0x8, 0xE) => Instruction::ShiftLeft(x, y),
+            (0x9, 0x0) => Instruction::SkipNotEqualRegister(x, y),
+            (0xA, _) => Instruction::LoadIndex(nnn),
+            (0xB, _) => Instruction::JumpOffset(nnn),
+            (0xC, _) => Instruction::Random(x, nn),
+            (0xD, _) => Instruction::Draw(x, y, n),
+            (0xE, _) if nn == 0x9E => Instruction::SkipKey(x),
+            (0xE, _) if nn == 0xA1 => Instruction::SkipNotKey(x),
+            (0xF, _) => match nn {
+                0x07 => Instruction::GetDelay(x),
+                0x0A => Instruction::WaitKey(x),
+                0x15 => Instruction::SetDelay(x),
+                0x18 => Instruction::SetSound(x),
+                0x1E => Instruction::AddIndex(x),
+                0x29 => Instruction::Font(x),
+                0x33 => Instruction::Bcd(x),
+                0x55 => Instruction::Store(x),
+                0x65 => Instruction::Restore(x),
+                _ => Instruction::Unknown(opcode),
+            },
+            _ => Instruction::Unknown(opcode),
+        }
+    }
+
+    pub fn encode(&self) -> u16 {
+        let xy = |op: u16, x: u8, y: u8, n: u16| op | (x as u16) << 8 | (y as u16) << 4 | n;
+        let xnn = |op: u16, x: u8, nn: u8| op | (x as u16) << 8 | nn as u16;
+        match *self {
+            Instruction::Sys(nnn) => nnn & 0xFFF,
+            Instruction::Clear => 0x00E0,
+            Instruction::Return => 0x00EE,
+            Instruction::Jump(nnn) => 0x1000 | (nnn & 0xFFF),
+            Instruction::Call(nnn) => 0x2000 | (nnn & 0xFFF),
+            Instruction::SkipEqual(x, nn) => xnn(0x3000, x, nn),
+            Instruction::SkipNotEqual(x, nn) => xnn(0x4000, x, nn),
+            Instruction::SkipEqualRegister(x, y) => xy(0x5000, x, y, 0),
+            Instruction::Load(x, nn) => xnn(0x6000, x, nn),
+            Instruction::Add(x, nn) => xnn(0x7000, x, nn),
+            Instruction::Move(x, y) => xy(0x8000, x, y, 0x0),
+            Instruction::Or(x, y) => xy(0x8000, x, y, 0x1),
+            Instruction::And(x, y) => xy(0x8000, x, y, 0x2),
+            Instruction::Xor(x, y) => xy(0x8000, x, y, 0x3),
+            Instruction::AddRegister(x, y) => xy(0x8000, x, y, 0x4),
+            Instruction::Sub(x, y) => xy(0x8000, x, y, 0x5),
+            Instruction::ShiftRight(x, y) => xy(0x8000, x, y, 0x6),
+            Instruction::SubReverse(x, y) => xy(0x8000, x, y, 0x7),
+            Instruction::ShiftLeft(x, y) => xy(0x8000, x, y, 0xE),
+            Instruction::SkipNotEqualRegister(x, y) => xy(0x9000, x, y, 0),
+            Instruction::LoadIndex(nnn) => 0xA000 | (nnn & 0xFFF),
+            Instruction::JumpOffset(nnn) => 0xB000 | (nnn & 0xFFF),
+            Instruction::Random(x, nn) => xnn(0xC000, x, nn),
+            Instruction::Draw(x, y, n) => xy(0xD000, x, y, n as u16 & 0xF),
+            Instruction::SkipKey(x) => xnn(0xE000, x, 0x9E),
+            Instruction::SkipNotKey(x) => xnn(0xE000, x, 0xA1),
+            Instruction::GetDelay(x) => xnn(0xF000, x, 0x07),
+            Instruction::WaitKey(x) => xnn(0xF000, x, 0x0A),
+            Instruction::SetDelay(x) => xnn(0xF000, x, 0x15),
+            Instruction::SetSound(x) => xnn(0xF000, x, 0x18),
+            Instruction::AddIndex(x) => xnn(0xF000, x, 0x1E),
+            Instruction::Font(x) => xnn(0xF000, x, 0x29),
+            Instruction::Bcd(x) => xnn(0xF000, x, 0x33),
+            Instruction::Store(x) => xnn(0xF000, x, 0x55),
+            Instruction::Restore(x) => xnn(0xF000, x, 0x65),
+            Instruction::Unknown(opcode) => opcode,
+        }
+    }
+
+    // the conditional instructions, which skip the next instruction
+    pub fn is_skip(&self) -> bool {
+        matches!(
+            self,
+            Instruction::SkipEqual(..)
+                | Instruction::SkipNotEqual(..)
+                | Instruction::SkipEqualRegister(..)
+                | Instruction::SkipNotEqualRegister(..)
+                | Instruction::SkipKey(_)
+                | Instruction::SkipNotKey(_)
+        )
+    }
+
+    // the instruction without its operands, e.g. "LD Vx, byte"
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Instruction::Sys(_) => "SYS addr",
+            Instruction::Clear => "CLS",
+            Instruction::Return => "RET",
+            Instruction::Jump(_) => "JP addr",
+            Instruction::Call(_) => "CALL addr",
+            Instruction::SkipEqual(..) => "SE Vx, byte",
+            Instruction::SkipNotEqual(..) => "SNE Vx, byte",
+            Instruction::SkipEqualRegister(..) => "SE Vx, Vy",
+            Instruction::Load(..) => "LD Vx, byte",
+            Instruction::Add(..) => "ADD Vx, byte",
+            Instruction::Move(..) => "LD Vx, Vy",
+            Instruction::Or(..) => "OR Vx, Vy",
+            Instruction::And(..) => "AND Vx, Vy",
+            Instruction::Xor(..) => "XOR Vx, Vy",
+            Instruction::AddRegister(..) => "ADD Vx, Vy",
+            Instruction::Sub(..) => "SUB Vx, Vy",
+            Instruction::ShiftRight(..) => "SHR Vx, Vy",
+            Instruction::SubReverse(..) => "SUBN Vx, Vy",
+            Instruction::ShiftLeft(..) => "SHL Vx, Vy",
+            Instruction::SkipNotEqualRegister(..) => "SNE Vx, Vy",
+            Instruction::LoadIndex(_) => "LD I, addr",
+            Instruction::JumpOffset(_) => "JP V0, addr",
+            Instruction::Random(..) => "RND Vx, byte",
+            Instruction::Draw(..) => "DRW Vx, Vy, nibble",
+            Instruction::SkipKey(_) => "SKP Vx",
+            Instruction::SkipNotKey(_) => "SKNP Vx",
+            Instruction::GetDelay(_) => "LD Vx, DT",
+            Instruction::WaitKey(_) => "LD Vx, K",
+            Instruction::SetDelay(_) => "LD DT, Vx",
+            Instruction::SetSound(_) => "LD ST, Vx",
+            Instruction::AddIndex(_) => "ADD I, Vx",
+            Instruction::Font(_) => "LD F, Vx",
+            Instruction::Bcd(_) => "LD B, Vx",
+            Instruction::Store(_) => "LD [I], Vx",
+            Instruction::Restore(_) => "LD Vx, [I]",
+            Instruction::Unknown(_) => "DW",
+        }
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Instruction::Sys(nnn) => write!(f, "SYS {:#05X}", nnn),
+            Instruction::Clear => write!(f, "CLS"),
+            Instruction::Return => write!(f, "RET"),
+            Instruction::Jump(nnn) => write!(f, "JP {:#05X}", nnn),
+            Instruction::Call(nnn) => write!(f, "CALL {:#05X}", nnn),
+            Instruction::SkipEqual(x, nn) => write!(f, "SE V{:X}, {:#04X}", x, nn),
+            Instruction::SkipNotEqual(x, nn) => write!(f, "SNE V{:X}, {:#04X}", x, nn),
+            Instruction::SkipEqualRegister(x, y) => write!(f, "SE V{:X}, V{:X}", x, y),
+            Instruction::Load(x, nn) => write!(f, "LD V{:X}, {:#04X}", x, nn),
+            Instruction::Add(x, nn) => write!(f, "ADD V{:X}, {:#04X}", x, nn),
+            Instruction::Move(x, y) => write!(f, "LD V{:X}, V{:X}", x, y),
+            Instruction::Or(x, y) => write!(f, "OR V{:X}, V{:X}", x, y),
+            Instruction::And(x, y) => write!(f, "AND V{:X}, V{:X}", x, y),
+            Instruction::Xor(x, y) => write!(f, "XOR V{:X}, V{:X}", x, y),
+            Instruction::AddRegister(x, y) => write!(f, "ADD V{:X}, V{:X}", x, y),
+            Instruction::Sub(x, y) => write!(f, "SUB V{:X}, V{:X}", x, y),
+            Instruction::ShiftRight(x, y) => write!(f, "SHR V{:X}, V{:X}", x, y),
+            Instruction::SubReverse(x, y) => write!(f, "SUBN V{:X}, V{:X}", x, y),
+            Instruction::ShiftLeft(x, y) => write!(f, "SHL V{:X}, V{:X}", x, y),
+            Instruction::SkipNotEqualRegister(x, y) => write!(f, "SNE V{:X}, V{:X}", x, y),
+            Instruction::LoadIndex(nnn) => write!(f, "LD I, {:#05X}", nnn),
+            Instruction::JumpOffset(nnn) => write!(f, "JP V0, {:#05X}", nnn),
+            Instruction::Random(x, nn) => write!(f, "RND V{:X}, {:#04X}", x, nn),
+            Instruction::Draw(x, y, n) => write!(f, "DRW V{:X}, V{:X}, {}", x, y, n),
+            Instruction::SkipKey(x) => write!(f, "SKP V{:X}", x),
+            Instruction::SkipNotKey(x) => write!(f, "SKNP V{:X}", x),
+            Instruction::GetDelay(x) => write!(f, "LD V{:X}, DT", x),
+            Instruction::WaitKey(x) => write!(f, "LD V{:X}, K", x),
+            Instruction::SetDelay(x) => write!(f, "LD DT, V{:X}", x),
+            Instruction::SetSound(x) => write!(f, "LD ST, V{:X}", x),
+            Instruction::AddIndex(x) => write!(f, "ADD I, V{:X}", x),
+            Instruction::Font(x) => write!(f, "LD F, V{:X}", x),
+            Instruction::Bcd(x) => write!(f, "LD B, V{:X}", x),
+            Instruction::Store(x) => write!(f, "LD [I], V{:X}", x),
+            Instruction::Restore(x) => write!(f, "LD V{:X}, [I]", x),
+            Instruction::Unknown(opcode) => write!(f, "DW {:#06X}", opcode),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decode_and_display() {
+        let cases = [
+            (0x00E0, "CLS"),
+            (0x00EE, "RET"),
+            (0x0123, "SYS 0x123"),
+            (0x1228, "JP 0x228"),
+            (0x3A0F, "SE VA, 0x0F"),
+            (0x8126, "SHR V1, V2"),
+            (0x812E, "SHL V1, V2"),
+            (0xD01F, "DRW V0, V1, 15"),
+            (0xF40A, "LD V4, K"),
+            (0xF265, "LD V2, [I]"),
+            (0x5121, "DW 0x5121"),
+            (0xE1A2, "DW 0xE1A2"),
+        ];
+        for (opcode, text) in cases {
+            assert_eq!(Instruction::decode(opcode).to_string(), text);
+        }
+    }
+
+    #[test]
+    fn encode_every_opcode() {
+        for opcode in 0..=0xFFFF {
+            assert_eq!(Instruction::decode(opcode).encode(), opcode);
+        }
+    }
+
+    #[test]
+    fn kinds() {
+        assert_eq!(Instruction::decode(0x6A05).kind(), "LD Vx, byte");
+        assert_eq!(Instruction::decode(0xA22A).kind(), "LD I, addr");
+        assert_eq!(Instruction::decode(0xFFFF).kind(), "DW");
+    }
+}
@@ -0,0 +1,71 @@
+// Bindings from host keys to the 16 keys of the CHIP-8 keypad.
+//
+// Host keys are named, e.g. "Q", "7", "Up" or "Space", and every frontend translates the names
+// into its own key codes. A CHIP-8 key can be bound to several host keys.
+use std::error::Error;
+
+// the conventional layout of the COSMAC VIP keypad on a QWERTY keyboard
+//
+//     1 2 3 C        1 2 3 4
+//     4 5 6 D   ->   Q W E R
+//     7 8 9 E        A S D F
+//     A 0 B F        Z X C V
+const DEFAULT: [&str; 16] = [
+    "X", "1", "2", "3", "Q", "W", "E", "A", "S", "D", "Z", "C", "4", "R", "F", "V",
+];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Keymap {
+    bindings: [Vec<String>; 16],
+}
+
+impl Default for Keymap {
+    fn default() -> Keymap {
+        Keymap {
+            bindings: DEFAULT.map(|name| vec![name.to_string()]),
+        }
+    }
+}
+
+impl Keymap {
+    pub fn keys(&self, key: usize) -> &[String] {
+        &self.bindings[key]
+    }
+
+    pub fn bind(&mut self, key: usize, names: Vec<String>) {
+        self.bindings[key] = names;
+    }
+
+    // all pairs of host key name and CHIP-8 key
+    pub fn bindings(&self) -> impl Iterator<Item = (&str, usize)> {
+        self.bindings
+            .iter()
+            .enumerate()
+            .flat_map(|(key, names)| names.iter().map(move |name| (name.as_str(), key)))
+    }
+
+    // Replaces the bindings of the keys in the table, e.g.
+    //
+    //     5 = "W"
+    //     A = ["Z", "Space"]
+    pub fn apply(&mut self, table: &toml::Table) -> Result<(), Box<dyn Error>> {
+        for (key, value) in table {
+            let index = usize::from_str_radix(key, 16)
+                .ok()
+                .filter(|&index| index < 16)
+                .ok_or_else(|| format!("Invalid CHIP-8 key '{}' in keymap", key))?;
+
+            let names = match value {
+                toml::Value::String(name) => vec![name.clone()],
+                toml::Value::Array(names) => names
+                    .iter()
+                    .map(|name| name.as_str().map(String::from))
+                    .collect::<Option<Vec<_>>>()
+                    .ok_or_else(|| format!("Invalid keys for CHIP-8 key '{}'", key))?,
+                _ => return Err(format!("Invalid keys for CHIP-8 key '{}'", key).into()),
+            };
+            self.bind(index, names);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,33 @@
+pub mod assembler;
+#[cfg(feature = "sdl")]
+pub mod audio;
+pub mod cache;
+pub mod capture;
+pub mod cartridge;
+pub mod cfg;
+pub mod cheat;
+pub mod chip8;
+pub mod config;
+pub mod coverage;
+pub mod cpu;
+pub mod env;
+pub mod framebuffer;
+pub mod gdb;
+#[cfg(feature = "sdl")]
+pub mod graphics;
+pub mod instruction;
+pub mod keymap;
+pub mod lint;
+pub mod octo;
+pub mod options;
+pub mod overlay;
+pub mod phosphor;
+pub mod profiler;
+pub mod remote;
+#[cfg(feature = "script")]
+pub mod script;
+pub mod state;
+#[cfg(feature = "terminal")]
+pub mod terminal;
+pub mod timing;
+pub mod watch;
@@ -0,0 +1,329 @@
+// Finds instructions that behave differently between interpreters, and other suspicious code.
+//
+// check() looks at every instruction reachable from the entry point, see Cfg. The Linter
+// additionally watches a running program, which finds what the static analysis can't see:
+// sprites crossing the edge of the screen, writes through a computed I and code reached
+// through BNNN.
+use crate::cfg::Cfg;
+use crate::chip8::{COL, ROW};
+use crate::cpu::Cpu;
+use crate::instruction::Instruction;
+use std::collections::{BTreeSet, HashSet};
+use std::fmt;
+
+const START: u16 = 0x200;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Kind {
+    // the name of a quirk in Quirks
+    Quirk(&'static str),
+    SelfModifying,
+    FontWrite,
+    OddJump,
+    // a jump or call into the interpreter area below 0x200
+    LowJump,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Finding {
+    pub address: u16,
+    pub kind: Kind,
+    pub message: String,
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let kind = match self.kind {
+            Kind::Quirk(name) => name,
+            Kind::SelfModifying => "self-modifying",
+            Kind::FontWrite => "font write",
+            Kind::OddJump => "odd jump",
+            Kind::LowJump => "low jump",
+        };
+        write!(f, "{:#05X}  {:<15} {}", self.address, kind, self.message)
+    }
+}
+
+fn finding(address: u16, kind: Kind, message: String) -> Finding {
+    Finding {
+        address,
+        kind,
+        message,
+    }
+}
+
+// the quirks an instruction depends on, regardless of the values of the registers
+fn quirks(address: u16, instruction: Instruction) -> Vec<Finding> {
+    let quirk = |name, text: &str| {
+        finding(
+            address,
+            Kind::Quirk(name),
+            format!("{} {}", instruction, text),
+        )
+    };
+    let mut findings = Vec::new();
+    match instruction {
+        Instruction::ShiftRight(x, y) | Instruction::ShiftLeft(x, y) if x != y => {
+            findings.push(quirk("shift", "shifts VX or VY"));
+        }
+        Instruction::Store(_) | Instruction::Restore(_) => {
+            findings.push(quirk("load_store", "may or may not increment I"));
+        }
+        Instruction::JumpOffset(nnn) if nnn & 0xF00 != 0 => {
+            findings.push(quirk("jump", "adds V0 or VX to the address"));
+        }
+        Instruction::Or(..) | Instruction::And(..) | Instruction::Xor(..) => {
+            findings.push(quirk("logic", "may or may not reset VF"));
+        }
+        Instruction::Draw(..) => {
+            findings.push(quirk("vblank", "may wait for the vertical blank"));
+        }
+        _ => (),
+    }
+    match instruction {
+        Instruction::AddRegister(0xF, _)
+        | Instruction::Sub(0xF, _)
+        | Instruction::SubReverse(0xF, _)
+        | Instruction::ShiftRight(0xF, _)
+        | Instruction::ShiftLeft(0xF, _) => {
+            findings.push(quirk("vf_order", "writes the result and the flag to VF"));
+        }
+        _ => (),
+    }
+    findings
+}
+
+fn jumps(address: u16, instruction: Instruction) -> Option<Finding> {
+    let target = match instruction {
+        Instruction::Jump(target) | Instruction::Call(target) => target,
+        _ => return None,
+    };
+    if !target.is_multiple_of(2) {
+        Some(finding(
+            address,
+            Kind::OddJump,
+            format!("{} jumps to an odd address", instruction),
+        ))
+    } else if target < START {
+        Some(finding(
+            address,
+            Kind::LowJump,
+            format!("{} jumps below 0x200", instruction),
+        ))
+    } else {
+        None
+    }
+}
+
+// the addresses written by an instruction
+pub(crate) fn writes(instruction: Instruction, i: u16) -> std::ops::Range<u16> {
+    let len = match instruction {
+        Instruction::Bcd(_) => 3,
+        Instruction::Store(x) => x as u16 + 1,
+        _ => 0,
+    };
+    i..i.saturating_add(len)
+}
+
+fn check_writes(
+    address: u16,
+    instruction: Instruction,
+    i: u16,
+    code: &HashSet<u16>,
+) -> Vec<Finding> {
+    let range = writes(instruction, i);
+    let mut findings = Vec::new();
+    if range.start < START && !range.is_empty() {
+        findings.push(finding(
+            address,
+            Kind::FontWrite,
+            format!(
+                "{} writes to {:#05X}, below 0x200",
+                instruction, range.start
+            ),
+        ));
+    }
+    if let Some(target) = range
+        .clone()
+        .find(|a| code.contains(a) || code.contains(&a.wrapping_sub(1)))
+    {
+        findings.push(finding(
+            address,
+            Kind::SelfModifying,
+            format!("{} writes to the code at {:#05X}", instruction, target),
+        ));
+    }
+    findings
+}
+
+pub fn check(memory: &[u8], entry: u16) -> Vec<Finding> {
+    let cfg = Cfg::build(memory, entry);
+    let code: HashSet<u16> = cfg
+        .blocks
+        .values()
+        .flat_map(|block| block.instructions.iter().map(|&(address, _)| address))
+        .collect();
+
+    let mut findings = BTreeSet::new();
+    for block in cfg.blocks.values() {
+        // I is known after LD I, addr until the end of the block
+        let mut i = None;
+        for &(address, instruction) in &block.instructions {
+            findings.extend(quirks(address, instruction));
+            findings.extend(jumps(address, instruction));
+            if let Some(i) = i {
+                findings.extend(check_writes(address, instruction, i, &code));
+            }
+            i = match instruction {
+                Instruction::LoadIndex(nnn) => Some(nnn),
+                Instruction::Store(x) | Instruction::Restore(x) => i.map(|i| i + x as u16 + 1),
+                Instruction::AddIndex(_) | Instruction::Font(_) => None,
+                _ => i,
+            };
+        }
+    }
+    findings.into_iter().collect()
+}
+
+// watches the executed instructions, inspect() has to be called before every step
+#[derive(Debug, Default)]
+pub struct Linter {
+    executed: HashSet<u16>,
+    written: HashSet<u16>,
+    findings: BTreeSet<Finding>,
+}
+
+impl Linter {
+    pub fn inspect(&mut self, cpu: &Cpu) {
+        let pc = cpu.pc;
+        if pc as usize + 1 >= cpu.memory.len() {
+            return;
+        }
+        let opcode = u16::from_be_bytes([cpu.memory[pc as usize], cpu.memory[pc as usize + 1]]);
+        let instruction = Instruction::decode(opcode);
+
+        if self.executed.insert(pc) {
+            self.findings.extend(quirks(pc, instruction));
+            self.findings.extend(jumps(pc, instruction));
+            if !pc.is_multiple_of(2) {
+                self.findings.insert(finding(
+                    pc,
+                    Kind::OddJump,
+                    format!("{} is executed at an odd address", instruction),
+                ));
+            }
+        }
+        if self.written.contains(&pc) || self.written.contains(&(pc + 1)) {
+            self.findings.insert(finding(
+                pc,
+                Kind::SelfModifying,
+                format!("{} was written by the program", instruction),
+            ));
+        }
+
+        self.findings
+            .extend(check_writes(pc, instruction, cpu.i, &self.executed));
+        self.written.extend(writes(instruction, cpu.i));
+
+        if let Instruction::Draw(x, y, n) = instruction {
+            let x = cpu.v[x as usize] as usize % COL;
+            let y = cpu.v[y as usize] as usize % ROW;
+            if x + 8 > COL || y + n as usize > ROW {
+                self.findings.insert(finding(
+                    pc,
+                    Kind::Quirk("clip"),
+                    format!("{} draws across the edge of the screen", instruction),
+                ));
+            }
+        }
+    }
+
+    pub fn findings(&self) -> impl Iterator<Item = &Finding> {
+        self.findings.iter()
+    }
+}
+
+// a report of the findings, ending with the quirks that matter
+pub fn report(findings: &[Finding]) -> String {
+    let mut out = String::new();
+    let mut names = BTreeSet::new();
+    for finding in findings {
+        out += &format!("{}\n", finding);
+        if let Kind::Quirk(name) = finding.kind {
+            names.insert(name);
+        }
+    }
+    if names.is_empty() {
+        out += "\nno quirk-sensitive instructions\n";
+    } else {
+        let names: Vec<&str> = names.into_iter().collect();
+        out += &format!("\nquirks that matter: {}\n", names.join(", "));
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn memory(program: &[u8]) -> [u8; 4096] {
+        let mut memory = [0; 4096];
+        memory[0x200..0x200 + program.len()].copy_from_slice(program);
+        memory
+    }
+
+    #[test]
+    fn static_findings() {
+        let memory = memory(&[
+            0x81, 0x26, // 0x200 shr v1, v2
+            0x81, 0x16, // 0x202 shr v1, v1 is portable
+            0xA2, 0x0C, // 0x204 ld i, 0x20C
+            0xF1, 0x55, // 0x206 ld [i], v1 overwrites the jump
+            0xA1, 0x00, // 0x208 ld i, 0x100
+            0xF0, 0x33, // 0x20A ld b, v0 writes into the font
+            0x12, 0x11, // 0x20C jp 0x211
+        ]);
+        let findings = check(&memory, 0x200);
+        let kinds: Vec<(u16, Kind)> = findings.iter().map(|f| (f.address, f.kind)).collect();
+        assert_eq!(
+            kinds,
+            [
+                (0x200, Kind::Quirk("shift")),
+                (0x206, Kind::Quirk("load_store")),
+                (0x206, Kind::SelfModifying),
+                (0x20A, Kind::FontWrite),
+                (0x20C, Kind::OddJump),
+            ]
+        );
+        assert!(report(&findings).ends_with("quirks that matter: load_store, shift\n"));
+    }
+
+    #[test]
+    fn low_jumps() {
+        let memory = memory(&[
+            0x21, 0x00, // 0x200 call 0x100
+            0x10, 0x81, // 0x202 jp 0x081 is odd first
+        ]);
+        let findings = check(&memory, 0x200);
+        let kinds: Vec<(u16, Kind)> = findings.iter().map(|f| (f.address, f.kind)).collect();
+        assert_eq!(kinds, [(0x200, Kind::LowJump), (0x202, Kind::OddJump)]);
+        assert!(findings[0].to_string().contains("low jump"));
+    }
+
+    #[test]
+    fn dynamic_findings() {
+        let mut cpu = Cpu::new();
+        cpu.memory = memory(&[
+            0x60, 0x3C, // 0x200 ld v0, 60
+            0xD0, 0x11, // 0x202 drw v0, v1, 1 crosses the right edge
+            0x12, 0x04, // 0x204 jp 0x204
+        ]);
+        let mut linter = Linter::default();
+        for _ in 0..4 {
+            linter.inspect(&cpu);
+            cpu.step();
+        }
+        let kinds: Vec<Kind> = linter.findings().map(|f| f.kind).collect();
+        assert_eq!(kinds, [Kind::Quirk("clip"), Kind::Quirk("vblank")]);
+    }
+}
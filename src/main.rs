@@ -1,19 +1,246 @@
-#![allow(dead_code)]
+use rc8::cfg::Cfg;
+use rc8::cheat::{self, Cheats};
+use rc8::chip8::{Chip8, Configure};
+use rc8::config::Config;
+use rc8::lint;
+use rc8::options::{Engine, Options, Quirks, MAX_SCALE};
+use rc8::phosphor::Persistence;
+#[cfg(feature = "terminal")]
+use rc8::terminal::Glyphs;
+use std::path::{Path, PathBuf};
 
-mod chip8;
-mod cpu;
-mod graphics;
+const USAGE: &str = "usage: rc8 [OPTIONS] [ROM]
+       rc8 export ROM CARTRIDGE.gif
+       rc8 cfg [--json] ROM
+       rc8 lint [--run FRAMES] ROM
+       rc8 script SCRIPT ROM
 
-use chip8::Chip8;
+ROM can be a raw CHIP-8 program or an Octo cartridge (.gif)
+
+cfg prints the control-flow graph of the ROM as Graphviz DOT, or as JSON with --json
+
+lint reports quirk-sensitive instructions, self-modifying code, writes below 0x200 and jumps
+to odd addresses, with --run it also watches the ROM for FRAMES frames
+
+script runs a Rhai script that presses keys, runs frames and checks registers and memory
+without a frontend, and fails if an assertion failed, see src/script.rs
+
+options:
+    --config FILE
+                read the configuration from FILE instead of ~/.config/rc8/config.toml
+    --scale N   size of a CHIP-8 pixel on screen, at most 1023
+    --speed N   number of instructions per frame
+    --quirks LIST
+                comma separated quirks to enable, all others are disabled ('none' for
+                no quirks): shift, load_store, vf_order, clip, vblank, jump, logic,
+                or the quirks of a platform: chip8, schip, xochip
+    --vip-timing
+                run at the speed of the COSMAC VIP, every instruction takes its cycles and
+                drawing waits for the vertical blank with the vblank quirk (ignores --speed)
+    --engine NAME
+                'interpreter' (default) or 'cached', which decodes blocks of instructions once
+                and runs much faster, e.g. for long headless runs. The interpreter is used
+                with --vip-timing, --gdb, --profile, --folded, --coverage and lint --run
+    --mute      disable sound
+    --tui       render in the terminal instead of a window
+    --braille   use braille characters in the terminal (2x4 pixels per cell)
+    --overlay   show the registers, timers, call stack, the instructions around PC and the
+                keypad next to the screen of the window, F1 hides and shows them
+    --headless FRAMES
+                run for FRAMES frames without any frontend
+    --persistence MODE
+                reduce sprite flicker, MODE is 'off', 'or' (combine the last two frames)
+                or a decay factor between 0 and 1 (fade out pixels)
+    --record FILE
+                record the session as an animated .gif or .png (APNG)
+    --screenshot FILE
+                save the framebuffer as .png when the emulator stops
+    --profile FILE
+                count the executed instructions and write a report with the hottest
+                addresses, instruction counts, draw/key wait intervals and the call graph
+    --folded FILE
+                profile and write the call stacks in the folded format of flamegraph.pl
+    --coverage FILE
+                write a disassembly of the ROM annotated with the executed instructions and
+                the data accessed through I, as HTML if FILE ends with .html
+    --lcov FILE write the coverage as lcov tracefile, the addresses are the line numbers
+    --cheats FILE
+                load the cheats from FILE instead of ~/.config/rc8/cheats/<sha1>.toml
+    --gdb PORT  wait for a debugger speaking the GDB remote serial protocol on
+                localhost:PORT before starting, e.g. 'target remote :PORT' in gdb
+    --remote PORT
+                accept remote control clients sending JSON commands on localhost:PORT,
+                see src/remote.rs
+    --watch     restart the ROM whenever its file changes with the settings, keymap and
+                cheats of the new ROM, breakpoints of the debugger and the remote control
+                are kept, as are the scale, palette, persistence and overlay
+    --watch-state FILE
+                like --watch, but restart from the save state in FILE with the new ROM
+                copied over the one in the state
+
+the debugger's monitor command searches the memory and freezes values, e.g.
+'monitor search', 'monitor search changed', 'monitor freeze 0x2F0 3 lives', 'monitor save',
+and so does the remote control with search, freeze, unfreeze, cheats and save_cheats
+
+press F12 while running to save a screenshot and F1 to toggle the overlay
+
+the keypad is mapped to 1234/QWER/ASDF/ZXCV, keys can be rebound in the [keymap] section
+of the configuration file
+
+settings are applied in this order, later ones win: built-in defaults, the configuration
+file, options of an Octo cartridge, the [rom.\"<sha1>\"] section of the configuration file
+and finally command line flags";
+
+#[derive(Clone)]
+struct Flags {
+    config: Option<String>,
+    scale: Option<u32>,
+    speed: Option<u32>,
+    quirks: Option<Quirks>,
+    vip_timing: bool,
+    engine: Option<Engine>,
+    mute: bool,
+    tui: bool,
+    #[cfg_attr(not(feature = "terminal"), allow(dead_code))]
+    braille: bool,
+    overlay: bool,
+    headless: Option<u32>,
+    persistence: Option<Persistence>,
+    record: Option<String>,
+    screenshot: Option<String>,
+    gdb: Option<u16>,
+    remote: Option<u16>,
+    watch: bool,
+    watch_state: Option<String>,
+    cheats: Option<String>,
+    profile: Option<String>,
+    folded: Option<String>,
+    coverage: Option<String>,
+    lcov: Option<String>,
+    json: bool,
+    run: Option<u32>,
+}
+
+impl Flags {
+    // removes all known flags from the arguments
+    fn parse(args: &mut Vec<String>) -> Flags {
+        Flags {
+            config: take_value(args, "--config"),
+            scale: take_value(args, "--scale").map(|scale| parse_scale(&scale)),
+            speed: take_value(args, "--speed").map(|speed| parse_number(&speed)),
+            quirks: take_value(args, "--quirks").map(|list| {
+                parse_quirks(&list).unwrap_or_else(|| {
+                    fail(&format!("Invalid quirks '{}'", list));
+                })
+            }),
+            vip_timing: take_flag(args, "--vip-timing"),
+            engine: take_value(args, "--engine").map(|name| {
+                Engine::parse(&name).unwrap_or_else(|| {
+                    fail(&format!("Invalid engine '{}'", name));
+                })
+            }),
+            mute: take_flag(args, "--mute"),
+            tui: take_flag(args, "--tui"),
+            braille: take_flag(args, "--braille"),
+            overlay: take_flag(args, "--overlay"),
+            headless: take_value(args, "--headless").map(|frames| parse_number(&frames)),
+            persistence: take_value(args, "--persistence").map(|mode| {
+                Persistence::parse(&mode).unwrap_or_else(|| {
+                    fail(&format!("Invalid persistence '{}'", mode));
+                })
+            }),
+            record: take_value(args, "--record"),
+            screenshot: take_value(args, "--screenshot"),
+            cheats: take_value(args, "--cheats"),
+            profile: take_value(args, "--profile"),
+            folded: take_value(args, "--folded"),
+            coverage: take_value(args, "--coverage"),
+            lcov: take_value(args, "--lcov"),
+            json: take_flag(args, "--json"),
+            run: take_value(args, "--run").map(|frames| parse_number(&frames)),
+            gdb: take_value(args, "--gdb").map(|port| parse_port(&port)),
+            remote: take_value(args, "--remote").map(|port| parse_port(&port)),
+            watch: take_flag(args, "--watch"),
+            watch_state: take_value(args, "--watch-state"),
+        }
+    }
+
+    fn apply(&self, options: &mut Options) {
+        if let Some(scale) = self.scale {
+            options.scale = scale;
+        }
+        if let Some(speed) = self.speed {
+            options.tickrate = speed;
+        }
+        if let Some(quirks) = self.quirks {
+            options.quirks = quirks;
+        }
+        if self.vip_timing {
+            options.vip_timing = true;
+        }
+        if let Some(engine) = self.engine {
+            options.engine = engine;
+        }
+        if self.mute {
+            options.audio = false;
+        }
+        if let Some(persistence) = self.persistence {
+            options.persistence = persistence;
+        }
+        if self.overlay {
+            options.overlay = true;
+        }
+    }
+}
 
 fn main() {
+    let mut args: Vec<String> = std::env::args().skip(1).collect();
+    let flags = Flags::parse(&mut args);
     let mut chip = Chip8::new();
 
-    chip.load_program("IBM.ch8").unwrap_or_else(|err| {
-        eprintln!("Error occured during loading the program: {}", err);
-        std::process::exit(1);
-    });
-    chip.gameloop();
+    match args.iter().map(String::as_str).collect::<Vec<_>>()[..] {
+        ["export", rom, cartridge] => {
+            load(&mut chip, rom, &flags);
+            chip.export_cartridge(cartridge).unwrap_or_else(|err| {
+                eprintln!("Error occured during exporting the cartridge: {}", err);
+                std::process::exit(1);
+            });
+        }
+        ["cfg", rom] => {
+            load(&mut chip, rom, &flags);
+            let cfg = Cfg::build(chip.memory(), 0x200);
+            if flags.json {
+                println!("{:#}", cfg.to_json());
+            } else {
+                print!("{}", cfg.to_dot());
+            }
+        }
+        ["lint", rom] => {
+            load(&mut chip, rom, &flags);
+            let mut findings = lint::check(chip.memory(), 0x200);
+            if let Some(frames) = flags.run {
+                chip.start_linting();
+                chip.headless_loop(frames);
+                findings.extend(chip.lint_findings());
+                findings.sort();
+                findings.dedup();
+            }
+            print!("{}", lint::report(&findings));
+        }
+        ["script", script, rom] => {
+            load(&mut chip, rom, &flags);
+            run_script(chip, script);
+        }
+        [] => {
+            load(&mut chip, "IBM.ch8", &flags);
+            run(&mut chip, &flags);
+        }
+        [rom] if !rom.starts_with('-') => {
+            load(&mut chip, rom, &flags);
+            run(&mut chip, &flags);
+        }
+        _ => fail("Invalid arguments"),
+    }
 
     //chip.setup_map();
 
@@ -26,3 +253,243 @@ fn main() {
 
     //chip.run();
 }
+
+// loads the program and applies the settings in the order described in USAGE
+fn load(chip: &mut Chip8, rom: &str, flags: &Flags) {
+    let config = match &flags.config {
+        Some(path) => Config::load_from(Path::new(path)),
+        None => Config::load(),
+    };
+    let config = config.unwrap_or_else(|err| {
+        eprintln!("Error occured during loading the configuration: {}", err);
+        std::process::exit(1);
+    });
+
+    let mut options = Options::default();
+    config.apply_global(&mut options).unwrap_or_else(|err| {
+        eprintln!("Error in the configuration: {}", err);
+        std::process::exit(1);
+    });
+    chip.set_options(options);
+
+    chip.load_program(rom).unwrap_or_else(|err| {
+        eprintln!("Error occured during loading the program: {}", err);
+        std::process::exit(1);
+    });
+
+    // --watch applies them again to the reloaded ROM
+    let configure = configure(config, flags.clone());
+    configure(chip).unwrap_or_else(|err| {
+        eprintln!("{}", err);
+        std::process::exit(1);
+    });
+    chip.configure_reloads(options, configure);
+}
+
+// applies the settings of the loaded ROM over the global and cartridge options
+fn configure(config: Config, flags: Flags) -> Configure {
+    Box::new(move |chip| {
+        let error = |err| format!("Error in the configuration: {}", err);
+        let mut options = chip.options();
+        config.apply_rom(chip.rom(), &mut options).map_err(error)?;
+        chip.set_keymap(config.keymap(chip.rom()).map_err(error)?);
+        flags.apply(&mut options);
+        chip.set_options(options);
+
+        let path = match &flags.cheats {
+            Some(path) => Some(PathBuf::from(path)),
+            None => cheat::path(chip.rom()),
+        };
+        if let Some(path) = path {
+            let cheats = Cheats::load(&path)
+                .map_err(|err| format!("Error occured during loading the cheats: {}", err))?;
+            chip.set_cheats(cheats);
+        }
+        Ok(())
+    })
+}
+
+fn run(chip: &mut Chip8, flags: &Flags) {
+    if let Some(port) = flags.gdb {
+        chip.attach_debugger(port).unwrap_or_else(|err| {
+            eprintln!("Error occured during waiting for the debugger: {}", err);
+            std::process::exit(1);
+        });
+    }
+    if let Some(port) = flags.remote {
+        chip.start_remote(port).unwrap_or_else(|err| {
+            eprintln!("Error occured during starting the remote control: {}", err);
+            std::process::exit(1);
+        });
+    }
+    if flags.watch || flags.watch_state.is_some() {
+        let state = flags.watch_state.as_ref().map(|path| {
+            std::fs::read(path).unwrap_or_else(|err| {
+                eprintln!("Error occured during loading the save state: {}", err);
+                std::process::exit(1);
+            })
+        });
+        chip.watch(state).unwrap_or_else(|err| {
+            eprintln!("Error occured during watching the ROM: {}", err);
+            std::process::exit(1);
+        });
+    }
+    if let Some(path) = &flags.record {
+        chip.start_recording(path).unwrap_or_else(|err| {
+            eprintln!("Error occured during starting the recording: {}", err);
+            std::process::exit(1);
+        });
+    }
+
+    if flags.profile.is_some() || flags.folded.is_some() {
+        chip.start_profiling();
+    }
+    if flags.coverage.is_some() || flags.lcov.is_some() {
+        chip.start_coverage();
+    }
+
+    if let Some(frames) = flags.headless {
+        chip.headless_loop(frames);
+    } else if flags.tui {
+        #[cfg(feature = "terminal")]
+        {
+            let glyphs = if flags.braille {
+                Glyphs::Braille
+            } else {
+                Glyphs::HalfBlock
+            };
+            chip.terminal_loop(glyphs).unwrap_or_else(|err| {
+                eprintln!("Error occured in the terminal frontend: {}", err);
+                std::process::exit(1);
+            });
+        }
+        #[cfg(not(feature = "terminal"))]
+        {
+            eprintln!("rc8 was built without the terminal frontend");
+            std::process::exit(1);
+        }
+    } else {
+        #[cfg(feature = "sdl")]
+        chip.gameloop();
+        #[cfg(not(feature = "sdl"))]
+        {
+            eprintln!("rc8 was built without a window, use --tui or --headless");
+            std::process::exit(1);
+        }
+    }
+
+    if let Err(err) = chip.stop_recording() {
+        eprintln!("Error occured during saving the recording: {}", err);
+    }
+    let reports = [
+        (&flags.profile, chip.profile_report()),
+        (&flags.folded, chip.profile_folded()),
+    ];
+    for (path, report) in reports {
+        if let (Some(path), Some(report)) = (path, report) {
+            if let Err(err) = std::fs::write(path, report) {
+                eprintln!("Error occured during saving the profile: {}", err);
+            }
+        }
+    }
+    if let Some(path) = &flags.coverage {
+        if let Err(err) = chip.save_coverage(path) {
+            eprintln!("Error occured during saving the coverage: {}", err);
+        }
+    }
+    if let Some(path) = &flags.lcov {
+        if let Err(err) = chip.save_lcov(path) {
+            eprintln!("Error occured during saving the coverage: {}", err);
+        }
+    }
+    if let Some(path) = &flags.screenshot {
+        if let Err(err) = chip.screenshot(path) {
+            eprintln!("Error occured during saving the screenshot: {}", err);
+        }
+    }
+}
+
+fn parse_number(text: &str) -> u32 {
+    match text.parse() {
+        Ok(number) if number > 0 => number,
+        _ => fail(&format!("Invalid number '{}'", text)),
+    }
+}
+
+fn parse_scale(text: &str) -> u32 {
+    let scale = parse_number(text);
+    if scale > MAX_SCALE {
+        fail(&format!("Invalid scale '{}', at most {}", text, MAX_SCALE));
+    }
+    scale
+}
+
+fn parse_port(text: &str) -> u16 {
+    text.parse()
+        .unwrap_or_else(|_| fail(&format!("Invalid port '{}'", text)))
+}
+
+fn parse_quirks(list: &str) -> Option<Quirks> {
+    let mut quirks = Quirks::default();
+    if list == "none" {
+        return Some(quirks);
+    }
+    if let Some(quirks) = Quirks::profile(list) {
+        return Some(quirks);
+    }
+    for name in list.split(',') {
+        *quirks.flag_mut(name.trim())? = true;
+    }
+    Some(quirks)
+}
+
+#[cfg(feature = "script")]
+fn run_script(chip: Chip8, path: &str) {
+    let source = std::fs::read_to_string(path).unwrap_or_else(|err| {
+        eprintln!("Error occured during loading the script: {}", err);
+        std::process::exit(1);
+    });
+    let report = rc8::script::Script::new(chip).run(&source);
+    for failure in &report.failures {
+        eprintln!("Assertion failed at {}", failure);
+    }
+    if let Some(err) = &report.error {
+        eprintln!("Error in the script: {}", err);
+    }
+    println!(
+        "{} assertions, {} failed",
+        report.assertions,
+        report.failures.len()
+    );
+    if !report.passed() {
+        std::process::exit(1);
+    }
+}
+
+#[cfg(not(feature = "script"))]
+fn run_script(_chip: Chip8, _path: &str) {
+    eprintln!("rc8 was built without scripting");
+    std::process::exit(1);
+}
+
+fn fail(message: &str) -> ! {
+    eprintln!("{}\n\n{}", message, USAGE);
+    std::process::exit(1);
+}
+
+// removes the flag from the arguments and returns whether it was present
+fn take_flag(args: &mut Vec<String>, flag: &str) -> bool {
+    let len = args.len();
+    args.retain(|arg| arg != flag);
+    args.len() != len
+}
+
+// removes the flag and its value from the arguments
+fn take_value(args: &mut Vec<String>, flag: &str) -> Option<String> {
+    let pos = args.iter().position(|arg| arg == flag)?;
+    if pos + 1 >= args.len() {
+        fail(&format!("Missing value for {}", flag));
+    }
+    args.remove(pos);
+    Some(args.remove(pos))
+}
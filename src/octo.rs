@@ -0,0 +1,1020 @@
+// Assembler for Octo, the language of the programs stored in Octo cartridges, e.g.
+//
+//     : main
+//         i := box
+//         loop
+//             sprite v0 v1 2
+//             v0 += 1
+//             if v0 == 60 then v0 := 0
+//         again
+//     : box 0xF0 0x90
+//
+// Execution starts at the label main. The language is covered except for strings
+// (:stringmode, :assert): :const, :alias, :calc and { } expressions, :macro, :org, :next,
+// :unpack, the structured if and loop statements and the SCHIP and XO-CHIP instructions, which
+// are assembled although rc8 only executes CHIP-8. Like in Octo, expressions have no
+// precedence and are evaluated from right to left, `{ 2 * 3 + 1 }` is 8.
+//
+// Statements are translated to an Instruction and encoded by it. The SCHIP and XO-CHIP ones,
+// which Instruction doesn't decode, are written as Instruction::Unknown with their opcode.
+use crate::instruction::Instruction;
+use std::collections::{HashMap, VecDeque};
+use std::error::Error;
+use std::mem;
+
+const START: usize = 0x200;
+// a recursive macro would expand forever
+const MAX_EXPANSIONS: usize = 100_000;
+// tokens that can't start a statement or name a subroutine
+const RESERVED: [&str; 22] = [
+    ":=", "+=", "-=", "=-", "|=", "&=", "^=", ">>=", "<<=", "==", "!=", "<", ">", "<=", ">=",
+    "then", "begin", "key", "-key", "random", "{", "}",
+];
+
+struct Token {
+    text: String,
+    line: usize,
+}
+
+// how a reference to a label that isn't defined yet is filled in
+#[derive(Debug, Clone, Copy)]
+enum Patch {
+    // the instruction taking the address, e.g. Instruction::Jump
+    Address(fn(u16) -> Instruction),
+    // the 16 bit operand after i := long
+    Long,
+    // the two register loads of :unpack: the nibble, the registers of the high and low byte
+    Unpack(u8, u8, u8),
+}
+
+struct Reference {
+    address: usize,
+    label: String,
+    patch: Patch,
+    line: usize,
+}
+
+#[derive(Clone)]
+struct Macro {
+    parameters: Vec<String>,
+    body: Vec<(String, usize)>,
+}
+
+struct Loop {
+    start: usize,
+    // the jumps of while out of the loop
+    exits: Vec<usize>,
+}
+
+#[derive(Default)]
+struct Assembler {
+    tokens: VecDeque<Token>,
+    // the line of the last token
+    line: usize,
+    rom: Vec<u8>,
+    here: usize,
+    labels: HashMap<String, usize>,
+    constants: HashMap<String, f64>,
+    aliases: HashMap<String, u8>,
+    macros: HashMap<String, Macro>,
+    expansions: usize,
+    references: Vec<Reference>,
+    loops: Vec<Loop>,
+    // the jumps to the else or end of if ... begin
+    ifs: Vec<usize>,
+    // the jump to main at 0x200, dropped if main comes first
+    main_jump: bool,
+}
+
+pub fn assemble(source: &str) -> Result<Vec<u8>, Box<dyn Error>> {
+    let mut assembler = Assembler {
+        tokens: tokenize(source),
+        here: START,
+        main_jump: true,
+        ..Assembler::default()
+    };
+    assembler.instruction(Instruction::Jump(0))?;
+    while !assembler.tokens.is_empty() {
+        assembler
+            .statement()
+            .map_err(|err| format!("line {}: {}", assembler.line, err))?;
+    }
+    Ok(assembler.finish()?)
+}
+
+fn tokenize(source: &str) -> VecDeque<Token> {
+    let mut tokens = VecDeque::new();
+    for (index, line) in source.lines().enumerate() {
+        for text in line.split_whitespace() {
+            if text.starts_with('#') {
+                break;
+            }
+            tokens.push_back(Token {
+                text: text.to_string(),
+                line: index + 1,
+            });
+        }
+    }
+    tokens
+}
+
+fn number(text: &str) -> Option<f64> {
+    let (sign, digits) = match text.strip_prefix('-') {
+        Some(digits) => (-1.0, digits),
+        None => (1.0, text),
+    };
+    let value = if let Some(hex) = digits.strip_prefix("0x") {
+        i64::from_str_radix(hex, 16).ok()? as f64
+    } else if let Some(binary) = digits.strip_prefix("0b") {
+        i64::from_str_radix(binary, 2).ok()? as f64
+    } else if digits.starts_with(|c: char| c.is_ascii_digit()) {
+        digits.parse().ok()?
+    } else {
+        return None;
+    };
+    Some(sign * value)
+}
+
+fn byte(value: f64) -> Result<u8, String> {
+    if !(-128.0..256.0).contains(&value) {
+        return Err(format!("{} doesn't fit in a byte", value));
+    }
+    Ok(value as i64 as u8)
+}
+
+fn nibble(value: f64) -> Result<u8, String> {
+    if !(0.0..16.0).contains(&value) {
+        return Err(format!("{} doesn't fit in a nibble", value));
+    }
+    Ok(value as u8)
+}
+
+// an SCHIP or XO-CHIP instruction with the register X
+fn extension(opcode: u16, x: u8) -> Instruction {
+    Instruction::Unknown(opcode | (x as u16) << 8)
+}
+
+fn binary(operator: &str, a: f64, b: f64) -> Result<f64, String> {
+    let (x, y) = (a as i64, b as i64);
+    let flag = |condition: bool| condition as i64 as f64;
+    Ok(match operator {
+        "+" => a + b,
+        "-" => a - b,
+        "*" => a * b,
+        "/" => a / b,
+        "%" => a % b,
+        "&" => (x & y) as f64,
+        "|" => (x | y) as f64,
+        "^" => (x ^ y) as f64,
+        "<<" => x.checked_shl(y as u32).unwrap_or(0) as f64,
+        ">>" => x.checked_shr(y as u32).unwrap_or(0) as f64,
+        "pow" => a.powf(b),
+        "min" => a.min(b),
+        "max" => a.max(b),
+        "<" => flag(a < b),
+        ">" => flag(a > b),
+        "<=" => flag(a <= b),
+        ">=" => flag(a >= b),
+        "==" => flag(a == b),
+        "!=" => flag(a != b),
+        _ => return Err(format!("unknown operator '{}'", operator)),
+    })
+}
+
+fn unary(operator: &str, a: f64) -> Option<f64> {
+    Some(match operator {
+        "-" => -a,
+        "~" => !(a as i64) as f64,
+        "!" => (a == 0.0) as i64 as f64,
+        "sin" => a.sin(),
+        "cos" => a.cos(),
+        "tan" => a.tan(),
+        "exp" => a.exp(),
+        "log" => a.ln(),
+        "abs" => a.abs(),
+        "sqrt" => a.sqrt(),
+        "sign" => a.signum(),
+        "ceil" => a.ceil(),
+        "floor" => a.floor(),
+        _ => return None,
+    })
+}
+
+impl Assembler {
+    fn next(&mut self) -> Result<String, String> {
+        let token = self
+            .tokens
+            .pop_front()
+            .ok_or("unexpected end of the program")?;
+        self.line = token.line;
+        Ok(token.text)
+    }
+
+    fn peek(&self) -> Option<&str> {
+        self.tokens.front().map(|token| token.text.as_str())
+    }
+
+    fn expect(&mut self, expected: &str) -> Result<(), String> {
+        let token = self.next()?;
+        if token != expected {
+            return Err(format!("expected '{}' instead of '{}'", expected, token));
+        }
+        Ok(())
+    }
+
+    fn emit(&mut self, byte: u8) -> Result<(), String> {
+        if self.here > 0xFFFF {
+            return Err("the program doesn't fit in 64 KB".to_string());
+        }
+        let index = self.here - START;
+        if index >= self.rom.len() {
+            self.rom.resize(index + 1, 0);
+        }
+        self.rom[index] = byte;
+        self.here += 1;
+        Ok(())
+    }
+
+    fn instruction(&mut self, instruction: Instruction) -> Result<(), String> {
+        let [high, low] = instruction.encode().to_be_bytes();
+        self.emit(high)?;
+        self.emit(low)
+    }
+
+    // replaces the instruction emitted at an address
+    fn put(&mut self, at: usize, instruction: Instruction) {
+        let index = at - START;
+        self.rom[index..index + 2].copy_from_slice(&instruction.encode().to_be_bytes());
+    }
+
+    fn register(&self, text: &str) -> Option<u8> {
+        if let Some(&x) = self.aliases.get(text) {
+            return Some(x);
+        }
+        let digit = text.strip_prefix(['v', 'V'])?;
+        if digit.len() != 1 {
+            return None;
+        }
+        u8::from_str_radix(digit, 16).ok()
+    }
+
+    fn expect_register(&mut self) -> Result<u8, String> {
+        let token = self.next()?;
+        match self.register(&token) {
+            Some(x) => Ok(x),
+            None => Err(format!("expected a register instead of '{}'", token)),
+        }
+    }
+
+    fn value(&mut self) -> Result<f64, String> {
+        let token = self.next()?;
+        self.value_of(&token)
+    }
+
+    // a number, constant, label that is already defined or { expression }
+    fn value_of(&mut self, token: &str) -> Result<f64, String> {
+        if token == "{" {
+            return self.calc();
+        }
+        self.known(token)
+            .ok_or_else(|| format!("unknown value '{}'", token))
+    }
+
+    fn known(&self, name: &str) -> Option<f64> {
+        number(name)
+            .or_else(|| self.constants.get(name).copied())
+            .or_else(|| self.labels.get(name).map(|&address| address as f64))
+    }
+
+    fn address(&mut self, patch: Patch) -> Result<u16, String> {
+        let token = self.next()?;
+        self.address_of(&token, patch)
+    }
+
+    // like value_of, but a label can be defined later
+    fn address_of(&mut self, token: &str, patch: Patch) -> Result<u16, String> {
+        let value = if token != "{" && self.known(token).is_none() {
+            if RESERVED.contains(&token) || self.register(token).is_some() {
+                return Err(format!("expected an address instead of '{}'", token));
+            }
+            self.references.push(Reference {
+                address: self.here,
+                label: token.to_string(),
+                patch,
+                line: self.line,
+            });
+            0.0
+        } else {
+            self.value_of(token)?
+        };
+        let max = match patch {
+            Patch::Long => 0xFFFF,
+            _ => 0xFFF,
+        };
+        if !(0.0..=max as f64).contains(&value) {
+            return Err(format!("address {} is out of range", value));
+        }
+        Ok(value as u16)
+    }
+
+    // the tokens up to the closing brace, the opening brace was already read
+    fn calc(&mut self) -> Result<f64, String> {
+        let mut tokens = Vec::new();
+        loop {
+            let token = self.next()?;
+            if token == "}" {
+                break;
+            }
+            tokens.push(token);
+        }
+        let mut position = 0;
+        let value = self.expression(&tokens, &mut position)?;
+        if position < tokens.len() {
+            return Err(format!("unexpected '{}' in expression", tokens[position]));
+        }
+        Ok(value)
+    }
+
+    fn expression(&self, tokens: &[String], position: &mut usize) -> Result<f64, String> {
+        let left = self.term(tokens, position)?;
+        match tokens.get(*position) {
+            Some(operator) if operator != ")" => {
+                *position += 1;
+                let right = self.expression(tokens, position)?;
+                binary(operator, left, right)
+            }
+            _ => Ok(left),
+        }
+    }
+
+    fn term(&self, tokens: &[String], position: &mut usize) -> Result<f64, String> {
+        let token = tokens
+            .get(*position)
+            .ok_or("incomplete expression")?
+            .as_str();
+        *position += 1;
+        match token {
+            "(" => {
+                let value = self.expression(tokens, position)?;
+                if tokens.get(*position).map(String::as_str) != Some(")") {
+                    return Err("missing ')' in expression".to_string());
+                }
+                *position += 1;
+                Ok(value)
+            }
+            "@" => {
+                let address = self.term(tokens, position)? as usize;
+                let byte = address
+                    .checked_sub(START)
+                    .and_then(|index| self.rom.get(index));
+                Ok(byte.copied().unwrap_or(0) as f64)
+            }
+            "HERE" => Ok(self.here as f64),
+            "PI" => Ok(std::f64::consts::PI),
+            "E" => Ok(std::f64::consts::E),
+            _ => match self.known(token) {
+                Some(value) => Ok(value),
+                None => {
+                    let operand = self.term(tokens, position);
+                    match operand.map(|a| unary(token, a)) {
+                        Ok(Some(value)) => Ok(value),
+                        _ => Err(format!("unknown value '{}' in expression", token)),
+                    }
+                }
+            },
+        }
+    }
+
+    fn define(&mut self, name: String, mut address: usize) -> Result<(), String> {
+        if self.known(&name).is_some() || self.register(&name).is_some() {
+            return Err(format!("'{}' is already defined", name));
+        }
+        let first = self.here == START + 2 && !self.labels.values().any(|&a| a == self.here);
+        if name == "main" && self.main_jump && first && address == self.here {
+            self.rom.clear();
+            self.here = START;
+            self.main_jump = false;
+            address = START;
+        }
+        self.labels.insert(name, address);
+        Ok(())
+    }
+
+    fn finish(mut self) -> Result<Vec<u8>, String> {
+        if !self.loops.is_empty() {
+            return Err("'loop' without 'again'".to_string());
+        }
+        if !self.ifs.is_empty() {
+            return Err("'if ... begin' without 'end'".to_string());
+        }
+        if self.main_jump {
+            let main = *self
+                .labels
+                .get("main")
+                .ok_or("the program has no label 'main'")?;
+            self.jump_to(START, main)?;
+        }
+
+        for reference in mem::take(&mut self.references) {
+            let address = *self.labels.get(&reference.label).ok_or_else(|| {
+                format!(
+                    "line {}: undefined label '{}'",
+                    reference.line, reference.label
+                )
+            })?;
+            let at = reference.address;
+            match reference.patch {
+                Patch::Long => {
+                    if address > 0xFFFF {
+                        return Err(format!("line {}: address out of range", reference.line));
+                    }
+                    self.put(at + 2, Instruction::Unknown(address as u16));
+                }
+                _ if address > 0xFFF => {
+                    return Err(format!(
+                        "line {}: '{}' is above 0xFFF",
+                        reference.line, reference.label
+                    ))
+                }
+                Patch::Address(instruction) => self.put(at, instruction(address as u16)),
+                Patch::Unpack(high, hi, lo) => {
+                    let [upper, lower] = (address as u16).to_be_bytes();
+                    self.put(at, Instruction::Load(hi, high << 4 | upper));
+                    self.put(at + 2, Instruction::Load(lo, lower));
+                }
+            }
+        }
+        Ok(self.rom)
+    }
+
+    fn jump_to(&mut self, at: usize, target: usize) -> Result<(), String> {
+        if target > 0xFFF {
+            return Err(format!("can't jump to {:#X}", target));
+        }
+        self.put(at, Instruction::Jump(target as u16));
+        Ok(())
+    }
+
+    fn statement(&mut self) -> Result<(), String> {
+        let token = self.next()?;
+        if let Some(x) = self.register(&token) {
+            return self.assignment(x);
+        }
+        match token.as_str() {
+            ":" => {
+                let name = self.next()?;
+                self.define(name, self.here)
+            }
+            ":next" => {
+                let name = self.next()?;
+                self.define(name, self.here + 1)
+            }
+            ":const" | ":calc" => {
+                let name = self.next()?;
+                if self.known(&name).is_some() {
+                    return Err(format!("'{}' is already defined", name));
+                }
+                if token == ":calc" {
+                    self.expect("{")?;
+                }
+                let value = if token == ":calc" {
+                    self.calc()?
+                } else {
+                    self.value()?
+                };
+                self.constants.insert(name, value);
+                Ok(())
+            }
+            ":alias" => {
+                let name = self.next()?;
+                let target = self.next()?;
+                let x = match self.register(&target) {
+                    Some(x) => x,
+                    None => nibble(self.value_of(&target)?)?,
+                };
+                self.aliases.insert(name, x);
+                Ok(())
+            }
+            ":byte" => {
+                let value = self.value()?;
+                self.emit(byte(value)?)
+            }
+            ":org" => {
+                let address = self.value()?;
+                if !(START as f64..=0xFFFF as f64).contains(&address) {
+                    return Err(format!("can't place code at {}", address));
+                }
+                self.here = address as usize;
+                Ok(())
+            }
+            ":macro" => self.define_macro(),
+            ":call" => {
+                let address = self.address(Patch::Address(Instruction::Call))?;
+                self.instruction(Instruction::Call(address))
+            }
+            ":unpack" => {
+                let high = nibble(self.value()?)?;
+                let hi = self.aliases.get("unpack-hi").copied().unwrap_or(0);
+                let lo = self.aliases.get("unpack-lo").copied().unwrap_or(1);
+                let address = self.address(Patch::Unpack(high, hi, lo))?;
+                let [upper, lower] = address.to_be_bytes();
+                self.instruction(Instruction::Load(hi, high << 4 | upper))?;
+                self.instruction(Instruction::Load(lo, lower))
+            }
+            // debugger directives of the Octo IDE
+            ":breakpoint" => self.next().map(drop),
+            ":monitor" => {
+                self.next()?;
+                self.next().map(drop)
+            }
+            ";" | "return" => self.instruction(Instruction::Return),
+            "clear" => self.instruction(Instruction::Clear),
+            "hires" => self.instruction(Instruction::Unknown(0x00FF)),
+            "lores" => self.instruction(Instruction::Unknown(0x00FE)),
+            "exit" => self.instruction(Instruction::Unknown(0x00FD)),
+            "scroll-left" => self.instruction(Instruction::Unknown(0x00FC)),
+            "scroll-right" => self.instruction(Instruction::Unknown(0x00FB)),
+            "scroll-down" | "scroll-up" => {
+                let n = nibble(self.value()?)? as u16;
+                let opcode = if token == "scroll-down" {
+                    0x00C0
+                } else {
+                    0x00D0
+                };
+                self.instruction(Instruction::Unknown(opcode | n))
+            }
+            "audio" => self.instruction(Instruction::Unknown(0xF002)),
+            "plane" => {
+                let n = nibble(self.value()?)?;
+                self.instruction(extension(0xF001, n))
+            }
+            "bcd" => {
+                let x = self.expect_register()?;
+                self.instruction(Instruction::Bcd(x))
+            }
+            "saveflags" | "loadflags" => {
+                let x = self.expect_register()?;
+                let opcode = if token == "saveflags" { 0xF075 } else { 0xF085 };
+                self.instruction(extension(opcode, x))
+            }
+            "save" | "load" => {
+                let x = self.expect_register()?;
+                let save = token == "save";
+                if self.peek() == Some("-") {
+                    self.next()?;
+                    let y = self.expect_register()?;
+                    let opcode = if save { 0x5002 } else { 0x5003 };
+                    return self.instruction(extension(opcode | (y as u16) << 4, x));
+                }
+                self.instruction(if save {
+                    Instruction::Store(x)
+                } else {
+                    Instruction::Restore(x)
+                })
+            }
+            "sprite" => {
+                let x = self.expect_register()?;
+                let y = self.expect_register()?;
+                let n = nibble(self.value()?)?;
+                self.instruction(Instruction::Draw(x, y, n))
+            }
+            "jump" | "jump0" | "native" => {
+                let instruction = match token.as_str() {
+                    "jump" => Instruction::Jump,
+                    "jump0" => Instruction::JumpOffset,
+                    _ => Instruction::Sys,
+                };
+                let address = self.address(Patch::Address(instruction))?;
+                self.instruction(instruction(address))
+            }
+            "delay" | "buzzer" | "pitch" => {
+                self.expect(":=")?;
+                let x = self.expect_register()?;
+                self.instruction(match token.as_str() {
+                    "delay" => Instruction::SetDelay(x),
+                    "buzzer" => Instruction::SetSound(x),
+                    _ => extension(0xF03A, x),
+                })
+            }
+            "i" => self.index(),
+            "if" => {
+                // the block form skips the jump to else or end when the condition holds
+                let block = self
+                    .tokens
+                    .iter()
+                    .map(|token| token.text.as_str())
+                    .find(|&text| text == "then" || text == "begin")
+                    == Some("begin");
+                self.conditional(block)?;
+                if !block {
+                    return self.expect("then");
+                }
+                self.expect("begin")?;
+                self.ifs.push(self.here);
+                self.instruction(Instruction::Jump(0))
+            }
+            "else" => {
+                let jump = self.ifs.pop().ok_or("'else' without 'if ... begin'")?;
+                self.ifs.push(self.here);
+                self.instruction(Instruction::Jump(0))?;
+                self.jump_to(jump, self.here)
+            }
+            "end" => {
+                let jump = self.ifs.pop().ok_or("'end' without 'if ... begin'")?;
+                self.jump_to(jump, self.here)
+            }
+            "loop" => {
+                self.loops.push(Loop {
+                    start: self.here,
+                    exits: Vec::new(),
+                });
+                Ok(())
+            }
+            "while" => {
+                if self.loops.is_empty() {
+                    return Err("'while' outside of a loop".to_string());
+                }
+                self.conditional(true)?;
+                let exit = self.here;
+                self.loops.last_mut().unwrap().exits.push(exit);
+                self.instruction(Instruction::Jump(0))
+            }
+            "again" => {
+                let Loop { start, exits } = self.loops.pop().ok_or("'again' without 'loop'")?;
+                let jump = self.here;
+                self.instruction(Instruction::Jump(0))?;
+                self.jump_to(jump, start)?;
+                for exit in exits {
+                    self.jump_to(exit, self.here)?;
+                }
+                Ok(())
+            }
+            _ => self.bare(token),
+        }
+    }
+
+    // data, a macro or a call of a subroutine
+    fn bare(&mut self, token: String) -> Result<(), String> {
+        if let Some(value) = number(&token).or_else(|| self.constants.get(&token).copied()) {
+            return self.emit(byte(value)?);
+        }
+        if let Some(definition) = self.macros.get(&token).cloned() {
+            self.expansions += 1;
+            if self.expansions > MAX_EXPANSIONS {
+                return Err(format!("too many expansions of the macro '{}'", token));
+            }
+            let mut arguments = HashMap::new();
+            for parameter in definition.parameters {
+                arguments.insert(parameter, self.next()?);
+            }
+            for (text, line) in definition.body.into_iter().rev() {
+                let text = arguments.get(&text).cloned().unwrap_or(text);
+                self.tokens.push_front(Token { text, line });
+            }
+            return Ok(());
+        }
+        if token.starts_with(':') {
+            return Err(format!("unsupported directive '{}'", token));
+        }
+        if RESERVED.contains(&token.as_str()) {
+            return Err(format!("unexpected '{}'", token));
+        }
+        let address = self.address_of(&token, Patch::Address(Instruction::Call))?;
+        self.instruction(Instruction::Call(address))
+    }
+
+    fn define_macro(&mut self) -> Result<(), String> {
+        let name = self.next()?;
+        let mut parameters = Vec::new();
+        loop {
+            let token = self.next()?;
+            if token == "{" {
+                break;
+            }
+            parameters.push(token);
+        }
+        let mut body = Vec::new();
+        let mut depth = 1;
+        loop {
+            let token = self.next()?;
+            match token.as_str() {
+                "{" => depth += 1,
+                "}" => depth -= 1,
+                _ => {}
+            }
+            if depth == 0 {
+                break;
+            }
+            body.push((token, self.line));
+        }
+        self.macros.insert(name, Macro { parameters, body });
+        Ok(())
+    }
+
+    fn assignment(&mut self, x: u8) -> Result<(), String> {
+        let operator = self.next()?;
+        if operator == ":=" {
+            let instruction = match self.peek() {
+                Some("random") => {
+                    self.next()?;
+                    let nn = byte(self.value()?)?;
+                    return self.instruction(Instruction::Random(x, nn));
+                }
+                Some("key") => Some(Instruction::WaitKey(x)),
+                Some("delay") => Some(Instruction::GetDelay(x)),
+                _ => None,
+            };
+            if let Some(instruction) = instruction {
+                self.next()?;
+                return self.instruction(instruction);
+            }
+        }
+
+        let operand = self.next()?;
+        if let Some(y) = self.register(&operand) {
+            let instruction = match operator.as_str() {
+                ":=" => Instruction::Move,
+                "|=" => Instruction::Or,
+                "&=" => Instruction::And,
+                "^=" => Instruction::Xor,
+                "+=" => Instruction::AddRegister,
+                "-=" => Instruction::Sub,
+                ">>=" => Instruction::ShiftRight,
+                "=-" => Instruction::SubReverse,
+                "<<=" => Instruction::ShiftLeft,
+                _ => return Err(format!("unknown operator '{}'", operator)),
+            };
+            return self.instruction(instruction(x, y));
+        }
+        let nn = byte(self.value_of(&operand)?)?;
+        match operator.as_str() {
+            ":=" => self.instruction(Instruction::Load(x, nn)),
+            "+=" => self.instruction(Instruction::Add(x, nn)),
+            "-=" => self.instruction(Instruction::Add(x, nn.wrapping_neg())),
+            _ => Err(format!("'{}' needs a register on the right", operator)),
+        }
+    }
+
+    fn index(&mut self) -> Result<(), String> {
+        let operator = self.next()?;
+        if operator == "+=" {
+            let x = self.expect_register()?;
+            return self.instruction(Instruction::AddIndex(x));
+        }
+        if operator != ":=" {
+            return Err(format!("unknown operator '{}' for i", operator));
+        }
+        let operand = self.next()?;
+        match operand.as_str() {
+            "hex" => {
+                let x = self.expect_register()?;
+                self.instruction(Instruction::Font(x))
+            }
+            "bighex" => {
+                let x = self.expect_register()?;
+                self.instruction(extension(0xF030, x))
+            }
+            "long" => {
+                let address = self.address(Patch::Long)?;
+                self.instruction(Instruction::Unknown(0xF000))?;
+                // the address follows as a plain word
+                self.instruction(Instruction::Unknown(address))
+            }
+            _ => {
+                let address = self.address_of(&operand, Patch::Address(Instruction::LoadIndex))?;
+                self.instruction(Instruction::LoadIndex(address))
+            }
+        }
+    }
+
+    // skips the next instruction unless the condition holds, or if it holds when negated
+    fn conditional(&mut self, negated: bool) -> Result<(), String> {
+        let x = self.expect_register()?;
+        let operator = self.next()?;
+        let operator = match (negated, operator.as_str()) {
+            (false, operator) => operator,
+            (true, "==") => "!=",
+            (true, "!=") => "==",
+            (true, "<") => ">=",
+            (true, ">") => "<=",
+            (true, "<=") => ">",
+            (true, ">=") => "<",
+            (true, "key") => "-key",
+            (true, "-key") => "key",
+            (true, operator) => operator,
+        }
+        .to_string();
+        match operator.as_str() {
+            "key" => return self.instruction(Instruction::SkipNotKey(x)),
+            "-key" => return self.instruction(Instruction::SkipKey(x)),
+            "==" | "!=" | "<" | ">" | "<=" | ">=" => {}
+            _ => return Err(format!("unknown comparison '{}'", operator)),
+        }
+
+        let operand = self.next()?;
+        let y = self.register(&operand);
+        let nn = match y {
+            Some(_) => 0,
+            None => byte(self.value_of(&operand)?)?,
+        };
+        let instruction = match (operator.as_str(), y) {
+            ("==", Some(y)) => Instruction::SkipNotEqualRegister(x, y),
+            ("==", None) => Instruction::SkipNotEqual(x, nn),
+            ("!=", Some(y)) => Instruction::SkipEqualRegister(x, y),
+            ("!=", None) => Instruction::SkipEqual(x, nn),
+            // the others compare by subtracting in a temporary register, VF unless aliased,
+            // and skip on the borrow flag
+            (_, y) => {
+                let temp = self.aliases.get("compare-temp").copied().unwrap_or(0xF);
+                self.instruction(match y {
+                    Some(y) => Instruction::Move(temp, y),
+                    None => Instruction::Load(temp, nn),
+                })?;
+                let (subtract, skip): (fn(u8, u8) -> Instruction, _) = match operator.as_str() {
+                    ">" => (Instruction::Sub, Instruction::SkipEqual(0xF, 1)),
+                    "<" => (Instruction::SubReverse, Instruction::SkipEqual(0xF, 1)),
+                    ">=" => (Instruction::SubReverse, Instruction::SkipNotEqual(0xF, 1)),
+                    _ => (Instruction::Sub, Instruction::SkipNotEqual(0xF, 1)),
+                };
+                self.instruction(subtract(temp, x))?;
+                skip
+            }
+        };
+        self.instruction(instruction)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::Cpu;
+
+    fn opcodes(source: &str) -> Vec<u16> {
+        assemble(source)
+            .unwrap()
+            .chunks(2)
+            .map(|word| u16::from_be_bytes([word[0], word[1]]))
+            .collect()
+    }
+
+    #[test]
+    fn instructions() {
+        let source = "
+            : main
+                clear  return  ;  v3 := 0x2A  v3 += 1  v3 -= 1  v3 := v4  v3 |= v4  v3 &= v4
+                v3 ^= v4  v3 += v4  v3 -= v4  v3 >>= v4  v3 =- v4  v3 <<= v4  v3 := random 7
+                v3 := key  v3 := delay  delay := v3  buzzer := v3  i := 0x345  i += v3
+                i := hex v3  bcd v3  save v3  load v3  sprite v1 v2 5  jump 0x300
+                jump0 0x300  native 0x123  0xAB -1
+                hires  lores  scroll-down 4  exit  i := bighex v3  saveflags v3  save v1 - v2
+                plane 3  audio  pitch := v3  i := long 0x1234
+        ";
+        assert_eq!(
+            opcodes(source),
+            [
+                0x00E0, 0x00EE, 0x00EE, 0x632A, 0x7301, 0x73FF, 0x8340, 0x8341, 0x8342, 0x8343,
+                0x8344, 0x8345, 0x8346, 0x8347, 0x834E, 0xC307, 0xF30A, 0xF307, 0xF315, 0xF318,
+                0xA345, 0xF31E, 0xF329, 0xF333, 0xF355, 0xF365, 0xD125, 0x1300, 0xB300, 0x0123,
+                0xABFF, 0x00FF, 0x00FE, 0x00C4, 0x00FD, 0xF330, 0xF375, 0x5122, 0xF301, 0xF002,
+                0xF33A, 0xF000, 0x1234,
+            ]
+        );
+    }
+
+    #[test]
+    fn labels() {
+        // main isn't first, so 0x200 jumps to it
+        let source = "
+            : data 0x01 0x02
+            : main
+                i := data
+                sprite_at
+                jump forward
+            :next target : forward
+                v0 := 0
+            :unpack 0xA data
+            : sprite_at return
+            :org 0x300 :byte 7
+        ";
+        assert_eq!(
+            opcodes(&source[..source.find(":org").unwrap()]),
+            [0x1204, 0x0102, 0xA202, 0x2210, 0x120A, 0x6000, 0x60A2, 0x6102, 0x00EE]
+        );
+        let rom = assemble(source).unwrap();
+        assert_eq!(rom.len(), 0x101);
+        assert_eq!(rom[0x100], 7);
+
+        // :next points into the instruction after it
+        let rom = assemble(": main :next target v0 := 5 i := target").unwrap();
+        assert_eq!(rom, [0x60, 0x05, 0xA2, 0x01]);
+
+        // every kind of reference to a label defined later
+        assert_eq!(
+            opcodes(": main i := long later :unpack 1 later jump0 later : later"),
+            [0xF000, 0x020A, 0x6012, 0x610A, 0xB20A]
+        );
+    }
+
+    #[test]
+    fn directives() {
+        let source = "
+            :const SPEED 3
+            :alias x v5
+            :calc DOUBLE { SPEED * 2 + 1 }
+            :macro twice op value { x op value x op value }
+            : main
+                x := SPEED
+                twice += DOUBLE
+                # 1 << ( 4 | SPEED )
+                :byte { 1 << 4 | SPEED }
+                :byte { ( 2 * 3 ) + 1 }
+                :byte { @ 0x201 }
+                :calc HEIGHT { HERE - main }
+                :byte HEIGHT
+        ";
+        assert_eq!(
+            assemble(source).unwrap(),
+            [0x65, 0x03, 0x75, 0x09, 0x75, 0x09, 0x80, 0x07, 0x03, 0x09]
+        );
+    }
+
+    #[test]
+    fn control_flow() {
+        let source = "
+            : main
+                loop
+                    if v0 == 3 then v1 := 1
+                    if v0 != v2 begin
+                        v1 := 2
+                    else
+                        v1 := 3
+                    end
+                    while v0 key
+                    if v0 > 4 then clear
+                again
+        ";
+        assert_eq!(
+            opcodes(source),
+            [
+                0x4003, 0x6101, 0x9020, 0x120C, 0x6102, 0x120E, 0x6103, 0xE09E, 0x121C, 0x6F04,
+                0x8F05, 0x3F01, 0x00E0, 0x1200,
+            ]
+        );
+    }
+
+    // every comparison gives the same result as Rust's
+    #[test]
+    fn comparisons() {
+        for operator in ["==", "!=", "<", ">", "<=", ">="] {
+            for (a, b) in [(3, 3), (2, 5), (5, 2), (0, 255)] {
+                for rhs in ["v1".to_string(), b.to_string()] {
+                    let source = format!(
+                        ": main v0 := {} v1 := {} v2 := 0 if v0 {} {} then v2 := 1 \
+                         if v0 {} {} begin v3 := 1 else v3 := 0 end loop again",
+                        a, b, operator, rhs, operator, rhs
+                    );
+                    let rom = assemble(&source).unwrap();
+                    let mut cpu = Cpu::new();
+                    cpu.memory[0x200..0x200 + rom.len()].copy_from_slice(&rom);
+                    for _ in 0..20 {
+                        cpu.step();
+                    }
+                    let expected = match operator {
+                        "==" => a == b,
+                        "!=" => a != b,
+                        "<" => a < b,
+                        ">" => a > b,
+                        "<=" => a <= b,
+                        _ => a >= b,
+                    };
+                    assert_eq!(cpu.v[2] == 1, expected, "{}", source);
+                    assert_eq!(cpu.v[3] == 1, expected, "{}", source);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn errors() {
+        let error = |source: &str| assemble(source).unwrap_err().to_string();
+        assert_eq!(
+            error(": main\n  jump nowhere"),
+            "line 2: undefined label 'nowhere'"
+        );
+        assert_eq!(error("v0 := 1"), "the program has no label 'main'");
+        assert_eq!(
+            error(": main\nv0 := 256"),
+            "line 2: 256 doesn't fit in a byte"
+        );
+        assert_eq!(
+            error(": main\n:stringmode x \"a\" { }"),
+            "line 2: unsupported directive ':stringmode'"
+        );
+        assert_eq!(error(": main loop"), "'loop' without 'again'");
+        assert_eq!(error(": main : main"), "line 1: 'main' is already defined");
+        assert_eq!(
+            error(": main :macro m { m } m"),
+            "line 1: too many expansions of the macro 'm'"
+        );
+    }
+}
@@ -0,0 +1,182 @@
+use crate::chip8::COL;
+use crate::phosphor::Persistence;
+use std::error::Error;
+
+// the width of the screen at this scale still fits the 16 bit sizes of windows and GIFs
+pub const MAX_SCALE: u32 = u16::MAX as u32 / COL as u32;
+
+// Interpreter behaviours that differ between CHIP-8 implementations.
+// The names follow the quirk flags used by Octo.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Quirks {
+    // 8XY6/8XYE shift v[X] in place and ignore v[Y]
+    pub shift: bool,
+    // FX55/FX65 leave I unchanged
+    pub load_store: bool,
+    // 8XY4/8XY5/8XY7 write v[0xF] before the result
+    pub vf_order: bool,
+    // sprites are clipped at the screen edge instead of wrapping
+    pub clip: bool,
+    // DXYN waits for the next vertical blank
+    pub vblank: bool,
+    // BNNN becomes BXNN and jumps to XNN + v[X]
+    pub jump: bool,
+    // 8XY1/8XY2/8XY3 reset v[0xF] to 0
+    pub logic: bool,
+}
+
+impl Quirks {
+    pub const NAMES: [&'static str; 7] = [
+        "shift",
+        "load_store",
+        "vf_order",
+        "clip",
+        "vblank",
+        "jump",
+        "logic",
+    ];
+
+    // the platforms most ROMs are written for
+    pub const PROFILES: [&'static str; 3] = ["chip8", "schip", "xochip"];
+
+    // the quirks of the COSMAC VIP interpreter, SUPER-CHIP 1.1 and XO-CHIP
+    pub fn profile(name: &str) -> Option<Quirks> {
+        let quirks = Quirks::default();
+        match name {
+            "chip8" => Some(Quirks {
+                logic: true,
+                vblank: true,
+                clip: true,
+                ..quirks
+            }),
+            "schip" => Some(Quirks {
+                shift: true,
+                load_store: true,
+                jump: true,
+                clip: true,
+                ..quirks
+            }),
+            "xochip" => Some(quirks),
+            _ => None,
+        }
+    }
+
+    pub fn flag_mut(&mut self, name: &str) -> Option<&mut bool> {
+        match name {
+            "shift" => Some(&mut self.shift),
+            "load_store" => Some(&mut self.load_store),
+            "vf_order" => Some(&mut self.vf_order),
+            "clip" => Some(&mut self.clip),
+            "vblank" => Some(&mut self.vblank),
+            "jump" => Some(&mut self.jump),
+            "logic" => Some(&mut self.logic),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Palette {
+    pub background: u32,
+    pub fill: u32,
+    pub fill2: u32,
+    pub blend: u32,
+    pub buzz: u32,
+    pub quiet: u32,
+}
+
+impl Default for Palette {
+    fn default() -> Palette {
+        Palette {
+            background: 0x000000,
+            fill: 0xFFFFFF,
+            fill2: 0xFF6600,
+            blend: 0x662200,
+            buzz: 0x990000,
+            quiet: 0x000000,
+        }
+    }
+}
+
+impl Palette {
+    pub fn color_mut(&mut self, name: &str) -> Option<&mut u32> {
+        match name {
+            "background" => Some(&mut self.background),
+            "fill" => Some(&mut self.fill),
+            "fill2" => Some(&mut self.fill2),
+            "blend" => Some(&mut self.blend),
+            "buzz" => Some(&mut self.buzz),
+            "quiet" => Some(&mut self.quiet),
+            _ => None,
+        }
+    }
+}
+
+// how instructions are executed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Engine {
+    // decodes every instruction when it is executed
+    Interpreter,
+    // caches decoded blocks, see BlockCache
+    Cached,
+}
+
+impl Engine {
+    pub fn parse(name: &str) -> Option<Engine> {
+        match name {
+            "interpreter" => Some(Engine::Interpreter),
+            "cached" => Some(Engine::Cached),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Options {
+    // number of instructions executed per 60Hz frame
+    pub tickrate: u32,
+    pub quirks: Quirks,
+    pub palette: Palette,
+    pub persistence: Persistence,
+    // size of a CHIP-8 pixel in the window, screenshots and recordings, up to MAX_SCALE
+    pub scale: u32,
+    pub audio: bool,
+    // charge every instruction its cycles on the COSMAC VIP instead of using the tickrate
+    pub vip_timing: bool,
+    pub engine: Engine,
+    // room for the debug overlay next to the screen of the window
+    pub overlay: bool,
+}
+
+impl Default for Options {
+    fn default() -> Options {
+        Options {
+            tickrate: 10,
+            quirks: Quirks::default(),
+            palette: Palette::default(),
+            persistence: Persistence::Off,
+            scale: 10,
+            audio: true,
+            vip_timing: false,
+            engine: Engine::Interpreter,
+            overlay: false,
+        }
+    }
+}
+
+pub fn rgb(color: u32) -> (u8, u8, u8) {
+    ((color >> 16) as u8, (color >> 8) as u8, color as u8)
+}
+
+// parses colors in the "#RRGGBB" notation
+pub fn parse_color(text: &str) -> Result<u32, Box<dyn Error>> {
+    let hex = text.trim().trim_start_matches('#');
+    if hex.len() != 6 {
+        return Err(format!("Invalid color '{}'", text).into());
+    }
+    Ok(u32::from_str_radix(hex, 16)?)
+}
+
+pub fn format_color(color: u32) -> String {
+    format!("#{:06X}", color & 0xFFFFFF)
+}
@@ -0,0 +1,121 @@
+// The contents of the debug overlay next to the screen of the window: the registers, the
+// timers, the call stack and the instructions around PC. The keypad below them is drawn by
+// the frontend in the layout of KEYPAD.
+use crate::cpu::Cpu;
+use crate::instruction::Instruction;
+
+// the keys as arranged on the keypad of the COSMAC VIP
+pub const KEYPAD: [[usize; 4]; 4] = [
+    [0x1, 0x2, 0x3, 0xC],
+    [0x4, 0x5, 0x6, 0xD],
+    [0x7, 0x8, 0x9, 0xE],
+    [0xA, 0x0, 0xB, 0xF],
+];
+
+// the instructions listed before and after the one at PC
+const BEFORE: u16 = 3;
+const AFTER: u16 = 6;
+// return addresses shown, the innermost last
+const STACK: usize = 4;
+// the number of lines
+pub const LINES: usize = 8 + (BEFORE + 1 + AFTER) as usize;
+
+#[derive(Debug, PartialEq)]
+pub struct Line {
+    pub text: String,
+    // the instruction at PC
+    pub current: bool,
+}
+
+impl Line {
+    fn new(text: String) -> Line {
+        Line {
+            text,
+            current: false,
+        }
+    }
+}
+
+pub fn lines(cpu: &Cpu) -> Vec<Line> {
+    let mut lines = vec![Line::new(format!(
+        "PC {:#05X}  I {:#05X}  SP {}",
+        cpu.pc, cpu.i, cpu.sp
+    ))];
+    for (row, values) in cpu.v.chunks(4).enumerate() {
+        let registers: Vec<String> = values
+            .iter()
+            .enumerate()
+            .map(|(x, value)| format!("V{:X} {:02X}", row * 4 + x, value))
+            .collect();
+        lines.push(Line::new(registers.join("  ")));
+    }
+    lines.push(Line::new(format!(
+        "DT {}  ST {}",
+        cpu.delay_timer, cpu.sound_timer
+    )));
+
+    let calls = &cpu.stack[..cpu.sp as usize];
+    let mut stack: Vec<String> = calls
+        .iter()
+        .skip(calls.len().saturating_sub(STACK))
+        .map(|address| format!("{:#05X}", address))
+        .collect();
+    if calls.len() > STACK {
+        stack.insert(0, "...".to_string());
+    }
+    if stack.is_empty() {
+        stack.push("-".to_string());
+    }
+    lines.push(Line::new(format!("Stack {}", stack.join(" "))));
+    lines.push(Line::new(String::new()));
+
+    let memory = &cpu.memory;
+    let start = cpu.pc.saturating_sub(BEFORE * 2);
+    for address in (start..=cpu.pc + AFTER * 2).step_by(2) {
+        let at = |address: u16| memory[address as usize % memory.len()];
+        let opcode = u16::from_be_bytes([at(address), at(address + 1)]);
+        lines.push(Line {
+            text: format!(
+                "{:#05X}  {:04X}  {}",
+                address,
+                opcode,
+                Instruction::decode(opcode)
+            ),
+            current: address == cpu.pc,
+        });
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn machine_state() {
+        let mut cpu = Cpu::new();
+        cpu.pc = 0x202;
+        cpu.i = 0x300;
+        cpu.v[0xA] = 0x3F;
+        cpu.delay_timer = 60;
+        cpu.memory[0x200..0x206].copy_from_slice(&[0x60, 0x05, 0xD0, 0x15, 0x00, 0xEE]);
+        for address in [0x206, 0x31A, 0x400, 0x408, 0x410] {
+            cpu.stack[cpu.sp as usize] = address;
+            cpu.sp += 1;
+        }
+
+        let lines = lines(&cpu);
+        let text: Vec<&str> = lines.iter().map(|line| line.text.as_str()).collect();
+        assert_eq!(text[0], "PC 0x202  I 0x300  SP 5");
+        assert_eq!(text[3], "V8 00  V9 00  VA 3F  VB 00");
+        assert_eq!(text[5], "DT 60  ST 0");
+        assert_eq!(text[6], "Stack ... 0x31A 0x400 0x408 0x410");
+        assert_eq!(text[8], "0x1FC  0000  SYS 0x000");
+        assert_eq!(text[10], "0x200  6005  LD V0, 0x05");
+        assert_eq!(text[11], "0x202  D015  DRW V0, V1, 5");
+        assert_eq!(text[12], "0x204  00EE  RET");
+        assert_eq!(lines.len(), LINES);
+        let current: Vec<usize> = (0..lines.len()).filter(|&n| lines[n].current).collect();
+        assert_eq!(current, [11]);
+    }
+}
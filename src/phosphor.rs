@@ -0,0 +1,169 @@
+// Display filter that hides the flicker of sprites being XORed off and on again every frame.
+// It sits between the framebuffer of the cpu and the frontend, emulation is not affected.
+use crate::chip8::{COL, ROW};
+use crate::framebuffer::{self, Rect};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Persistence {
+    Off,
+    // a pixel that turns off keeps this fraction of its brightness each frame
+    Decay(f32),
+    // a pixel is lit if it was set in one of the last two frames
+    Or,
+}
+
+impl Persistence {
+    // 'off', 'or' or a decay factor between 0 and 1
+    pub fn parse(mode: &str) -> Option<Persistence> {
+        match mode {
+            "off" => Some(Persistence::Off),
+            "or" => Some(Persistence::Or),
+            decay => match decay.parse() {
+                Ok(decay) if (0.0..=1.0).contains(&decay) => Some(Persistence::Decay(decay)),
+                _ => None,
+            },
+        }
+    }
+}
+
+pub struct Phosphor {
+    mode: Persistence,
+    previous: [u8; ROW * COL],
+    previous_dirty: Option<Rect>,
+    // brightness of each pixel from 0 (background) to 255 (fill color)
+    output: [u8; ROW * COL],
+}
+
+impl Phosphor {
+    pub fn new(mode: Persistence) -> Phosphor {
+        Phosphor {
+            mode,
+            previous: [0; ROW * COL],
+            previous_dirty: None,
+            output: [0; ROW * COL],
+        }
+    }
+
+    // feeds the current framebuffer and the area of it that changed, see Framebuffer.
+    // Returns the area of the output that changed.
+    pub fn update(&mut self, map: &[u8; ROW * COL], dirty: Option<Rect>) -> Option<Rect> {
+        // only the changed pixels need a look, and with 'or' those of the last frame
+        let region = match self.mode {
+            Persistence::Off => dirty,
+            Persistence::Or => framebuffer::union(dirty, self.previous_dirty),
+            Persistence::Decay(_) => Some(Rect::FULL),
+        };
+        self.previous_dirty = dirty;
+
+        let mut changed = None;
+        for (x, y) in region.into_iter().flat_map(Rect::pixels) {
+            let i = x + y * COL;
+            let lit = if map[i] != 0 { 255 } else { 0 };
+            let level = match self.mode {
+                Persistence::Off => lit,
+                Persistence::Or if self.previous[i] != 0 => 255,
+                Persistence::Or => lit,
+                Persistence::Decay(decay) => {
+                    let faded = (self.output[i] as f32 * decay.clamp(0.0, 1.0)) as u8;
+                    lit.max(faded)
+                }
+            };
+            if level != self.output[i] {
+                let pixel = Rect {
+                    x,
+                    y,
+                    width: 1,
+                    height: 1,
+                };
+                changed = framebuffer::union(changed, Some(pixel));
+            }
+            self.output[i] = level;
+        }
+        self.previous = *map;
+        changed
+    }
+
+    pub fn frame(&self) -> &[u8; ROW * COL] {
+        &self.output
+    }
+}
+
+// mixes two 0xRRGGBB colors, a level of 0 gives `from` and 255 gives `to`
+pub fn blend(from: u32, to: u32, level: u8) -> u32 {
+    let mix = |shift: u32| {
+        let a = (from >> shift) & 0xFF;
+        let b = (to >> shift) & 0xFF;
+        ((a * (255 - level as u32) + b * level as u32) / 255) << shift
+    };
+    mix(16) | mix(8) | mix(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn or_keeps_last_frame() {
+        let mut phosphor = Phosphor::new(Persistence::Or);
+        let mut map = [0; ROW * COL];
+
+        map[3] = 1;
+        assert!(phosphor.update(&map, Some(Rect::FULL)).is_some());
+        assert_eq!(phosphor.frame()[3], 255);
+
+        map[3] = 0;
+        assert!(phosphor.update(&map, Some(Rect::FULL)).is_none());
+        assert_eq!(phosphor.frame()[3], 255);
+
+        assert!(phosphor.update(&map, Some(Rect::FULL)).is_some());
+        assert_eq!(phosphor.frame()[3], 0);
+    }
+
+    #[test]
+    fn off_repaints_dirty_pixels() {
+        let mut phosphor = Phosphor::new(Persistence::Off);
+        let mut map = [0; ROW * COL];
+        map[3 + COL] = 1;
+        assert_eq!(phosphor.update(&map, None), None);
+
+        let dirty = Rect {
+            x: 0,
+            y: 0,
+            width: 8,
+            height: 2,
+        };
+        let changed = Rect {
+            x: 3,
+            y: 1,
+            width: 1,
+            height: 1,
+        };
+        assert_eq!(phosphor.update(&map, Some(dirty)), Some(changed));
+        assert_eq!(phosphor.frame()[3 + COL], 255);
+    }
+
+    #[test]
+    fn decay_fades_out() {
+        let mut phosphor = Phosphor::new(Persistence::Decay(0.5));
+        let mut map = [0; ROW * COL];
+
+        map[0] = 1;
+        phosphor.update(&map, Some(Rect::FULL));
+        map[0] = 0;
+        phosphor.update(&map, Some(Rect::FULL));
+        assert_eq!(phosphor.frame()[0], 127);
+        phosphor.update(&map, Some(Rect::FULL));
+        assert_eq!(phosphor.frame()[0], 63);
+
+        map[0] = 1;
+        phosphor.update(&map, Some(Rect::FULL));
+        assert_eq!(phosphor.frame()[0], 255);
+    }
+
+    #[test]
+    fn blend_colors() {
+        assert_eq!(blend(0x000000, 0xFFFFFF, 0), 0x000000);
+        assert_eq!(blend(0x000000, 0xFFFFFF, 255), 0xFFFFFF);
+        assert_eq!(blend(0x000000, 0xFF8000, 127), 0x7F3F00);
+    }
+}
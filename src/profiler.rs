@@ -0,0 +1,258 @@
+// Counts where a ROM spends its time.
+//
+// The profiler follows the subroutine calls (2NNN/00EE) to build a call graph and to attribute
+// every instruction to its call stack, which report_folded() writes in the folded format of
+// flamegraph.pl and inferno. Subroutines are named after their address, the program itself
+// is "main".
+use crate::cpu::Cpu;
+use crate::instruction::Instruction;
+use std::collections::HashMap;
+
+// deeper call stacks are cut off, a broken ROM could otherwise call forever
+const MAX_DEPTH: usize = 64;
+const TOP_ADDRESSES: usize = 20;
+
+#[derive(Debug, Clone, Copy, Default)]
+struct Events {
+    count: u64,
+    // instruction and frame of the last event
+    last: Option<(u64, u64)>,
+    min: u64,
+    max: u64,
+    instructions: u64,
+    frames: u64,
+}
+
+impl Events {
+    fn record(&mut self, instruction: u64, frame: u64) {
+        if let Some((last_instruction, last_frame)) = self.last {
+            let interval = instruction - last_instruction;
+            if self.count == 1 || interval < self.min {
+                self.min = interval;
+            }
+            self.max = self.max.max(interval);
+            self.instructions += interval;
+            self.frames += frame - last_frame;
+        }
+        self.count += 1;
+        self.last = Some((instruction, frame));
+    }
+
+    fn summary(&self, name: &str) -> String {
+        if self.count < 2 {
+            return format!("{:<10} {} events\n", name, self.count);
+        }
+        let intervals = (self.count - 1) as f64;
+        format!(
+            "{:<10} {} events, every {:.1} instructions (min {}, max {}) or {:.2} frames\n",
+            name,
+            self.count,
+            self.instructions as f64 / intervals,
+            self.min,
+            self.max,
+            self.frames as f64 / intervals
+        )
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Profiler {
+    instructions: u64,
+    frames: u64,
+    hits: Vec<u64>,
+    kinds: HashMap<&'static str, u64>,
+    waits: Events,
+    draws: Events,
+    // number of calls from the caller to the callee, by their entry addresses
+    calls: HashMap<(u16, u16), u64>,
+    stack: Vec<u16>,
+    folded: HashMap<Vec<u16>, u64>,
+}
+
+impl Profiler {
+    pub fn new(entry: u16) -> Profiler {
+        Profiler {
+            instructions: 0,
+            frames: 0,
+            hits: vec![0; 4096],
+            kinds: HashMap::new(),
+            waits: Events::default(),
+            draws: Events::default(),
+            calls: HashMap::new(),
+            stack: vec![entry],
+            folded: HashMap::new(),
+        }
+    }
+
+    // called after the instruction at pc was executed
+    pub fn record(&mut self, pc: u16, cpu: &Cpu) {
+        let address = pc as usize % cpu.memory.len();
+        let opcode = u16::from_be_bytes([
+            cpu.memory[address],
+            cpu.memory[(address + 1) % cpu.memory.len()],
+        ]);
+        let instruction = Instruction::decode(opcode);
+
+        self.instructions += 1;
+        self.hits[address] += 1;
+        *self.kinds.entry(instruction.kind()).or_insert(0) += 1;
+        match self.folded.get_mut(self.stack.as_slice()) {
+            Some(count) => *count += 1,
+            None => {
+                self.folded.insert(self.stack.clone(), 1);
+            }
+        }
+
+        match instruction {
+            Instruction::Call(address) => {
+                let caller = *self.stack.last().unwrap();
+                *self.calls.entry((caller, address)).or_insert(0) += 1;
+                if self.stack.len() < MAX_DEPTH {
+                    self.stack.push(address);
+                }
+            }
+            Instruction::Return if self.stack.len() > 1 => {
+                self.stack.pop();
+            }
+            // FX0A repeats itself until a key is pressed, count only the end of the wait
+            Instruction::WaitKey(_) if cpu.pc != pc => {
+                self.waits.record(self.instructions, self.frames)
+            }
+            Instruction::Draw(..) => self.draws.record(self.instructions, self.frames),
+            _ => (),
+        }
+    }
+
+    pub fn end_frame(&mut self) {
+        self.frames += 1;
+    }
+
+    // the hottest addresses with their disassembly, instruction counts and the call graph
+    pub fn report(&self, memory: &[u8]) -> String {
+        let percent = |count: u64| count as f64 * 100.0 / self.instructions.max(1) as f64;
+        let mut out = format!(
+            "{} instructions in {} frames\n\nhottest addresses\n",
+            self.instructions, self.frames
+        );
+
+        let mut hits: Vec<(usize, u64)> = self
+            .hits
+            .iter()
+            .copied()
+            .enumerate()
+            .filter(|&(_, count)| count > 0)
+            .collect();
+        hits.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        for &(address, count) in hits.iter().take(TOP_ADDRESSES) {
+            let opcode =
+                u16::from_be_bytes([memory[address], memory[(address + 1) % memory.len()]]);
+            out += &format!(
+                "{:#05X}  {:04X}  {:<18} {:>12} {:6.2}%\n",
+                address,
+                opcode,
+                Instruction::decode(opcode).to_string(),
+                count,
+                percent(count)
+            );
+        }
+
+        out += "\ninstructions\n";
+        let mut kinds: Vec<(&str, u64)> = self.kinds.iter().map(|(&k, &c)| (k, c)).collect();
+        kinds.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+        for (kind, count) in kinds {
+            out += &format!("{:<18} {:>12} {:6.2}%\n", kind, count, percent(count));
+        }
+
+        out += "\nevents\n";
+        out += &self.waits.summary("key wait");
+        out += &self.draws.summary("draw");
+
+        out += "\ncall graph\n";
+        let mut calls: Vec<(&(u16, u16), &u64)> = self.calls.iter().collect();
+        calls.sort();
+        for (&(caller, callee), count) in calls {
+            out += &format!(
+                "{:<8} -> {:<8} {} calls\n",
+                self.name(caller),
+                self.name(callee),
+                count
+            );
+        }
+        out
+    }
+
+    // one line per call stack with the number of instructions executed in it
+    pub fn report_folded(&self) -> String {
+        let mut lines: Vec<String> = self
+            .folded
+            .iter()
+            .map(|(stack, count)| {
+                let names: Vec<String> = stack.iter().map(|&entry| self.name(entry)).collect();
+                format!("{} {}\n", names.join(";"), count)
+            })
+            .collect();
+        lines.sort();
+        lines.concat()
+    }
+
+    fn name(&self, entry: u16) -> String {
+        if entry == self.stack[0] {
+            "main".to_string()
+        } else {
+            format!("{:#05X}", entry)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // runs the program and profiles every instruction
+    fn profile(program: &[u8], steps: usize) -> (Profiler, Cpu) {
+        let mut cpu = Cpu::new();
+        cpu.memory[0x200..0x200 + program.len()].copy_from_slice(program);
+        let mut profiler = Profiler::new(0x200);
+        for _ in 0..steps {
+            let pc = cpu.pc;
+            cpu.step();
+            profiler.record(pc, &cpu);
+        }
+        (profiler, cpu)
+    }
+
+    #[test]
+    fn counts_and_call_graph() {
+        // main: call 0x206, jump to main; 0x206: add v0, 1, return
+        let program = [0x22, 0x06, 0x12, 0x00, 0x00, 0x00, 0x70, 0x01, 0x00, 0xEE];
+        let (profiler, cpu) = profile(&program, 8);
+
+        assert_eq!(profiler.instructions, 8);
+        assert_eq!(profiler.hits[0x206], 2);
+        assert_eq!(profiler.kinds["ADD Vx, byte"], 2);
+        assert_eq!(profiler.calls[&(0x200, 0x206)], 2);
+
+        let folded = profiler.report_folded();
+        assert_eq!(folded, "main 4\nmain;0x206 4\n");
+
+        let report = profiler.report(&cpu.memory);
+        assert!(report.contains("0x206  7001  ADD V0, 0x01"));
+        assert!(report.contains("main     -> 0x206    2 calls"));
+    }
+
+    #[test]
+    fn draw_intervals() {
+        // draw, two loads, draw, one load, draw
+        let program = [
+            0xD0, 0x01, 0x60, 0x00, 0x60, 0x00, 0xD0, 0x01, 0x60, 0x00, 0xD0, 0x01,
+        ];
+        let (profiler, _) = profile(&program, 6);
+        assert_eq!(profiler.draws.count, 3);
+        assert_eq!(profiler.draws.min, 2);
+        assert_eq!(profiler.draws.max, 3);
+        assert!(profiler
+            .draws
+            .summary("draw")
+            .contains("every 2.5 instructions"));
+    }
+}
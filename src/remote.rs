@@ -0,0 +1,423 @@
+// Server for controlling the emulator from other programs, e.g. automated tests of games.
+//
+// Clients connect to localhost over TCP and send one JSON object per line. Every request gets
+// a reply line with "ok" and, if the request had one, the same "id":
+//
+//     {"id": 1, "cmd": "press", "key": 5}
+//     {"id": 1, "ok": true}
+//     {"id": 2, "cmd": "read_mem", "address": 512, "length": 2}
+//     {"id": 2, "ok": true, "data": [0, 224]}
+//     {"cmd": "load", "path": "missing.ch8"}
+//     {"ok": false, "error": "No such file or directory (os error 2)"}
+//
+// The commands are
+//
+//     load PATH | ROM         restarts the machine with a file or an array of bytes and the
+//                             settings and cheats of that ROM, --watch follows the file
+//     press KEY, release KEY  keys pressed by a client stay pressed until they are released
+//     pause, resume           a paused machine only runs with step and run_frames
+//     step [COUNT]            executes up to MAX_STEPS instructions, pausing the machine first
+//     run_frames FRAMES       runs up to MAX_FRAMES frames right away, paused or not
+//     break ADDRESS           pauses the machine when PC reaches the address
+//     unbreak ADDRESS
+//     read_mem ADDRESS LENGTH, write_mem ADDRESS DATA
+//     screenshot [PATH]       the pixels as one string of digits per row, or a PNG file
+//     save_state [PATH]       the state as hex or into a file, see state.rs
+//     load_state PATH | STATE
+//     search [CONDITION]      starts or narrows a memory search, see cheat.rs, and returns the
+//                             candidates as [address, value] pairs
+//     freeze ADDRESS VALUE [NAME], unfreeze ADDRESS
+//     cheats                  the frozen addresses
+//     save_cheats             saves the cheats of the ROM
+//     quit                    ends the frontend
+//
+// and every client receives these events:
+//
+//     {"event": "frame", "frame": 1234}
+//     {"event": "breakpoint", "pc": 530}
+//     {"event": "sound", "on": true}
+//
+// Replies and events are written without blocking the emulator. A client that doesn't read
+// them is disconnected when MAX_PENDING bytes are waiting.
+use crate::cpu::Cpu;
+use serde_json::{json, Map, Value};
+use std::collections::HashSet;
+use std::error::Error;
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream};
+
+// the requests run synchronously within a frame, so they are limited to keep it short
+pub const MAX_STEPS: u64 = 100_000;
+pub const MAX_FRAMES: u64 = 600;
+const MAX_PENDING: usize = 1 << 20;
+
+struct Client {
+    stream: TcpStream,
+    buffer: Vec<u8>,
+    // replies and events the socket didn't take yet
+    pending: Vec<u8>,
+    closed: bool,
+}
+
+impl Client {
+    fn send(&mut self, message: &Value) {
+        if self.closed {
+            return;
+        }
+        self.pending
+            .extend_from_slice(message.to_string().as_bytes());
+        self.pending.push(b'\n');
+        if self.pending.len() > MAX_PENDING {
+            self.closed = true;
+            return;
+        }
+        self.flush();
+    }
+
+    // writes as much as the socket takes without blocking
+    fn flush(&mut self) {
+        while !self.pending.is_empty() {
+            match self.stream.write(&self.pending) {
+                Ok(0) => self.closed = true,
+                Ok(len) => {
+                    self.pending.drain(..len);
+                    continue;
+                }
+                Err(err) if err.kind() == ErrorKind::WouldBlock => {}
+                Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+                Err(_) => self.closed = true,
+            }
+            break;
+        }
+    }
+
+    // the complete lines received so far
+    fn read_lines(&mut self) -> Vec<String> {
+        let mut data = [0; 4096];
+        loop {
+            match self.stream.read(&mut data) {
+                Ok(0) => {
+                    self.closed = true;
+                    break;
+                }
+                Ok(len) => self.buffer.extend_from_slice(&data[..len]),
+                Err(err) if err.kind() == ErrorKind::WouldBlock => break,
+                Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+                Err(_) => {
+                    self.closed = true;
+                    break;
+                }
+            }
+        }
+        let mut lines = Vec::new();
+        while let Some(end) = self.buffer.iter().position(|&b| b == b'\n') {
+            let line: Vec<u8> = self.buffer.drain(..=end).collect();
+            lines.push(String::from_utf8_lossy(&line).trim().to_string());
+        }
+        lines
+    }
+}
+
+pub struct Remote {
+    listener: TcpListener,
+    clients: Vec<Client>,
+    breakpoints: HashSet<u16>,
+    paused: bool,
+    // the keys pressed by the clients, one bit per key
+    keys: u16,
+    sound: bool,
+    frames: u64,
+}
+
+impl Remote {
+    // doesn't wait for clients, they can connect at any time
+    pub fn listen(port: u16) -> io::Result<Remote> {
+        let listener = TcpListener::bind(("127.0.0.1", port))?;
+        listener.set_nonblocking(true)?;
+        println!("Listening for remote control on localhost:{}", port);
+        Ok(Remote {
+            listener,
+            clients: Vec::new(),
+            breakpoints: HashSet::new(),
+            paused: false,
+            keys: 0,
+            sound: false,
+            frames: 0,
+        })
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    pub fn pause(&mut self) {
+        self.paused = true;
+    }
+
+    pub fn has_breakpoints(&self) -> bool {
+        !self.breakpoints.is_empty()
+    }
+
+    // accepts new clients and reads their requests. Requests about the server and the keys
+    // are answered right away, the others are returned with the index of their client and
+    // have to be answered with reply().
+    pub fn poll(&mut self, cpu: &mut Cpu) -> Vec<(usize, Value)> {
+        for client in &mut self.clients {
+            client.flush();
+        }
+        self.clients.retain(|client| !client.closed);
+        while let Ok((stream, _)) = self.listener.accept() {
+            if stream.set_nonblocking(true).is_ok() {
+                let _ = stream.set_nodelay(true);
+                self.clients.push(Client {
+                    stream,
+                    buffer: Vec::new(),
+                    pending: Vec::new(),
+                    closed: false,
+                });
+            }
+        }
+
+        let mut requests = Vec::new();
+        for index in 0..self.clients.len() {
+            for line in self.clients[index].read_lines() {
+                if line.is_empty() {
+                    continue;
+                }
+                let request = match serde_json::from_str::<Value>(&line) {
+                    Ok(request) if request.is_object() => request,
+                    Ok(_) => json!({}),
+                    Err(err) => {
+                        let reply = json!({"ok": false, "error": err.to_string()});
+                        self.clients[index].send(&reply);
+                        continue;
+                    }
+                };
+                match self.handle(&request, cpu) {
+                    Some(result) => self.reply(index, &request, result),
+                    None => requests.push((index, request)),
+                }
+            }
+        }
+        requests
+    }
+
+    fn handle(&mut self, request: &Value, cpu: &mut Cpu) -> Option<Result<Value, Box<dyn Error>>> {
+        let result = match request["cmd"].as_str().unwrap_or("") {
+            "press" | "release" => key(request).map(|key| {
+                let pressed = request["cmd"] == "press";
+                self.keys = self.keys & !(1 << key) | (pressed as u16) << key;
+                cpu.keypad[key] = pressed as u8;
+                json!({})
+            }),
+            "pause" => {
+                self.paused = true;
+                Ok(json!({}))
+            }
+            "resume" => {
+                self.paused = false;
+                Ok(json!({}))
+            }
+            "break" => address(request).map(|address| {
+                self.breakpoints.insert(address);
+                json!({})
+            }),
+            "unbreak" => address(request).map(|address| {
+                self.breakpoints.remove(&address);
+                json!({})
+            }),
+            _ => return None,
+        };
+        Some(result)
+    }
+
+    pub fn reply(&mut self, client: usize, request: &Value, result: Result<Value, Box<dyn Error>>) {
+        let mut reply = Map::new();
+        if let Some(id) = request.get("id") {
+            reply.insert("id".to_string(), id.clone());
+        }
+        match result {
+            Ok(Value::Object(fields)) => {
+                reply.insert("ok".to_string(), true.into());
+                reply.extend(fields);
+            }
+            Ok(_) => {
+                reply.insert("ok".to_string(), true.into());
+            }
+            Err(err) => {
+                reply.insert("ok".to_string(), false.into());
+                reply.insert("error".to_string(), err.to_string().into());
+            }
+        }
+        if let Some(client) = self.clients.get_mut(client) {
+            client.send(&Value::Object(reply));
+        }
+    }
+
+    fn broadcast(&mut self, event: Value) {
+        for client in &mut self.clients {
+            client.send(&event);
+        }
+    }
+
+    // presses the keys of the clients again, after the frontend updated the keypad
+    pub fn apply_keys(&self, cpu: &mut Cpu) {
+        for (key, state) in cpu.keypad.iter_mut().enumerate() {
+            if self.keys & (1 << key) != 0 {
+                *state = 1;
+            }
+        }
+    }
+
+    // called after every executed instruction, returns whether the machine paused
+    pub fn check_breakpoint(&mut self, cpu: &Cpu) -> bool {
+        if !self.breakpoints.contains(&cpu.pc) {
+            return false;
+        }
+        self.paused = true;
+        self.broadcast(json!({"event": "breakpoint", "pc": cpu.pc}));
+        true
+    }
+
+    pub fn end_frame(&mut self, cpu: &Cpu) {
+        self.frames += 1;
+        self.broadcast(json!({"event": "frame", "frame": self.frames}));
+        if cpu.sound_active() != self.sound {
+            self.sound = cpu.sound_active();
+            self.broadcast(json!({"event": "sound", "on": self.sound}));
+        }
+    }
+}
+
+// a number parameter of a request
+pub fn number(request: &Value, name: &str, max: u64) -> Result<u64, Box<dyn Error>> {
+    request[name]
+        .as_u64()
+        .filter(|&value| value <= max)
+        .ok_or_else(|| format!("'{}' has to be a number up to {}", name, max).into())
+}
+
+fn key(request: &Value) -> Result<usize, Box<dyn Error>> {
+    Ok(number(request, "key", 15)? as usize)
+}
+
+fn address(request: &Value) -> Result<u16, Box<dyn Error>> {
+    Ok(number(request, "address", 0xFFF)? as u16)
+}
+
+// a parameter with an array of bytes
+pub fn bytes(request: &Value, name: &str) -> Result<Vec<u8>, Box<dyn Error>> {
+    let error = || format!("'{}' has to be an array of bytes", name);
+    request[name]
+        .as_array()
+        .ok_or_else(error)?
+        .iter()
+        .map(|byte| {
+            byte.as_u64()
+                .and_then(|byte| u8::try_from(byte).ok())
+                .ok_or_else(|| error().into())
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader};
+    use std::net::TcpListener;
+
+    fn connect() -> (Remote, BufReader<TcpStream>) {
+        // a free port
+        let port = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let remote = Remote::listen(port).unwrap();
+        let stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+        (remote, BufReader::new(stream))
+    }
+
+    fn read(client: &mut BufReader<TcpStream>) -> Value {
+        let mut line = String::new();
+        client.read_line(&mut line).unwrap();
+        serde_json::from_str(&line).unwrap()
+    }
+
+    // polls until the requests of the client arrived
+    fn poll(remote: &mut Remote, cpu: &mut Cpu, count: usize) -> Vec<(usize, Value)> {
+        let mut requests = Vec::new();
+        for _ in 0..1000 {
+            requests.extend(remote.poll(cpu));
+            if requests.len() >= count {
+                break;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(1));
+        }
+        requests
+    }
+
+    #[test]
+    fn requests_and_events() {
+        let (mut remote, mut client) = connect();
+        let mut cpu = Cpu::new();
+        client
+            .get_mut()
+            .write_all(
+                b"{\"id\": 1, \"cmd\": \"press\", \"key\": 5}\nnot json\n\
+                  {\"cmd\": \"break\", \"address\": 514}\n{\"id\": 7, \"cmd\": \"load\"}\n",
+            )
+            .unwrap();
+        let requests = poll(&mut remote, &mut cpu, 1);
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].1["id"], 7);
+        assert_eq!(read(&mut client), json!({"id": 1, "ok": true}));
+        assert_eq!(read(&mut client)["ok"], false);
+        assert_eq!(read(&mut client), json!({"ok": true}));
+        assert_eq!(cpu.keypad[5], 1);
+
+        remote.reply(0, &requests[0].1, Err("No ROM".into()));
+        assert_eq!(
+            read(&mut client),
+            json!({"id": 7, "ok": false, "error": "No ROM"})
+        );
+
+        cpu.keypad[5] = 0;
+        remote.apply_keys(&mut cpu);
+        assert_eq!(cpu.keypad[5], 1);
+        cpu.pc = 514;
+        assert!(remote.check_breakpoint(&cpu));
+        assert!(remote.is_paused());
+        assert_eq!(read(&mut client), json!({"event": "breakpoint", "pc": 514}));
+        cpu.sound_timer = 3;
+        remote.end_frame(&cpu);
+        assert_eq!(read(&mut client), json!({"event": "frame", "frame": 1}));
+        assert_eq!(read(&mut client), json!({"event": "sound", "on": true}));
+    }
+
+    #[test]
+    fn disconnects_clients_that_dont_read() {
+        let (mut remote, _client) = connect();
+        let mut cpu = Cpu::new();
+        while remote.clients.is_empty() {
+            remote.poll(&mut cpu);
+        }
+        let large = json!({"data": "x".repeat(64 * 1024)});
+        let mut sent = 0;
+        while !remote.clients[0].closed {
+            remote.reply(0, &json!({}), Ok(large.clone()));
+            sent += 1;
+            assert!(sent < 1000, "never disconnected");
+        }
+        remote.poll(&mut cpu);
+        assert!(remote.clients.is_empty());
+    }
+
+    #[test]
+    fn parameters() {
+        let request = json!({"key": 16, "data": [1, 2, 300]});
+        assert!(key(&request).is_err());
+        assert!(bytes(&request, "data").is_err());
+        assert_eq!(bytes(&json!({"data": [1, 2]}), "data").unwrap(), [1, 2]);
+    }
+}
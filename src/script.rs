@@ -0,0 +1,460 @@
+// Rhai scripts for automating the emulator, e.g. test scenarios for ROMs that don't need
+// to be compiled. rc8 script FILE ROM runs one headless and fails if an assertion failed:
+//
+//     on_pc(0x2A4, || print(`collision, ${v(3)} lives left`));
+//     on_write(0x300, |address, value| assert(value < 10, "score overflow"));
+//     on_frame(|frame| if frame == 600 { release(5) });
+//
+//     press(5);
+//     run_frames(900);
+//     assert_eq(read(0x300), 2, "points after ten seconds");
+//
+// The functions are
+//
+//     run_frames(FRAMES)          runs the machine, which calls the hooks
+//     frame()                     the frames run so far
+//     press(KEY), release(KEY)    keys stay pressed until they are released
+//     v(X), i(), pc(), delay_timer(), sound_timer()
+//     read(ADDRESS), write(ADDRESS, VALUE)
+//     pixel(X, Y)                 whether the pixel is on
+//     assert(CONDITION [, MESSAGE]), assert_eq(ACTUAL, EXPECTED [, MESSAGE])
+//     on_frame(|frame| ...)       called after every frame
+//     on_pc(ADDRESS, || ...)      called when PC reaches the address, before the instruction
+//     on_write(ADDRESS, |address, value| ...)
+//                                 called after FX33 or FX55 wrote to the address
+//
+// Failed assertions are reported at the end and don't stop the script, errors do.
+use crate::chip8::{Chip8, Watcher, COL, ROW};
+use crate::cpu::Cpu;
+use rhai::{Dynamic, Engine, EvalAltResult, FnPtr, NativeCallContext, AST, INT};
+use std::collections::HashMap;
+use std::mem;
+use std::ops::Range;
+use std::sync::{Arc, Mutex, Weak};
+
+type ScriptResult<T> = Result<T, Box<EvalAltResult>>;
+
+// a hook and its arguments
+type Call = (FnPtr, Vec<Dynamic>);
+
+#[derive(Default)]
+struct Hooks {
+    frame: Vec<FnPtr>,
+    pc: HashMap<u16, Vec<FnPtr>>,
+    write: HashMap<u16, Vec<FnPtr>>,
+}
+
+#[derive(Default)]
+struct State {
+    // taken by run_frames() while the machine runs
+    chip: Option<Chip8>,
+    // the cpu of the running machine while a hook is called
+    cpu: Option<Cpu>,
+    frames: u64,
+    hooks: Hooks,
+    // for calling the hooks
+    engine: Weak<Engine>,
+    ast: Option<Arc<AST>>,
+    assertions: usize,
+    failures: Vec<String>,
+    // an error in a hook, which ends the script
+    error: Option<String>,
+}
+
+impl State {
+    fn cpu(&mut self) -> &mut Cpu {
+        match (&mut self.cpu, &mut self.chip) {
+            (Some(cpu), _) => cpu,
+            (None, Some(chip)) => chip.cpu_mut(),
+            (None, None) => unreachable!("scripts only run with the machine or in a hook"),
+        }
+    }
+
+    fn check(&mut self, context: &NativeCallContext, passed: bool, message: String) {
+        self.assertions += 1;
+        if !passed {
+            let failure = match context.call_position().line() {
+                Some(line) => format!("line {}: {}", line, message),
+                None => message,
+            };
+            self.failures.push(failure);
+        }
+    }
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct Report {
+    pub assertions: usize,
+    pub failures: Vec<String>,
+    pub error: Option<String>,
+}
+
+impl Report {
+    pub fn passed(&self) -> bool {
+        self.failures.is_empty() && self.error.is_none()
+    }
+}
+
+pub struct Script {
+    engine: Arc<Engine>,
+    state: Arc<Mutex<State>>,
+}
+
+impl Script {
+    pub fn new(mut chip: Chip8) -> Script {
+        let state = Arc::new(Mutex::new(State::default()));
+        chip.set_watcher(Box::new(ScriptWatcher(Arc::downgrade(&state))));
+        state.lock().unwrap().chip = Some(chip);
+        let mut engine = Engine::new();
+        register(&mut engine, &state);
+        let engine = Arc::new(engine);
+        state.lock().unwrap().engine = Arc::downgrade(&engine);
+        Script { engine, state }
+    }
+
+    pub fn run(&self, source: &str) -> Report {
+        let result = self
+            .engine
+            .compile(source)
+            .map_err(|err| err.to_string())
+            .and_then(|ast| {
+                let ast = Arc::new(ast);
+                self.state.lock().unwrap().ast = Some(ast.clone());
+                self.engine.run_ast(&ast).map_err(|err| err.to_string())
+            });
+        let mut state = self.state.lock().unwrap();
+        Report {
+            assertions: state.assertions,
+            failures: mem::take(&mut state.failures),
+            error: result.err(),
+        }
+    }
+}
+
+struct ScriptWatcher(Weak<Mutex<State>>);
+
+impl Watcher for ScriptWatcher {
+    fn before(&mut self, cpu: &mut Cpu) -> bool {
+        let Some(state) = self.0.upgrade() else {
+            return true;
+        };
+        let calls = {
+            let hooks = &state.lock().unwrap().hooks;
+            let pc = hooks.pc.get(&cpu.pc).into_iter().flatten();
+            pc.map(|hook| (hook.clone(), Vec::new())).collect()
+        };
+        call_hooks(&state, cpu, calls)
+    }
+
+    fn after(&mut self, cpu: &mut Cpu, written: Range<u16>) -> bool {
+        let Some(state) = self.0.upgrade() else {
+            return true;
+        };
+        let mut calls = Vec::new();
+        {
+            let hooks = &state.lock().unwrap().hooks;
+            for address in written {
+                let Some(&value) = cpu.memory.get(address as usize) else {
+                    break;
+                };
+                for hook in hooks.write.get(&address).into_iter().flatten() {
+                    let args = vec![Dynamic::from(address as INT), Dynamic::from(value as INT)];
+                    calls.push((hook.clone(), args));
+                }
+            }
+        }
+        call_hooks(&state, cpu, calls)
+    }
+
+    fn end_frame(&mut self, cpu: &mut Cpu) {
+        let Some(state) = self.0.upgrade() else {
+            return;
+        };
+        let calls = {
+            let mut state = state.lock().unwrap();
+            state.frames += 1;
+            let frame = Dynamic::from(state.frames as INT);
+            let hooks = &state.hooks.frame;
+            hooks
+                .iter()
+                .map(|hook| (hook.clone(), vec![frame.clone()]))
+                .collect()
+        };
+        call_hooks(&state, cpu, calls);
+    }
+}
+
+// lends the cpu to the functions of the script while the hooks run, returns false if one of
+// them failed
+fn call_hooks(state: &Mutex<State>, cpu: &mut Cpu, calls: Vec<Call>) -> bool {
+    if calls.is_empty() {
+        return true;
+    }
+    let (Some(engine), Some(ast)) = ({
+        let state = state.lock().unwrap();
+        (state.engine.upgrade(), state.ast.clone())
+    }) else {
+        return true;
+    };
+    state.lock().unwrap().cpu = Some(mem::take(cpu));
+    let mut result = Ok(());
+    for (hook, args) in calls {
+        result = hook.call::<Dynamic>(&engine, &ast, args).map(|_| ());
+        if result.is_err() {
+            break;
+        }
+    }
+    let mut state = state.lock().unwrap();
+    *cpu = state.cpu.take().unwrap_or_default();
+    match result {
+        Ok(()) => true,
+        Err(err) => {
+            state.error = Some(err.to_string());
+            false
+        }
+    }
+}
+
+fn run_frames(state: &Mutex<State>, frames: INT) -> ScriptResult<()> {
+    let mut chip = state
+        .lock()
+        .unwrap()
+        .chip
+        .take()
+        .ok_or("run_frames() can't be called in a hook")?;
+    for _ in 0..frames {
+        chip.run_frame();
+        if state.lock().unwrap().error.is_some() {
+            break;
+        }
+    }
+    let mut state = state.lock().unwrap();
+    state.chip = Some(chip);
+    match state.error.take() {
+        Some(err) => Err(err.into()),
+        None => Ok(()),
+    }
+}
+
+// a number parameter between 0 and max
+fn number(name: &str, value: INT, max: INT) -> ScriptResult<usize> {
+    if (0..=max).contains(&value) {
+        Ok(value as usize)
+    } else {
+        Err(format!("{} {} is out of range (0 to {})", name, value, max).into())
+    }
+}
+
+fn register(engine: &mut Engine, state: &Arc<Mutex<State>>) {
+    let s = state.clone();
+    engine.register_fn("run_frames", move |frames: INT| run_frames(&s, frames));
+    let s = state.clone();
+    engine.register_fn("frame", move || s.lock().unwrap().frames as INT);
+
+    for (name, pressed) in [("press", 1), ("release", 0)] {
+        let s = state.clone();
+        engine.register_fn(name, move |key: INT| -> ScriptResult<()> {
+            s.lock().unwrap().cpu().keypad[number("key", key, 15)?] = pressed;
+            Ok(())
+        });
+    }
+
+    let s = state.clone();
+    engine.register_fn("v", move |x: INT| -> ScriptResult<INT> {
+        Ok(s.lock().unwrap().cpu().v[number("register", x, 15)?] as INT)
+    });
+    type Register = fn(&Cpu) -> INT;
+    let registers: [(&str, Register); 4] = [
+        ("i", |cpu| cpu.i as INT),
+        ("pc", |cpu| cpu.pc as INT),
+        ("delay_timer", |cpu| cpu.delay_timer as INT),
+        ("sound_timer", |cpu| cpu.sound_timer as INT),
+    ];
+    for (name, register) in registers {
+        let s = state.clone();
+        engine.register_fn(name, move || register(s.lock().unwrap().cpu()));
+    }
+
+    let s = state.clone();
+    engine.register_fn("read", move |address: INT| -> ScriptResult<INT> {
+        let mut state = s.lock().unwrap();
+        let memory = &state.cpu().memory;
+        Ok(memory[number("address", address, memory.len() as INT - 1)?] as INT)
+    });
+    let s = state.clone();
+    engine.register_fn(
+        "write",
+        move |address: INT, value: INT| -> ScriptResult<()> {
+            let mut state = s.lock().unwrap();
+            let memory = &mut state.cpu().memory;
+            let address = number("address", address, memory.len() as INT - 1)?;
+            memory[address] = number("value", value, 0xFF)? as u8;
+            Ok(())
+        },
+    );
+    let s = state.clone();
+    engine.register_fn("pixel", move |x: INT, y: INT| -> ScriptResult<bool> {
+        let (x, y) = (
+            number("x", x, COL as INT - 1)?,
+            number("y", y, ROW as INT - 1)?,
+        );
+        Ok(s.lock().unwrap().cpu().graphics.pixels()[y * COL + x] != 0)
+    });
+
+    let s = state.clone();
+    engine.register_fn("assert", move |context: NativeCallContext, passed: bool| {
+        let message = "assertion failed".to_string();
+        s.lock().unwrap().check(&context, passed, message);
+    });
+    let s = state.clone();
+    engine.register_fn(
+        "assert",
+        move |context: NativeCallContext, passed: bool, message: &str| {
+            s.lock()
+                .unwrap()
+                .check(&context, passed, message.to_string());
+        },
+    );
+    let s = state.clone();
+    engine.register_fn(
+        "assert_eq",
+        move |context: NativeCallContext, actual: INT, expected: INT| {
+            let message = format!("expected {}, got {}", expected, actual);
+            s.lock()
+                .unwrap()
+                .check(&context, actual == expected, message);
+        },
+    );
+    let s = state.clone();
+    engine.register_fn(
+        "assert_eq",
+        move |context: NativeCallContext, actual: INT, expected: INT, message: &str| {
+            let message = format!("{}: expected {}, got {}", message, expected, actual);
+            s.lock()
+                .unwrap()
+                .check(&context, actual == expected, message);
+        },
+    );
+
+    let s = state.clone();
+    engine.register_fn("on_frame", move |hook: FnPtr| {
+        s.lock().unwrap().hooks.frame.push(hook);
+    });
+    let s = state.clone();
+    engine.register_fn(
+        "on_pc",
+        move |address: INT, hook: FnPtr| -> ScriptResult<()> {
+            let address = number("address", address, 0xFFF)? as u16;
+            let mut state = s.lock().unwrap();
+            state.hooks.pc.entry(address).or_default().push(hook);
+            Ok(())
+        },
+    );
+    let s = state.clone();
+    engine.register_fn(
+        "on_write",
+        move |address: INT, hook: FnPtr| -> ScriptResult<()> {
+            let address = number("address", address, 0xFFF)? as u16;
+            let mut state = s.lock().unwrap();
+            state.hooks.write.entry(address).or_default().push(hook);
+            Ok(())
+        },
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::assemble;
+
+    const COUNTER: &str = include_str!("../tests/roms/counter.asm");
+
+    fn script() -> Script {
+        let mut chip = Chip8::new();
+        chip.load_rom(assemble(COUNTER).unwrap()).unwrap();
+        Script::new(chip)
+    }
+
+    #[test]
+    fn keys_and_assertions() {
+        let report = script().run(
+            "
+            press(5);
+            run_frames(12);
+            release(5);
+            run_frames(5);
+            assert_eq(frame(), 17);
+            assert_eq(read(0x301) * 10 + read(0x302), v(0));
+            assert(pc() >= 0x200 && i() == 0x300);
+            assert_eq(read(0x300), 1, \"hundreds\");
+            assert(pixel(0, 0));
+            ",
+        );
+        assert_eq!(report.assertions, 5);
+        assert!(!report.passed());
+        assert_eq!(
+            report.failures,
+            [
+                "line 9: hundreds: expected 1, got 0",
+                "line 10: assertion failed"
+            ]
+        );
+        assert_eq!(report.error, None);
+    }
+
+    #[test]
+    fn hooks() {
+        let report = script().run(
+            "
+            let writes = [];
+            let frames = 0;
+            let loops = 0;
+            on_write(0x302, |address, value| writes.push([address, value]));
+            on_frame(|frame| frames = frame);
+            on_pc(0x202, || {
+                loops += 1;
+                assert_eq(pc(), 0x202);
+            });
+            on_frame(|frame| if frame == 3 { press(5) });
+            run_frames(6);
+            assert_eq(frames, 6);
+            assert(loops > 0);
+            assert_eq(writes.len(), loops);
+            assert_eq(writes[-1][1], v(0) % 10);
+            assert(v(0) > 0);
+            ",
+        );
+        assert_eq!(report.error, None);
+        assert_eq!(report.failures, Vec::<String>::new());
+        assert!(report.passed());
+    }
+
+    #[test]
+    fn pc_hook_before_the_instruction() {
+        let report = script().run(
+            "
+            let calls = 0;
+            let counts = [];
+            on_pc(0x200, || calls += 1);
+            // ADD V0, 1
+            on_pc(0x206, || counts.push(v(0)));
+            press(5);
+            run_frames(3);
+            assert_eq(calls, 1);
+            assert_eq(counts[0], 0);
+            assert_eq(counts.len(), v(0));
+            ",
+        );
+        assert_eq!(report.error, None);
+        assert_eq!(report.failures, Vec::<String>::new());
+        assert_eq!(report.assertions, 3);
+    }
+
+    #[test]
+    fn errors() {
+        let report = script().run("on_pc(0x202, || run_frames(1)); run_frames(2);");
+        assert!(report.error.unwrap().contains("can't be called in a hook"));
+        assert!(script().run("press(16);").error.is_some());
+        assert!(script().run("run_frames(").error.is_some());
+    }
+}
@@ -0,0 +1,100 @@
+// Save states: a snapshot of the machine with a fixed size, as libretro frontends expect.
+//
+//     "RC8S" and the version byte
+//     memory, V0-VF, I, PC, SP, the stack, the delay and the sound timer
+//     the rows of every plane of the framebuffer
+//
+// Numbers wider than a byte are big-endian. The options, the keypad and the state of the
+// random number generator are not part of it.
+use crate::chip8::ROW;
+use crate::cpu::Cpu;
+use crate::framebuffer::PLANES;
+use std::error::Error;
+
+const MAGIC: &[u8; 4] = b"RC8S";
+const VERSION: u8 = 1;
+
+pub const SIZE: usize = MAGIC.len() + 1 + 4096 + 16 + 3 * 2 + 16 * 2 + 2 + PLANES * ROW * 8;
+
+pub fn save(cpu: &Cpu) -> Vec<u8> {
+    let mut data = Vec::with_capacity(SIZE);
+    data.extend_from_slice(MAGIC);
+    data.push(VERSION);
+    data.extend_from_slice(&cpu.memory);
+    data.extend_from_slice(&cpu.v);
+    for word in [cpu.i, cpu.pc, cpu.sp].iter().chain(&cpu.stack) {
+        data.extend_from_slice(&word.to_be_bytes());
+    }
+    data.push(cpu.delay_timer);
+    data.push(cpu.sound_timer);
+    for row in cpu.graphics.planes().iter().flatten() {
+        data.extend_from_slice(&row.to_be_bytes());
+    }
+    data
+}
+
+// the cpu is left untouched if the data isn't a valid state
+pub fn load(cpu: &mut Cpu, data: &[u8]) -> Result<(), Box<dyn Error>> {
+    if data.len() != SIZE || &data[..4] != MAGIC {
+        return Err("Not a save state of rc8".into());
+    }
+    if data[4] != VERSION {
+        return Err(format!("Unsupported save state version {}", data[4]).into());
+    }
+    let mut rest = &data[5..];
+    let mut take = |len: usize| {
+        let (bytes, tail) = rest.split_at(len);
+        rest = tail;
+        bytes
+    };
+
+    cpu.memory.copy_from_slice(take(4096));
+    cpu.v.copy_from_slice(take(16));
+    let mut word = || u16::from_be_bytes(take(2).try_into().unwrap());
+    (cpu.i, cpu.pc, cpu.sp) = (word(), word() & 0xFFF, word() % 16);
+    for address in cpu.stack.iter_mut() {
+        *address = word();
+    }
+    let timers = take(2);
+    (cpu.delay_timer, cpu.sound_timer) = (timers[0], timers[1]);
+    let mut planes = [[0; ROW]; PLANES];
+    for row in planes.iter_mut().flatten() {
+        *row = u64::from_be_bytes(take(8).try_into().unwrap());
+    }
+    cpu.graphics.set_planes(planes);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let mut cpu = Cpu::new();
+        cpu.load_font();
+        cpu.v[3] = 7;
+        cpu.i = 0x123;
+        cpu.pc = 0x2A4;
+        cpu.stack[0] = 0x202;
+        cpu.sp = 1;
+        cpu.sound_timer = 9;
+        cpu.graphics.draw(1, 60, 30, &[0xFF, 0xFF, 0xFF], false);
+        let data = save(&cpu);
+        assert_eq!(data.len(), SIZE);
+
+        let mut restored = Cpu::new();
+        load(&mut restored, &data).unwrap();
+        assert_eq!(restored.memory, cpu.memory);
+        assert_eq!(restored.v, cpu.v);
+        assert_eq!((restored.i, restored.pc, restored.sp), (0x123, 0x2A4, 1));
+        assert_eq!(restored.stack, cpu.stack);
+        assert_eq!(restored.sound_timer, 9);
+        assert_eq!(restored.graphics.pixels(), cpu.graphics.pixels());
+
+        assert!(load(&mut restored, &data[1..]).is_err());
+        let mut newer = data.clone();
+        newer[4] = VERSION + 1;
+        assert!(load(&mut restored, &newer).is_err());
+    }
+}
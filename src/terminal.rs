@@ -0,0 +1,262 @@
+use crate::chip8::{Hotkey, COL, ROW};
+use crate::framebuffer::Rect;
+use crate::keymap::Keymap;
+use crate::options::{rgb, Palette};
+use crate::phosphor::blend;
+use crossterm::event::{
+    self, Event, KeyCode, KeyEventKind, KeyModifiers, KeyboardEnhancementFlags,
+    PopKeyboardEnhancementFlags, PushKeyboardEnhancementFlags,
+};
+use crossterm::style::{Color, Print, ResetColor, SetBackgroundColor, SetForegroundColor};
+use crossterm::{cursor, execute, queue, terminal};
+use std::io::{self, Stdout, Write};
+use std::ops::Range;
+use std::time::Duration;
+
+// Most terminals only report key presses, so a key counts as held for this many frames after
+// its last (auto repeated) press. Terminals that support the kitty keyboard protocol also
+// report releases and don't need this.
+const HOLD_FRAMES: u8 = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Glyphs {
+    // one cell shows 1x2 pixels using '▀' with separate fore- and background colors
+    HalfBlock,
+    // one cell shows 2x4 pixels using braille dots
+    Braille,
+}
+
+pub struct Terminal {
+    out: Stdout,
+    palette: Palette,
+    glyphs: Glyphs,
+    held: [u8; 16],
+    reports_releases: bool,
+    // host key and the CHIP-8 key it is bound to
+    keys: Vec<(KeyCode, usize)>,
+}
+
+impl Terminal {
+    pub fn new(palette: Palette, glyphs: Glyphs, keymap: &Keymap) -> io::Result<Terminal> {
+        let keys = bindings(keymap);
+        terminal::enable_raw_mode()?;
+        let mut out = io::stdout();
+        execute!(
+            out,
+            terminal::EnterAlternateScreen,
+            terminal::Clear(terminal::ClearType::All),
+            cursor::Hide
+        )?;
+
+        let reports_releases = terminal::supports_keyboard_enhancement().unwrap_or(false);
+        if reports_releases {
+            execute!(
+                out,
+                PushKeyboardEnhancementFlags(KeyboardEnhancementFlags::REPORT_EVENT_TYPES)
+            )?;
+        }
+
+        Ok(Terminal {
+            out,
+            palette,
+            glyphs,
+            held: [0; 16],
+            reports_releases,
+            keys,
+        })
+    }
+
+    // e.g. the keymap of the ROM --watch reloaded
+    pub fn set_keymap(&mut self, keymap: &Keymap) {
+        self.keys = bindings(keymap);
+    }
+
+    // reads all pending key events, Esc and Ctrl-C quit and F12 takes a screenshot
+    pub fn update_keypad(&mut self, keypad: &mut [u8; 16]) -> io::Result<Vec<Hotkey>> {
+        let mut hotkeys = Vec::new();
+        if !self.reports_releases {
+            for held in self.held.iter_mut() {
+                *held = held.saturating_sub(1);
+            }
+        }
+
+        while event::poll(Duration::ZERO)? {
+            let key = match event::read()? {
+                Event::Key(key) => key,
+                _ => continue,
+            };
+            let ctrl_c =
+                key.code == KeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL);
+            if key.code == KeyCode::Esc || ctrl_c {
+                hotkeys.push(Hotkey::Quit);
+            }
+            if key.code == KeyCode::F(12) && key.kind == KeyEventKind::Press {
+                hotkeys.push(Hotkey::Screenshot);
+            }
+
+            let code = match key.code {
+                KeyCode::Char(c) => KeyCode::Char(c.to_ascii_lowercase()),
+                code => code,
+            };
+            for &(_, pos) in self.keys.iter().filter(|(k, _)| *k == code) {
+                self.held[pos] = match key.kind {
+                    KeyEventKind::Release => 0,
+                    _ => HOLD_FRAMES,
+                };
+            }
+        }
+
+        for (state, &held) in keypad.iter_mut().zip(self.held.iter()) {
+            *state = (held > 0) as u8;
+        }
+        Ok(hotkeys)
+    }
+
+    // map holds the brightness of each pixel, see Phosphor. Only the cells covering rect are
+    // redrawn.
+    pub fn draw(&mut self, map: &[u8; ROW * COL], rect: Rect) -> io::Result<()> {
+        let (width, height) = match self.glyphs {
+            Glyphs::HalfBlock => (1, 2),
+            Glyphs::Braille => (2, 4),
+        };
+        // whole cells
+        let columns = rect.x / width * width..(rect.x + rect.width).div_ceil(width) * width;
+        let rows = rect.y / height * height..(rect.y + rect.height).div_ceil(height) * height;
+        match self.glyphs {
+            Glyphs::HalfBlock => self.draw_half_blocks(map, columns, rows)?,
+            Glyphs::Braille => self.draw_braille(map, columns, rows)?,
+        }
+        self.out.flush()
+    }
+
+    fn draw_half_blocks(
+        &mut self,
+        map: &[u8; ROW * COL],
+        columns: Range<usize>,
+        rows: Range<usize>,
+    ) -> io::Result<()> {
+        let mut colors = None;
+        for y in rows.step_by(2) {
+            queue!(
+                self.out,
+                cursor::MoveTo(columns.start as u16, (y / 2) as u16)
+            )?;
+            for x in columns.clone() {
+                let top = self.color(map[x + y * COL]);
+                let bottom = self.color(map[x + (y + 1) * COL]);
+                if colors != Some((top, bottom)) {
+                    queue!(
+                        self.out,
+                        SetForegroundColor(top),
+                        SetBackgroundColor(bottom)
+                    )?;
+                    colors = Some((top, bottom));
+                }
+                queue!(self.out, Print('▀'))?;
+            }
+        }
+        queue!(self.out, ResetColor)
+    }
+
+    fn draw_braille(
+        &mut self,
+        map: &[u8; ROW * COL],
+        columns: Range<usize>,
+        rows: Range<usize>,
+    ) -> io::Result<()> {
+        // bit of each dot in a braille cell, indexed by [y][x]
+        const DOTS: [[u32; 2]; 4] = [[0x01, 0x08], [0x02, 0x10], [0x04, 0x20], [0x40, 0x80]];
+
+        let (fill, background) = (self.color(255), self.color(0));
+        queue!(
+            self.out,
+            SetForegroundColor(fill),
+            SetBackgroundColor(background)
+        )?;
+        for y in rows.step_by(4) {
+            queue!(
+                self.out,
+                cursor::MoveTo((columns.start / 2) as u16, (y / 4) as u16)
+            )?;
+            for x in columns.clone().step_by(2) {
+                let mut bits = 0;
+                for (dy, row) in DOTS.iter().enumerate() {
+                    for (dx, &bit) in row.iter().enumerate() {
+                        if map[x + dx + (y + dy) * COL] >= 128 {
+                            bits |= bit;
+                        }
+                    }
+                }
+                queue!(self.out, Print(char::from_u32(0x2800 + bits).unwrap()))?;
+            }
+        }
+        queue!(self.out, ResetColor)
+    }
+
+    pub fn status(&mut self, text: &str) -> io::Result<()> {
+        let row = match self.glyphs {
+            Glyphs::HalfBlock => ROW / 2,
+            Glyphs::Braille => ROW / 4,
+        };
+        queue!(
+            self.out,
+            cursor::MoveTo(0, row as u16),
+            terminal::Clear(terminal::ClearType::UntilNewLine),
+            Print(text)
+        )?;
+        self.out.flush()
+    }
+
+    pub fn beep(&mut self) -> io::Result<()> {
+        queue!(self.out, Print('\x07'))?;
+        self.out.flush()
+    }
+
+    fn color(&self, value: u8) -> Color {
+        let (r, g, b) = rgb(blend(self.palette.background, self.palette.fill, value));
+        Color::Rgb { r, g, b }
+    }
+}
+
+impl Drop for Terminal {
+    fn drop(&mut self) {
+        if self.reports_releases {
+            let _ = execute!(self.out, PopKeyboardEnhancementFlags);
+        }
+        let _ = execute!(self.out, cursor::Show, terminal::LeaveAlternateScreen);
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+// host key and CHIP-8 key of every binding with a known name
+fn bindings(keymap: &Keymap) -> Vec<(KeyCode, usize)> {
+    let mut keys = Vec::new();
+    for (name, key) in keymap.bindings() {
+        match key_code(name) {
+            Some(code) => keys.push((code, key)),
+            None => eprintln!("Unknown key '{}' in keymap", name),
+        }
+    }
+    keys
+}
+
+// translates the key names of a Keymap, letters are matched case insensitive
+fn key_code(name: &str) -> Option<KeyCode> {
+    let mut chars = name.chars();
+    if let (Some(c), None) = (chars.next(), chars.next()) {
+        return Some(KeyCode::Char(c.to_ascii_lowercase()));
+    }
+
+    let name = name.to_lowercase();
+    match name.as_str() {
+        "up" => Some(KeyCode::Up),
+        "down" => Some(KeyCode::Down),
+        "left" => Some(KeyCode::Left),
+        "right" => Some(KeyCode::Right),
+        "space" => Some(KeyCode::Char(' ')),
+        "return" | "enter" => Some(KeyCode::Enter),
+        "tab" => Some(KeyCode::Tab),
+        "backspace" => Some(KeyCode::Backspace),
+        _ => name.strip_prefix('f')?.parse().ok().map(KeyCode::F),
+    }
+}
@@ -0,0 +1,161 @@
+// Timing of the COSMAC VIP, for ROMs that depend on the speed of the original interpreter.
+//
+// The CDP1802 of the VIP executes a machine cycle every 8 clock cycles at 1.7609 MHz, which
+// makes 3668 machine cycles per 60 Hz frame. The display DMA and the interrupt routine take
+// part of every frame, the interpreter gets the rest. The cost of each instruction is an
+// approximation derived from the timings in Matthew Mikolay's "Mastering CHIP-8".
+//
+// With the vblank quirk DXYN waits for the interrupt, so a draw always ends the frame and is
+// executed as the first instruction of the next one.
+use crate::cpu::Cpu;
+use crate::instruction::Instruction;
+
+pub const FRAME_CYCLES: u64 = 3668;
+// 128 scanlines with 8 bytes of DMA each
+const DISPLAY_CYCLES: u64 = 1024;
+// the interrupt routine, which also counts down the timers
+const INTERRUPT_CYCLES: u64 = 40;
+const PROGRAM_CYCLES: u64 = FRAME_CYCLES - DISPLAY_CYCLES - INTERRUPT_CYCLES;
+
+// machine cycles of an instruction
+pub fn cost(instruction: Instruction, cpu: &Cpu) -> u64 {
+    match instruction {
+        Instruction::Clear => 24,
+        Instruction::Sys(_)
+        | Instruction::Return
+        | Instruction::Jump(_)
+        | Instruction::Call(_)
+        | Instruction::JumpOffset(_) => 23,
+        Instruction::SkipEqual(..) | Instruction::SkipNotEqual(..) | Instruction::LoadIndex(_) => {
+            12
+        }
+        Instruction::SkipEqualRegister(..)
+        | Instruction::SkipNotEqualRegister(..)
+        | Instruction::SkipKey(_)
+        | Instruction::SkipNotKey(_) => 16,
+        Instruction::Load(..) => 6,
+        Instruction::Add(..)
+        | Instruction::GetDelay(_)
+        | Instruction::WaitKey(_)
+        | Instruction::SetDelay(_)
+        | Instruction::SetSound(_)
+        | Instruction::Unknown(_) => 10,
+        Instruction::Move(..)
+        | Instruction::Or(..)
+        | Instruction::And(..)
+        | Instruction::Xor(..)
+        | Instruction::AddRegister(..)
+        | Instruction::Sub(..)
+        | Instruction::ShiftRight(..)
+        | Instruction::SubReverse(..)
+        | Instruction::ShiftLeft(..) => 44,
+        Instruction::Random(..) => 36,
+        // every row is shifted into place and XORed into two bytes of the display
+        Instruction::Draw(x, _, n) => {
+            let shifted = !cpu.v[x as usize].is_multiple_of(8);
+            26 + n as u64 * if shifted { 22 } else { 15 }
+        }
+        Instruction::AddIndex(_) => 19,
+        Instruction::Font(_) => 20,
+        Instruction::Bcd(_) => 204,
+        Instruction::Store(x) | Instruction::Restore(x) => 14 + 14 * (x as u64 + 1),
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Timing {
+    // machine cycles since the start
+    pub cycles: u64,
+    // cycles left for the interpreter in the current frame, negative after an overrun
+    budget: i64,
+    // no instruction was executed in the frame yet
+    first: bool,
+}
+
+impl Timing {
+    pub fn start_frame(&mut self) {
+        self.cycles += DISPLAY_CYCLES + INTERRUPT_CYCLES;
+        self.budget += PROGRAM_CYCLES as i64;
+        self.first = true;
+    }
+
+    // Returns the cost of the next instruction, or None if the frame is over. An instruction
+    // that runs past the end of the frame takes the cycles of the next one.
+    pub fn next(&mut self, cpu: &Cpu) -> Option<u64> {
+        if self.budget <= 0 {
+            return None;
+        }
+        let pc = cpu.pc as usize;
+        let opcode = u16::from_be_bytes([
+            cpu.memory[pc % cpu.memory.len()],
+            cpu.memory[(pc + 1) % cpu.memory.len()],
+        ]);
+        let instruction = Instruction::decode(opcode);
+
+        if matches!(instruction, Instruction::Draw(..)) && cpu.quirks.vblank && !self.first {
+            // wait for the interrupt
+            self.cycles += self.budget as u64;
+            self.budget = 0;
+            return None;
+        }
+        Some(cost(instruction, cpu))
+    }
+
+    pub fn charge(&mut self, cycles: u64) {
+        self.cycles += cycles;
+        self.budget -= cycles as i64;
+        self.first = false;
+    }
+
+    // the rest of the frame passes without executing anything, e.g. in the debugger
+    pub fn skip_frame(&mut self) {
+        self.cycles += self.budget.max(0) as u64;
+        self.budget = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // runs frames like Chip8::run_frame and returns the instructions executed per frame
+    fn run(program: &[u8], frames: usize) -> (Vec<usize>, Timing) {
+        let mut cpu = Cpu::new();
+        cpu.quirks.vblank = true;
+        cpu.memory[0x200..0x200 + program.len()].copy_from_slice(program);
+        let mut timing = Timing::default();
+        let mut counts = Vec::new();
+        for _ in 0..frames {
+            timing.start_frame();
+            let mut count = 0;
+            while let Some(cycles) = timing.next(&cpu) {
+                cpu.step();
+                timing.charge(cycles);
+                count += 1;
+            }
+            counts.push(count);
+        }
+        (counts, timing)
+    }
+
+    #[test]
+    fn cycles_per_frame() {
+        // add v0, 1; jp 0x200
+        let (counts, timing) = run(&[0x70, 0x01, 0x12, 0x00], 3);
+        // 33 cycles per loop, the overrun of a frame is taken from the next one
+        let per_frame = PROGRAM_CYCLES as f64 / 33.0 * 2.0;
+        assert!(counts
+            .iter()
+            .all(|&count| (count as f64 - per_frame).abs() < 2.0));
+        assert!(timing.cycles >= 3 * FRAME_CYCLES);
+    }
+
+    #[test]
+    fn draw_waits_for_vblank() {
+        // ld v0, 0; drw v0, v0, 1; drw v0, v0, 1; jp 0x206
+        let (counts, _) = run(&[0x60, 0x00, 0xD0, 0x01, 0xD0, 0x01, 0x12, 0x06], 3);
+        assert_eq!(counts[0], 1);
+        assert_eq!(counts[1], 1);
+        assert!(counts[2] > 10);
+    }
+}
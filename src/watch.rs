@@ -0,0 +1,80 @@
+// Watches the file of the ROM for --watch, so a ROM rebuilt by an external assembler restarts
+// without restarting rc8.
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+
+// how often the modification time is checked
+const INTERVAL: Duration = Duration::from_millis(250);
+
+pub struct Watch {
+    path: PathBuf,
+    modified: Option<SystemTime>,
+    checked: Instant,
+    // the machine restarts from this save state instead of a reset, see state.rs
+    pub state: Option<Vec<u8>>,
+}
+
+impl Watch {
+    pub fn new(path: &Path, state: Option<Vec<u8>>) -> Watch {
+        Watch {
+            path: path.to_path_buf(),
+            modified: modified(path),
+            checked: Instant::now(),
+            state,
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    // whether the file changed since the last call. A missing file doesn't count, editors
+    // remove it for a moment while saving.
+    pub fn changed(&mut self) -> bool {
+        if self.checked.elapsed() < INTERVAL {
+            return false;
+        }
+        self.checked = Instant::now();
+        let modified = modified(&self.path);
+        if modified.is_none() || modified == self.modified {
+            return false;
+        }
+        self.modified = modified;
+        true
+    }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::File;
+
+    #[test]
+    fn changes() {
+        let path = std::env::temp_dir().join(format!("rc8-watch-{}.ch8", std::process::id()));
+        fs::write(&path, [0x12, 0x00]).unwrap();
+        let mut watch = Watch::new(&path, None);
+        watch.checked -= INTERVAL;
+        assert!(!watch.changed());
+
+        let file = File::options().write(true).open(&path).unwrap();
+        file.set_modified(SystemTime::now() + Duration::from_secs(10))
+            .unwrap();
+        assert!(!watch.changed(), "checked too early");
+        watch.checked -= INTERVAL;
+        assert!(watch.changed());
+        watch.checked -= INTERVAL;
+        assert!(!watch.changed());
+
+        fs::remove_file(&path).unwrap();
+        watch.checked -= INTERVAL;
+        assert!(!watch.changed());
+    }
+}
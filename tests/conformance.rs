@@ -0,0 +1,163 @@
+// Runs the test ROMs in tests/roms for every quirk profile and platform, and compares the
+// framebuffer with the golden image in tests/golden. Both engines have to produce the same
+// image. Set UPDATE_GOLDEN=1 to rewrite the golden images after an intended change.
+use rc8::assembler::assemble;
+use rc8::chip8::{Chip8, COL, ROW};
+use rc8::options::{Engine, Options, Quirks};
+use std::collections::HashSet;
+use std::env;
+use std::fs;
+use std::path::Path;
+
+const FRAMES: u32 = 180;
+// the ROMs and whether their image depends on the quirks and the platform
+const ROMS: [(&str, bool); 2] = [("flags", false), ("quirks", true)];
+const PLATFORMS: [&str; 2] = ["tickrate", "vip"];
+
+// the checks of flags.asm in the order they draw their marks
+const CHECKS: [&str; 37] = [
+    "8XY4 result",
+    "8XY4 without carry",
+    "8XY4 result with carry",
+    "8XY4 carry",
+    "8XY4 wrapping to 0",
+    "8XY4 carry when wrapping to 0",
+    "8XY5 result",
+    "8XY5 without borrow",
+    "8XY5 result with borrow",
+    "8XY5 borrow",
+    "8XY5 result of equal values",
+    "8XY5 without borrow for equal values",
+    "8XY7 result",
+    "8XY7 without borrow",
+    "8XY7 result with borrow",
+    "8XY7 borrow",
+    "8XY7 result of equal values",
+    "8XY7 without borrow for equal values",
+    "8XY6 result",
+    "8XY6 shifting out a 1",
+    "8XYE result",
+    "8XYE shifting out a 1",
+    "8XY6 result shifting out a 0",
+    "8XY6 shifting out a 0",
+    "8XYE result shifting out a 0",
+    "8XYE shifting out a 0",
+    "8XY0",
+    "8XY1",
+    "8XY2",
+    "8XY3",
+    "7XNN result",
+    "7XNN leaves VF alone",
+    "7XNN wrapping around",
+    "7XNN wrapping around without carry",
+    "FX33 hundreds",
+    "FX33 tens",
+    "FX33 ones",
+];
+// the mark of a passed check, the leftmost pixel in the highest bit
+const TICK: [u8; 4] = [0b0001, 0b0010, 0b1010, 0b0100];
+
+fn framebuffer(rom: &str, profile: &str, platform: &str, engine: Engine) -> [u8; COL * ROW] {
+    let source = fs::read_to_string(format!("tests/roms/{}.asm", rom)).unwrap();
+    let mut chip = Chip8::new();
+    chip.set_options(Options {
+        tickrate: 100,
+        quirks: Quirks::profile(profile).unwrap(),
+        vip_timing: platform == "vip",
+        engine,
+        ..Options::default()
+    });
+    chip.load_rom(assemble(&source).unwrap()).unwrap();
+    chip.headless_loop(FRAMES);
+    chip.framebuffer()
+}
+
+// the framebuffer as text, '#' for a set pixel
+fn image(framebuffer: &[u8]) -> String {
+    framebuffer
+        .chunks(COL)
+        .map(|row| {
+            let mut line: String = row
+                .iter()
+                .map(|&p| if p != 0 { '#' } else { '.' })
+                .collect();
+            line.push('\n');
+            line
+        })
+        .collect()
+}
+
+// the 4x4 pixels of the mark of a check, twelve per row
+fn mark(framebuffer: &[u8], check: usize) -> [u8; 4] {
+    let (x, y) = (check % 12 * 5, check / 12 * 6);
+    let mut rows = [0; 4];
+    for (dy, row) in rows.iter_mut().enumerate() {
+        for dx in 0..4 {
+            let pixel = framebuffer[(y + dy) * COL + x + dx] != 0;
+            *row |= (pixel as u8) << (3 - dx);
+        }
+    }
+    rows
+}
+
+#[test]
+fn flag_checks() {
+    for profile in Quirks::PROFILES {
+        let framebuffer = framebuffer("flags", profile, "tickrate", Engine::Interpreter);
+        let failed: Vec<&str> = CHECKS
+            .iter()
+            .enumerate()
+            .filter(|&(check, _)| mark(&framebuffer, check) != TICK)
+            .map(|(_, name)| *name)
+            .collect();
+        assert!(
+            failed.is_empty(),
+            "{}: {} failed",
+            profile,
+            failed.join(", ")
+        );
+        assert_eq!(
+            mark(&framebuffer, CHECKS.len()),
+            [0; 4],
+            "flags.asm has more checks than CHECKS"
+        );
+    }
+}
+
+#[test]
+fn golden_framebuffers() {
+    let update = env::var_os("UPDATE_GOLDEN").is_some();
+    let mut written = HashSet::new();
+    let mut failures = Vec::new();
+    for (rom, quirky) in ROMS {
+        for profile in Quirks::PROFILES {
+            for platform in PLATFORMS {
+                let actual = image(&framebuffer(rom, profile, platform, Engine::Interpreter));
+                let path = if quirky {
+                    format!("tests/golden/{}-{}-{}.txt", rom, profile, platform)
+                } else {
+                    format!("tests/golden/{}.txt", rom)
+                };
+                // the cached engine isn't used with vip timing
+                if platform == "tickrate"
+                    && image(&framebuffer(rom, profile, platform, Engine::Cached)) != actual
+                {
+                    failures.push(format!("{} differs with the cached engine", path));
+                }
+                // an image shared by all profiles is written once and compared with the rest
+                if update && written.insert(path.clone()) {
+                    fs::write(&path, &actual).unwrap();
+                }
+                if !Path::new(&path).exists() {
+                    failures.push(format!("{} is missing", path));
+                } else if fs::read_to_string(&path).unwrap() != actual {
+                    failures.push(format!(
+                        "{} differs for {} {}, got\n{}",
+                        path, profile, platform, actual
+                    ));
+                }
+            }
+        }
+    }
+    assert!(failures.is_empty(), "{}", failures.join("\n"));
+}
@@ -0,0 +1,15 @@
+; Counts the frames key 5 is held as a BCD number at 0x300. The unit tests of env.rs and
+; script.rs drive it.
+
+        LD V0, 0
+loop:   LD V1, 5
+        SKNP V1
+        ADD V0, 1
+        LD I, 0x300
+        LD B, V0
+        LD V2, 1
+        LD DT, V2
+wait:   LD V2, DT
+        SE V2, 0
+        JP wait
+        JP loop
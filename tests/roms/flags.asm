@@ -0,0 +1,243 @@
+; Checks the results and the flags of the arithmetic instructions. Every check draws a tick
+; if the result is right and a cross if not, twelve per row. None of the checks depends on
+; the quirks, tests/conformance.rs names them in CHECKS.
+
+        LD VC, 0                ; position of the next mark
+        LD VD, 0
+
+; 8XY4 without carry
+        LD V1, 0x10
+        LD V2, 0x20
+        ADD V1, V2
+        LD VE, VF
+        LD VA, V1
+        LD VB, 0x30
+        CALL mark
+        LD VA, VE
+        LD VB, 0
+        CALL mark
+
+; 8XY4 with carry
+        LD V1, 0xF0
+        LD V2, 0x20
+        ADD V1, V2
+        LD VE, VF
+        LD VA, V1
+        LD VB, 0x10
+        CALL mark
+        LD VA, VE
+        LD VB, 1
+        CALL mark
+
+; 8XY4 wraps to 0 with carry
+        LD V1, 0x80
+        LD V2, 0x80
+        ADD V1, V2
+        LD VE, VF
+        LD VA, V1
+        LD VB, 0
+        CALL mark
+        LD VA, VE
+        LD VB, 1
+        CALL mark
+
+; 8XY5 without borrow
+        LD V1, 0x30
+        LD V2, 0x10
+        SUB V1, V2
+        LD VE, VF
+        LD VA, V1
+        LD VB, 0x20
+        CALL mark
+        LD VA, VE
+        LD VB, 1
+        CALL mark
+
+; 8XY5 with borrow
+        LD V1, 0x10
+        LD V2, 0x30
+        SUB V1, V2
+        LD VE, VF
+        LD VA, V1
+        LD VB, 0xE0
+        CALL mark
+        LD VA, VE
+        LD VB, 0
+        CALL mark
+
+; 8XY5 of equal values doesn't borrow
+        LD V1, 0x20
+        LD V2, 0x20
+        SUB V1, V2
+        LD VE, VF
+        LD VA, V1
+        LD VB, 0
+        CALL mark
+        LD VA, VE
+        LD VB, 1
+        CALL mark
+
+; 8XY7 without borrow
+        LD V1, 0x10
+        LD V2, 0x30
+        SUBN V1, V2
+        LD VE, VF
+        LD VA, V1
+        LD VB, 0x20
+        CALL mark
+        LD VA, VE
+        LD VB, 1
+        CALL mark
+
+; 8XY7 with borrow
+        LD V1, 0x30
+        LD V2, 0x10
+        SUBN V1, V2
+        LD VE, VF
+        LD VA, V1
+        LD VB, 0xE0
+        CALL mark
+        LD VA, VE
+        LD VB, 0
+        CALL mark
+
+; 8XY7 of equal values doesn't borrow
+        LD V1, 0x20
+        LD V2, 0x20
+        SUBN V1, V2
+        LD VE, VF
+        LD VA, V1
+        LD VB, 0
+        CALL mark
+        LD VA, VE
+        LD VB, 1
+        CALL mark
+
+; 8XY6 shifts out a 1
+        LD V1, 5
+        SHR V1
+        LD VE, VF
+        LD VA, V1
+        LD VB, 2
+        CALL mark
+        LD VA, VE
+        LD VB, 1
+        CALL mark
+
+; 8XYE shifts out a 1
+        LD V1, 0x81
+        SHL V1
+        LD VE, VF
+        LD VA, V1
+        LD VB, 2
+        CALL mark
+        LD VA, VE
+        LD VB, 1
+        CALL mark
+
+; 8XY6 shifts out a 0
+        LD V1, 4
+        SHR V1
+        LD VE, VF
+        LD VA, V1
+        LD VB, 2
+        CALL mark
+        LD VA, VE
+        LD VB, 0
+        CALL mark
+
+; 8XYE shifts out a 0
+        LD V1, 0x41
+        SHL V1
+        LD VE, VF
+        LD VA, V1
+        LD VB, 0x82
+        CALL mark
+        LD VA, VE
+        LD VB, 0
+        CALL mark
+
+; 8XY0
+        LD V1, 0x5A
+        LD V2, V1
+        LD VA, V2
+        LD VB, 0x5A
+        CALL mark
+
+; 8XY1, 8XY2 and 8XY3
+        LD V1, 0x0F
+        LD V2, 0xF0
+        OR V1, V2
+        LD VA, V1
+        LD VB, 0xFF
+        CALL mark
+        LD V1, 0x3C
+        LD V2, 0x0F
+        AND V1, V2
+        LD VA, V1
+        LD VB, 0x0C
+        CALL mark
+        LD V1, 0x3C
+        XOR V1, V2
+        LD VA, V1
+        LD VB, 0x33
+        CALL mark
+
+; 7XNN doesn't touch vf
+        LD VF, 5
+        LD V1, 0x10
+        ADD V1, 0x20
+        LD VE, VF
+        LD VA, V1
+        LD VB, 0x30
+        CALL mark
+        LD VA, VE
+        LD VB, 5
+        CALL mark
+
+; 7XNN wraps around without a carry
+        LD VF, 0
+        LD V1, 0xF0
+        ADD V1, 0x20
+        LD VE, VF
+        LD VA, V1
+        LD VB, 0x10
+        CALL mark
+        LD VA, VE
+        LD VB, 0
+        CALL mark
+
+; FX33 and FX65
+        LD V1, 234
+        LD I, scratch
+        LD B, V1
+        LD V2, [I]
+        LD VE, V1
+        LD VA, V0
+        LD VB, 2
+        CALL mark
+        LD VA, VE
+        LD VB, 3
+        CALL mark
+        LD VA, V2
+        LD VB, 4
+        CALL mark
+
+end:    JP end
+
+; draws a tick if va equals vb and a cross otherwise
+mark:   LD I, tick
+        SE VA, VB
+        LD I, cross
+        DRW VC, VD, 4
+        ADD VC, 5
+        SE VC, 60
+        RET
+        LD VC, 0
+        ADD VD, 6
+        RET
+
+tick:   DB 0b00010000, 0b00100000, 0b10100000, 0b01000000
+cross:  DB 0b10010000, 0b01100000, 0b01100000, 0b10010000
+scratch:
+        DB 0, 0, 0
@@ -0,0 +1,87 @@
+; Shows a digit for every quirk, 1 if the interpreter has it and 0 if not, in the order
+; shift, load_store, jump, logic, vf_order, vblank. A block is drawn at the bottom right
+; corner, which wraps around to the other corners unless sprites are clipped.
+
+        LD V4, 4                ; position of the next digit
+        LD V5, 8
+
+; 8XY6 shifts v1 instead of v2
+        LD V1, 0x10
+        LD V2, 4
+        SHR V1, V2
+        LD V3, 0
+        SNE V1, 8
+        LD V3, 1
+        CALL digit
+
+; FX55 leaves I unchanged, so FX65 reads the byte just written
+        LD V0, 7
+        LD I, scratch
+        LD [I], V0
+        LD V0, [I]
+        LD V3, 0
+        SNE V0, 7
+        LD V3, 1
+        CALL digit
+
+; BNNN jumps to table + v2 instead of table + v0, the table has to be below 0x300
+        LD V0, 0
+        LD V2, 2
+        JP V0, table
+table:  JP no_jump
+        JP jump
+no_jump:
+        LD V3, 0
+        JP jump_done
+jump:   LD V3, 1
+jump_done:
+        CALL digit
+
+; 8XY1 resets vf
+        LD VF, 1
+        LD V1, 1
+        OR V1, V1
+        LD V3, 0
+        SNE VF, 0
+        LD V3, 1
+        CALL digit
+
+; 8XY4 writes the flag before the result, which ends up in vf
+        LD VF, 3
+        LD V1, 1
+        ADD VF, V1
+        LD V3, 0
+        SNE VF, 4
+        LD V3, 1
+        CALL digit
+
+; every DXYN waits for the next frame, so three draws let the delay timer count down
+        LD V6, 10
+        LD DT, V6
+        DRW V0, V0, 0
+        DRW V0, V0, 0
+        DRW V0, V0, 0
+        LD V7, DT
+        LD V8, 9
+        SUB V7, V8
+        LD V3, 0
+        SNE VF, 0
+        LD V3, 1
+        CALL digit
+
+; DXYN clips at the screen edge
+        LD V0, 60
+        LD V1, 30
+        LD I, block
+        DRW V0, V1, 4
+
+end:    JP end
+
+digit:  LD F, V3
+        DRW V4, V5, 5
+        ADD V4, 6
+        RET
+
+block:  DB 0xFF, 0xFF, 0xFF, 0xFF
+scratch:
+        DB 0, 0
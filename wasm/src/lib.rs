@@ -0,0 +1,136 @@
+// WebAssembly module of rc8 for running ROMs in a browser, see www/ for the page.
+//
+// The exports only take and return numbers, so the module needs no JavaScript glue and runs
+// in any wasm runtime. www/rc8.js wraps them in a class. Bytes are passed through the
+// linear memory: rc8_alloc() returns a buffer to copy a ROM to, and rc8_framebuffer()
+// points to ROW * COL bytes, one per pixel row by row.
+//
+//     cargo build --release --target wasm32-unknown-unknown
+//     cp target/wasm32-unknown-unknown/release/rc8_wasm.wasm www/
+//     python3 -m http.server -d www
+#![allow(clippy::missing_safety_doc)]
+
+use rc8::chip8::{Chip8, COL, ROW};
+use rc8::options::Options;
+use std::ptr;
+use std::slice;
+
+pub struct Machine {
+    chip: Chip8,
+    // the framebuffer after the last frame, which rc8_framebuffer() points to
+    pixels: [u8; ROW * COL],
+    keys: u16,
+}
+
+impl Machine {
+    fn refresh(&mut self) {
+        self.pixels = self.chip.framebuffer();
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn rc8_width() -> u32 {
+    COL as u32
+}
+
+#[no_mangle]
+pub extern "C" fn rc8_height() -> u32 {
+    ROW as u32
+}
+
+// a buffer of len bytes, freed by rc8_free()
+#[no_mangle]
+pub extern "C" fn rc8_alloc(len: usize) -> *mut u8 {
+    Box::into_raw(vec![0u8; len].into_boxed_slice()) as *mut u8
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn rc8_free(buffer: *mut u8, len: usize) {
+    if !buffer.is_null() {
+        drop(Box::from_raw(ptr::slice_from_raw_parts_mut(buffer, len)));
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn rc8_new() -> *mut Machine {
+    Box::into_raw(Box::new(Machine {
+        chip: Chip8::new(),
+        pixels: [0; ROW * COL],
+        keys: 0,
+    }))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn rc8_drop(machine: *mut Machine) {
+    if !machine.is_null() {
+        drop(Box::from_raw(machine));
+    }
+}
+
+// loads a raw ROM or an Octo cartridge and restarts the machine, returns false if the data
+// is neither
+#[no_mangle]
+pub unsafe extern "C" fn rc8_load_rom(machine: *mut Machine, data: *const u8, len: usize) -> bool {
+    let machine = &mut *machine;
+    let mut chip = Chip8::new();
+    if chip
+        .load_data(slice::from_raw_parts(data, len).to_vec())
+        .is_err()
+    {
+        return false;
+    }
+    chip.set_options(Options {
+        tickrate: machine.chip.options().tickrate,
+        ..chip.options()
+    });
+    machine.chip = chip;
+    machine.keys = 0;
+    machine.refresh();
+    true
+}
+
+// the instructions per frame
+#[no_mangle]
+pub unsafe extern "C" fn rc8_set_speed(machine: *mut Machine, tickrate: u32) {
+    let machine = &mut *machine;
+    machine.chip.set_options(Options {
+        tickrate,
+        ..machine.chip.options()
+    });
+}
+
+// the browser has no source of randomness for Rust, so the page passes one in after
+// rc8_load_rom(), which starts a new machine
+#[no_mangle]
+pub unsafe extern "C" fn rc8_seed(machine: *mut Machine, seed: u32) {
+    (*machine).chip.seed(seed as u64);
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn rc8_set_key(machine: *mut Machine, key: u32, pressed: bool) {
+    let machine = &mut *machine;
+    if key < 16 {
+        machine.keys = machine.keys & !(1 << key) | (pressed as u16) << key;
+        machine.chip.set_keypad(machine.keys);
+    }
+}
+
+// runs frames of 1/60 second
+#[no_mangle]
+pub unsafe extern "C" fn rc8_run_frames(machine: *mut Machine, frames: u32) {
+    let machine = &mut *machine;
+    for _ in 0..frames {
+        machine.chip.run_frame();
+    }
+    machine.refresh();
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn rc8_framebuffer(machine: *const Machine) -> *const u8 {
+    (*machine).pixels.as_ptr()
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn rc8_sound_active(machine: *const Machine) -> bool {
+    (*machine).chip.sound_active()
+}
@@ -0,0 +1,95 @@
+// Builds the module for wasm32-unknown-unknown and runs a ROM in it with the wasmi
+// interpreter, the way www/rc8.js drives it in a browser.
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+use wasmi::{Engine, Instance, Linker, Memory, Module, Store};
+
+// LD V0, K; LD F, V0; DRW V1, V1, 5; JP 0x206
+const ROM: [u8; 8] = [0xF0, 0x0A, 0xF0, 0x29, 0xD1, 0x15, 0x12, 0x06];
+
+fn build() -> Vec<u8> {
+    let target_dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("wasm");
+    let status = Command::new(env!("CARGO"))
+        .args(["build", "--release", "--target", "wasm32-unknown-unknown"])
+        .arg("--target-dir")
+        .arg(&target_dir)
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .status()
+        .unwrap();
+    assert!(
+        status.success(),
+        "building the module failed, is the wasm32-unknown-unknown target installed?"
+    );
+    fs::read(target_dir.join("wasm32-unknown-unknown/release/rc8_wasm.wasm")).unwrap()
+}
+
+struct Module8 {
+    store: Store<()>,
+    instance: Instance,
+    memory: Memory,
+}
+
+impl Module8 {
+    fn new(wasm: &[u8]) -> Module8 {
+        let engine = Engine::default();
+        let module = Module::new(&engine, wasm).unwrap();
+        let mut store = Store::new(&engine, ());
+        // the module must not need any imports
+        let instance = Linker::<()>::new(&engine)
+            .instantiate(&mut store, &module)
+            .unwrap()
+            .start(&mut store)
+            .unwrap();
+        let memory = instance.get_memory(&store, "memory").unwrap();
+        Module8 {
+            store,
+            instance,
+            memory,
+        }
+    }
+
+    fn call<P: wasmi::WasmParams, R: wasmi::WasmResults>(&mut self, name: &str, params: P) -> R {
+        self.instance
+            .get_typed_func::<P, R>(&self.store, name)
+            .unwrap()
+            .call(&mut self.store, params)
+            .unwrap()
+    }
+
+    fn lit(&mut self, machine: i32) -> usize {
+        let pixels: i32 = self.call("rc8_framebuffer", machine);
+        let mut frame = vec![0; 64 * 32];
+        self.memory
+            .read(&self.store, pixels as usize, &mut frame)
+            .unwrap();
+        frame.iter().filter(|&&p| p != 0).count()
+    }
+}
+
+#[test]
+fn run_a_rom() {
+    let mut wasm = Module8::new(&build());
+    assert_eq!(wasm.call::<(), i32>("rc8_width", ()), 64);
+    assert_eq!(wasm.call::<(), i32>("rc8_height", ()), 32);
+
+    let machine: i32 = wasm.call("rc8_new", ());
+    let buffer: i32 = wasm.call("rc8_alloc", ROM.len() as i32);
+    wasm.memory
+        .write(&mut wasm.store, buffer as usize, &ROM)
+        .unwrap();
+    assert_eq!(
+        wasm.call::<_, i32>("rc8_load_rom", (machine, buffer, ROM.len() as i32)),
+        1
+    );
+    wasm.call::<_, ()>("rc8_free", (buffer, ROM.len() as i32));
+
+    wasm.call::<_, ()>("rc8_run_frames", (machine, 5));
+    assert_eq!(wasm.lit(machine), 0);
+    // the ROM draws the digit of the pressed key, 8 has 16 pixels
+    wasm.call::<_, ()>("rc8_set_key", (machine, 8, 1));
+    wasm.call::<_, ()>("rc8_run_frames", (machine, 1));
+    assert_eq!(wasm.lit(machine), 16);
+    wasm.call::<_, ()>("rc8_drop", machine);
+}
@@ -0,0 +1,60 @@
+// The exports of rc8_wasm.wasm wrapped in a class, e.g.
+//
+//     const chip = await Rc8.load("rc8_wasm.wasm");
+//     chip.loadRom(new Uint8Array(await file.arrayBuffer()));
+//     chip.runFrames(1);
+//     chip.framebuffer(); // width * height bytes, 0 for an unlit pixel
+export class Rc8 {
+  // module is a URL or the bytes of the module
+  static async load(module) {
+    const bytes =
+      typeof module === "string" ? await (await fetch(module)).arrayBuffer() : module;
+    const { instance } = await WebAssembly.instantiate(bytes);
+    return new Rc8(instance.exports);
+  }
+
+  constructor(exports) {
+    this.exports = exports;
+    this.machine = exports.rc8_new();
+    this.width = exports.rc8_width();
+    this.height = exports.rc8_height();
+  }
+
+  // a raw ROM or an Octo cartridge, throws if it is neither
+  loadRom(bytes) {
+    const buffer = this.exports.rc8_alloc(bytes.length);
+    new Uint8Array(this.exports.memory.buffer, buffer, bytes.length).set(bytes);
+    const loaded = this.exports.rc8_load_rom(this.machine, buffer, bytes.length);
+    this.exports.rc8_free(buffer, bytes.length);
+    if (!loaded) {
+      throw new Error("Not a CHIP-8 ROM");
+    }
+    this.exports.rc8_seed(this.machine, (Math.random() * 2 ** 32) >>> 0);
+  }
+
+  setSpeed(instructionsPerFrame) {
+    this.exports.rc8_set_speed(this.machine, instructionsPerFrame);
+  }
+
+  setKey(key, pressed) {
+    this.exports.rc8_set_key(this.machine, key, pressed);
+  }
+
+  runFrames(frames) {
+    this.exports.rc8_run_frames(this.machine, frames);
+  }
+
+  // a view into the memory of the module, valid until the next call
+  framebuffer() {
+    const pixels = this.exports.rc8_framebuffer(this.machine);
+    return new Uint8Array(this.exports.memory.buffer, pixels, this.width * this.height);
+  }
+
+  soundActive() {
+    return this.exports.rc8_sound_active(this.machine) !== 0;
+  }
+
+  free() {
+    this.exports.rc8_drop(this.machine);
+  }
+}